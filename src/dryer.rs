@@ -1,247 +1,1182 @@
+pub mod autotune;
+pub mod backlight;
 mod button_cluster;
+pub mod char_rom;
+pub mod checkpoint;
+pub mod config;
 mod display;
 mod dry_table;
+pub mod fusion;
+mod glyph;
+pub mod hal;
 mod lcd_interface;
+pub mod pid;
+pub mod plateau;
+mod profile_editor;
+mod profile_run;
+pub mod retry;
+pub mod safety;
+mod schedule;
 mod shared_data;
-mod temp_sensor;
+pub mod storage;
+pub mod temp_sensor;
 
 use std::{
     error::Error,
+    fs,
+    path::Path,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
-};
-
-use rppal::{
-    gpio::{Gpio, OutputPin},
-    i2c::I2c,
+    time::{Duration, Instant, SystemTime},
 };
 
+use autotune::{AutotuneStatus, Autotuner};
+use backlight::Backlight;
 use button_cluster::ButtonCluster;
+use checkpoint::{Checkpoint, DowntimePolicy, ResumeMode};
+use config::Config;
+use fusion::Fusion;
+use glyph::{DEGREE, DROP};
+use hal::{Button, I2cBus, Reading, Relay, SensorError, TempHumSensor};
+use pid::{Pid, PidGains, TimeProportioner};
+use profile_run::{Output, ProfileRun};
+use safety::{Ceiling, Fault, SafetySupervisor};
+use schedule::Scheduled;
 use shared_data::SharedData;
-use temp_sensor::{SHTAddr, TempSensor};
+use storage::Storage;
+use temp_sensor::Role;
 
-use display::{Display, DisplayState};
+use display::{Display, DisplayState, RunAction, RunPhase};
 
-use crate::dryer::dry_table::Material;
+use crate::dryer::dry_table::{DryTable, Material, ProfileKind};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum HeaterState {
     Idle,
+    // Set to start later, everything off until then
+    Waiting,
     Running,
+    // Run on hold, heater off with the fan on and its clock stopped
+    Paused,
+    // Keeping the spools dry after a profile finished
+    Storage,
+    // Relay autotune of the PID gains
+    Autotune,
+    // Heater locked out by the safety supervisor
+    Fault(Fault),
+}
+
+// Everything the dryer needs from the outside world
+// Built by a backend in hal and handed to Dryer::new
+pub struct Hardware<I, R, B, S> {
+    pub i2c: I,
+    pub fan: R,
+    pub heater: R,
+    pub back: B,
+    pub confirm: B,
+    pub right: B,
+    pub left: B,
+    pub sensors: Vec<Sensor<S>>,
+}
+
+// A sensor driver and what the config says about it
+#[derive(Debug)]
+pub struct Sensor<S> {
+    pub name: String,
+    pub role: Role,
+    pub driver: S,
 }
 
 #[derive(Debug)]
-pub struct Dryer {
+pub struct Dryer<I: I2cBus, R: Relay, B: Button, S: TempHumSensor<I>> {
     display: Display,
-    i2c: I2c,
-    near_sensor: TempSensor,
-    far_sensor: TempSensor,
-    buttons: ButtonCluster,
-    fan: OutputPin,
-    heater: OutputPin,
+    backlight: Backlight,
+    i2c: I,
+    sensors: Vec<Sensor<S>>,
+    // Latest read of every sensor, in the same order
+    readings: Vec<Result<Reading, SensorError>>,
+    _buttons: ButtonCluster<B>,
+    fan: R,
+    heater: R,
+    pid: Pid,
+    heater_pwm: TimeProportioner,
+    autotuner: Option<Autotuner>,
+    // The profile being run, picked up from shared_data.material when it starts
+    run: Option<ProfileRun>,
+    // Set when a profile finishes into storage
+    storage: Option<Storage>,
+    // Run from before a restart, picked up on the first update
+    restore: Option<Checkpoint>,
+    // Likewise for a run that was waiting to start
+    restore_schedule: Option<Scheduled>,
+    // Start time of the run waiting
+    waiting: Option<Scheduled>,
+    // When the run was last written out and whether it was paused then
+    last_checkpoint: Option<(Instant, bool)>,
+    // The same moment on both clocks, update only gets an Instant but checkpoints and
+    // schedules need the wall clock
+    // None until the first sync_clock
+    clock: Option<(Instant, SystemTime)>,
+    safety: SafetySupervisor,
+    ceiling: Ceiling,
+    fusion: Fusion,
+    config: Config,
+    // Shown on the idle screen until the next run, e.g. how autotune went
+    notice: Option<String>,
     data: Arc<Mutex<SharedData>>,
     last_temp: f32,
     last_hum: f32,
     last_reading: Instant,
     display_update: Instant,
+    // Seconds the display has been going, the second line steps through the sensors with it
+    display_ticks: usize,
+    debug: bool,
 }
 
-impl Dryer {
-    pub fn new() -> Self {
-        // Data shared by callback functions
-        let data = Arc::new(Mutex::new(SharedData::new()));
-
-        // Creates the Input pins and sets callbacks for them
-        let buttons = ButtonCluster::new(&data);
-
-        // Create the output pins
-        let gpio = Gpio::new().unwrap();
+impl<I: I2cBus, R: Relay, B: Button, S: TempHumSensor<I>> Dryer<I, R, B, S> {
+    pub fn new(hardware: Hardware<I, R, B, S>, config: Config) -> Result<Self, Box<dyn Error>> {
+        let Hardware {
+            mut i2c,
+            fan,
+            heater,
+            back,
+            confirm,
+            right,
+            left,
+            mut sensors,
+        } = hardware;
+        config.check()?;
 
-        // Relay is open on low
-        let fan_pin = gpio.get(14).unwrap().into_output_high(); // Physical Pin 8
-        let heater_pin = gpio.get(15).unwrap().into_output_high(); // Phsyical Pin 10
+        // A bad profiles file stops startup rather than leaving the menu empty
+        let mut dry_table = match &config.profiles {
+            Some(path) => DryTable::load(path, config.max_chamber_temp)?,
+            None => DryTable::built_in(config.max_chamber_temp)?,
+        };
+        let custom_path = config.custom_profiles_path();
+        if custom_path.exists()
+            && let Err(e) = dry_table.load_custom(&custom_path)
+        {
+            println!("Couldn't load {}: {e}", custom_path.display());
+        }
 
-        // Create the sensors
-        let near_sensor = TempSensor::new(SHTAddr::Default);
-        let far_sensor = TempSensor::new(SHTAddr::Alternate);
+        // Data shared by callback functions
+        let data = Arc::new(Mutex::new(SharedData::new(dry_table)));
 
-        // One I2c instance is passed around because
-        // I've had issues with each I2c device holding their own instance
-        let mut i2c = I2c::new().unwrap();
+        // Sets callbacks for the input pins
+        let buttons = ButtonCluster::new(back, confirm, right, left, &data)?;
 
         // Initialize the display
-        let _ = lcd_interface::init(&mut i2c);
+        let mut display = Display::new(&config.lcd);
+        let _ = display.init(&mut i2c);
+
+        // Sensors get reset and checked over before the first reading
+        // A failure here gets caught by the supervisor on the next reading
+        for sensor in &mut sensors {
+            if let Err(e) = sensor.driver.init(&mut i2c) {
+                println!("{} sensor init failed: {e}", sensor.name);
+            }
+        }
 
         // First reading of the temperature and humidity sensors
-        let near_reading = near_sensor.read(&mut i2c);
-        let far_reading = far_sensor.read(&mut i2c);
-        // Just averaging the values of the two sensors for now
-        let last_temp = (near_reading.0 + far_reading.0) / 2.0;
-        let last_hum = (near_reading.1 + far_reading.1) / 2.0;
+        // A failure here gets caught by the supervisor on the next reading
+        let control = sensors
+            .iter()
+            .filter(|sensor| sensor.role == Role::Control)
+            .map(|sensor| sensor.name.clone())
+            .collect();
+        let mut fusion = Fusion::new(config.fusion.clone(), control);
+        let readings = read_sensors(&mut sensors, &mut i2c, &config);
+        let (last_temp, last_hum) = match fusion.fuse(&control_readings(&readings)) {
+            Some(reading) => (reading.temp, reading.hum),
+            None => (0.0, 0.0),
+        };
+        for (sensor, (role, reading)) in sensors.iter().zip(&readings) {
+            if let (false, Err(e)) = (*role == Role::Control, reading) {
+                println!("{} sensor failed: {e}", sensor.name);
+            }
+        }
+        let readings = readings.into_iter().map(|(_, reading)| reading).collect();
         let last_reading = Instant::now();
 
         // Initialize with a time
         let display_update = Instant::now();
 
-        Self {
-            display: Display::new(),
+        // A run cut short by a power cut or reboot
+        let checkpoint_path = config.checkpoint_path();
+        let restore = if checkpoint_path.exists() {
+            match Checkpoint::load(&checkpoint_path) {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(e) => {
+                    println!("Couldn't load {}: {e}", checkpoint_path.display());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let schedule_path = config.schedule_path();
+        let restore_schedule = if schedule_path.exists() {
+            match Scheduled::load(&schedule_path) {
+                Ok(scheduled) => Some(scheduled),
+                Err(e) => {
+                    println!("Couldn't load {}: {e}", schedule_path.display());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Gains from the last autotune take over from the config
+        let mut pid = Pid::new(config.pid);
+        let gains_path = config.gains_path();
+        if gains_path.exists() {
+            match PidGains::load(&gains_path) {
+                Ok(gains) => pid.set_gains(gains),
+                Err(e) => println!("Couldn't load {}: {e}", gains_path.display()),
+            }
+        }
+
+        Ok(Self {
+            display,
+            backlight: Backlight::new(config.backlight, Instant::now()),
             i2c,
-            near_sensor,
-            far_sensor,
-            buttons,
-            fan: fan_pin,
-            heater: heater_pin,
+            sensors,
+            readings,
+            _buttons: buttons,
+            fan,
+            heater,
+            pid,
+            heater_pwm: TimeProportioner::new(config.pid.window(), config.pid.min_pulse()),
+            autotuner: None,
+            run: None,
+            storage: None,
+            restore,
+            restore_schedule,
+            waiting: None,
+            last_checkpoint: None,
+            clock: None,
+            safety: SafetySupervisor::new(config.safety),
+            ceiling: Ceiling::default(),
+            fusion,
+            config,
+            notice: None,
             data,
             last_temp,
             last_hum,
             last_reading,
             display_update,
+            display_ticks: 0,
+            debug: true,
+        })
+    }
+
+    // Turns the state dump printed on every update on or off
+    #[cfg(feature = "sim")]
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    // Name and latest reading of every sensor, None if its last read failed
+    #[cfg(feature = "sim")]
+    pub fn sensor_readings(&self) -> impl Iterator<Item = (&str, Option<Reading>)> {
+        self.sensors
+            .iter()
+            .zip(&self.readings)
+            .map(|(sensor, reading)| (sensor.name.as_str(), reading.clone().ok()))
+    }
+
+    // Heater controller gains and limits
+    #[cfg(feature = "sim")]
+    pub fn pid_config(&self) -> pid::PidConfig {
+        self.pid.config()
+    }
+
+    // Overrides the gains for this session without saving them
+    #[cfg(feature = "sim")]
+    pub fn set_pid_gains(&mut self, gains: PidGains) {
+        self.pid.set_gains(gains);
+    }
+
+    // Lines the wall clock up with the system one, called before every update
+    // A clock that jumps, e.g. NTP syncing after boot, is logged and followed
+    pub fn sync_clock(&mut self, now: Instant, wall: SystemTime) {
+        if self.clock.is_some() {
+            let expected = self.wall_time(now);
+            let moved = match wall.duration_since(expected) {
+                Ok(ahead) => ahead.as_secs() as i64,
+                Err(behind) => -(behind.duration().as_secs() as i64),
+            };
+            if moved.abs() > 5 {
+                println!("Clock moved {moved:+}s");
+                if let Some(scheduled) = &self.waiting {
+                    println!(
+                        "{} starts in {}",
+                        scheduled.material,
+                        hms(scheduled.left(wall))
+                    );
+                }
+            }
+        }
+        self.clock = Some((now, wall));
+    }
+
+    // Heater off and latched until someone resets it from the fault screen
+    // The first fault is the one that gets shown
+    fn trip(&mut self, shared_data: &mut SharedData, fault: Fault) {
+        if let HeaterState::Fault(_) = shared_data.heater_state {
+            return;
+        }
+        println!("Safety fault: {fault:?}");
+        if self.heater.is_on() {
+            self.heater.off();
+        }
+        self.autotuner = None;
+        self.clear_run();
+        self.storage = None;
+        self.safety.reset();
+        shared_data.heater_state = HeaterState::Fault(fault);
+        shared_data.material = Material::None;
+        shared_data.display_state = DisplayState::Idle;
+        shared_data.editor = None;
+    }
+
+    fn wall_time(&self, now: Instant) -> SystemTime {
+        match self.clock {
+            Some((instant, system)) => system + (now - instant),
+            None => SystemTime::now(),
+        }
+    }
+
+    // Drops the run and its checkpoint, it is over one way or another
+    fn clear_run(&mut self) {
+        if self.run.take().is_none() {
+            return;
+        }
+        self.last_checkpoint = None;
+        remove_state(&self.config.checkpoint_path());
+    }
+
+    fn clear_schedule(&mut self) {
+        if self.waiting.take().is_some() {
+            remove_state(&self.config.schedule_path());
+        }
+    }
+
+    // Picks up the run from before a restart, or drops it, as the resume config says
+    fn resume_run(&mut self, shared_data: &mut SharedData, checkpoint: Checkpoint, now: Instant) {
+        let config = self.config.resume;
+        let wall = self.wall_time(now);
+        let minutes = checkpoint.downtime(wall).as_secs() / 60;
+        let resumed = checkpoint.material().and_then(|material| {
+            if config.mode == ResumeMode::Off {
+                return Err(String::from("resume is off"));
+            }
+            let state = checkpoint
+                .resume(&config, wall)
+                .ok_or_else(|| format!("the power was off for {minutes} minutes"))?;
+            let index = shared_data
+                .dry_table
+                .find(&material.name)
+                .ok_or_else(|| format!("{} is no longer on the menu", material.name))?;
+            Ok((material, state, index))
+        });
+        let (material, state, index) = match resumed {
+            Ok(resumed) => resumed,
+            Err(e) => {
+                println!("Not resuming the last run: {e}");
+                self.notice = Some(String::from("Run not resumed"));
+                remove_state(&self.config.checkpoint_path());
+                return;
+            }
+        };
+
+        println!("Resuming {} after {minutes} minutes off", material.name);
+        self.run = Some(match config.downtime {
+            DowntimePolicy::Restart => {
+                ProfileRun::new(&material, self.config.plateau, self.last_temp, now)
+            }
+            _ => ProfileRun::restore(material, self.config.plateau, state, now),
+        });
+        shared_data.material = index;
+        shared_data.heater_started = Some(now);
+        shared_data.heater_state = HeaterState::Running;
+        // Prompted runs come back paused with the question up, back leads to cancel
+        if state.paused || config.mode == ResumeMode::Prompt {
+            shared_data.heater_state = HeaterState::Paused;
+        }
+        if config.mode == ResumeMode::Prompt {
+            shared_data.display_state = DisplayState::Confirm(RunAction::Resume);
+        }
+    }
+
+    // Waits again for a run that was set to start later, a start missed while the power was off
+    // happens straight away
+    fn resume_schedule(
+        &mut self,
+        shared_data: &mut SharedData,
+        scheduled: Scheduled,
+        now: Instant,
+    ) {
+        let index = shared_data.dry_table.find(&scheduled.material);
+        match index {
+            // A run picked up from a checkpoint wins
+            Some(_) if shared_data.heater_state != HeaterState::Idle => {}
+            Some(index) => {
+                let wall = self.wall_time(now);
+                match scheduled.start().duration_since(wall) {
+                    Ok(left) => println!("{} starts in {}", scheduled.material, hms(left)),
+                    Err(late) => println!(
+                        "{} missed its start by {} minutes, starting now",
+                        scheduled.material,
+                        late.duration().as_secs() / 60
+                    ),
+                }
+                shared_data.material = index;
+                shared_data.heater_state = HeaterState::Waiting;
+                shared_data.heater_started = Some(now);
+                self.waiting = Some(scheduled);
+                return;
+            }
+            None => {
+                println!(
+                    "Not starting {}, it is no longer on the menu",
+                    scheduled.material
+                );
+                self.notice = Some(String::from("Start dropped"));
+            }
+        }
+        remove_state(&self.config.schedule_path());
+    }
+
+    // PID sets the duty cycle, the heater is switched once per PWM window
+    fn heat(&mut self, setpoint: f32, now: Instant) {
+        let duty = self.pid.update(setpoint, self.last_temp, now);
+        if self.heater_pwm.output(duty, now) && self.ceiling.over().is_none() {
+            if !self.heater.is_on() {
+                self.heater.on();
+            }
+        } else if self.heater.is_on() {
+            self.heater.off();
+        }
+    }
+
+    // Setpoint of whatever is asking for heat right now
+    fn target(&self, state: HeaterState, now: Instant) -> Option<f32> {
+        match state {
+            HeaterState::Running => match self.run.as_ref().and_then(|run| run.output(now)) {
+                Some(Output::Heat(setpoint)) => Some(setpoint),
+                _ => None,
+            },
+            HeaterState::Storage => self
+                .storage
+                .as_ref()
+                .filter(|storage| storage.heating())
+                .map(Storage::temp),
+            HeaterState::Autotune => Some(self.config.autotune.setpoint),
+            _ => None,
         }
     }
 
-    pub fn update(&mut self) -> Result<(), Box<dyn Error>> {
-        let now = Instant::now();
-        let mut shared_data = self.data.lock().unwrap();
+    // Fan and heater state, (fan, heater)
+    #[cfg(feature = "sim")]
+    pub fn outputs(&self) -> (bool, bool) {
+        (self.fan.is_on(), self.heater.is_on())
+    }
+
+    // now is passed in so the simulator can run faster than real time
+    pub fn update(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let data = self.data.clone();
+        let mut shared_data = data.lock().unwrap();
+        let wall = self.wall_time(now);
+        shared_data.wall_clock = wall;
+
+        if let Some(checkpoint) = self.restore.take() {
+            self.resume_run(&mut shared_data, checkpoint, now);
+        }
+        if let Some(scheduled) = self.restore_schedule.take() {
+            self.resume_schedule(&mut shared_data, scheduled, now);
+        }
+
+        // Profiles finished in the editor are saved here, the button threads have no config
+        if let Some(profile) = shared_data.new_profile.take() {
+            let name = profile.name.clone();
+            let path = self.config.custom_profiles_path();
+            let table = &mut shared_data.dry_table;
+            let saved = table.add_custom(profile).and_then(|i| {
+                table
+                    .save_custom(&path)
+                    .map(|()| i)
+                    .map_err(|e| e.to_string())
+            });
+            self.notice = Some(match saved {
+                Ok(i) => {
+                    println!("Saved profile {name}");
+                    shared_data.hovered_material = Material::Profile(i);
+                    format!("Saved {name:.10}")
+                }
+                Err(e) => {
+                    println!("Couldn't save profile {name}: {e}");
+                    e
+                }
+            });
+        }
+
+        // When Idle, only update temperature every 30 seconds
+        let reading_due = match shared_data.heater_state {
+            HeaterState::Idle | HeaterState::Waiting | HeaterState::Fault(_) => {
+                now - self.last_reading > Duration::from_secs(30)
+            }
+            // Humidity creeps up slowly, storage only needs the fast rate while it heats
+            HeaterState::Storage => {
+                self.storage.as_ref().is_some_and(Storage::heating)
+                    || now - self.last_reading > Duration::from_secs(30)
+            }
+            HeaterState::Running | HeaterState::Paused | HeaterState::Autotune => true,
+        };
+        if reading_due {
+            let readings = read_sensors(&mut self.sensors, &mut self.i2c, &self.config);
+            self.last_reading = now;
+
+            // The supervisor sees every reading, the controller only gets what fusion lets
+            // through from the control sensors
+            if let Some(reading) = self.fusion.fuse(&control_readings(&readings)) {
+                self.last_temp = reading.temp;
+                self.last_hum = reading.hum;
+            }
+            if let Err(fault) = self.safety.check_readings(&readings) {
+                self.trip(&mut shared_data, fault);
+            }
+
+            // Fusion logs the control sensors coming and going, this covers the rest
+            for (i, (role, reading)) in readings.into_iter().enumerate() {
+                let name = &self.sensors[i].name;
+                match (&self.readings[i], &reading) {
+                    _ if role == Role::Control => {}
+                    (Ok(_), Err(e)) => println!("{name} sensor failed: {e}"),
+                    (Err(_), Ok(_)) => println!("{name} sensor back"),
+                    _ => {}
+                }
+                self.readings[i] = reading;
+            }
+        }
+
+        // Checked whatever is running and before it, so nothing can turn the heater on over a limit
+        // The material's limit counts from picking it until the run is over
+        let hottest = self
+            .sensors
+            .iter()
+            .zip(&self.readings)
+            .filter(|(sensor, _)| sensor.role.in_chamber())
+            .filter_map(|(_, reading)| reading.as_ref().ok())
+            .fold(self.last_temp, |hottest, reading| hottest.max(reading.temp));
+        let material_limit = shared_data
+            .dry_table
+            .get(shared_data.material)
+            .max_temp
+            .map_or(f32::MAX, |max| max as f32);
+        let limit = self.config.max_chamber_temp.min(material_limit);
+        if self.ceiling.check(hottest, limit) && self.heater.is_on() {
+            self.heater.off();
+        }
+
+        // Whatever is asking for heat has to be getting it, as far as the ceiling lets it
+        let target = self
+            .ceiling
+            .cap(self.target(shared_data.heater_state, now), limit);
+        if let Err(fault) = self.safety.check_heating(self.last_temp, target, now) {
+            self.trip(&mut shared_data, fault);
+        }
+
+        // Started, cancelled or replaced, it isn't waiting any more
+        if shared_data.heater_state != HeaterState::Waiting {
+            self.clear_schedule();
+        }
+
+        // Finished, cancelled or replaced, nothing is tuning any more
+        if shared_data.heater_state != HeaterState::Autotune {
+            self.autotuner = None;
+        }
+
         match shared_data.heater_state {
             HeaterState::Idle => {
-                if self.heater.is_set_low() {
-                    self.heater.set_high();
+                // Cancelled runs end up here
+                self.clear_run();
+                self.storage = None;
+                if self.heater.is_on() {
+                    self.heater.off();
                 }
-                if self.fan.is_set_low() {
-                    self.fan.set_high();
+                if self.fan.is_on() {
+                    self.fan.off();
+                }
+            }
+            HeaterState::Waiting => {
+                // A run it is replacing is over
+                self.clear_run();
+                self.storage = None;
+                if self.heater.is_on() {
+                    self.heater.off();
+                }
+                if self.fan.is_on() {
+                    self.fan.off();
                 }
-                // When Idle, only update temperature every 30 seconds
-                if now - self.last_reading > Duration::from_secs(30) {
-                    let near_reading = self.near_sensor.read(&mut self.i2c);
-                    let far_reading = self.far_sensor.read(&mut self.i2c);
 
-                    // Average two sensors together
-                    self.last_temp = (near_reading.0 + far_reading.0) / 2.0;
-                    self.last_hum = (near_reading.1 + far_reading.1) / 2.0;
+                // Buttons don't know the time, the start is worked out on the first update after
+                // confirm, with ramps timed from the chamber as it is
+                if shared_data.heater_started.is_none() {
+                    shared_data.heater_started = Some(now);
+                    self.notice = None;
+                    let picker = shared_data.new_schedule.take();
+                    let material = shared_data.dry_table.get(shared_data.material);
+                    let start = match picker {
+                        Some(picker) => picker.start(material.time(self.last_temp), wall),
+                        None => Err(String::from("Nothing to start")),
+                    };
+                    match start {
+                        Ok(start) => {
+                            println!("{} starts at {}", material.name, schedule::clock(start));
+                            let scheduled = Scheduled::new(&material.name, start);
+                            let path = self.config.schedule_path();
+                            if let Err(e) = scheduled.save(&path) {
+                                println!("Couldn't save {}: {e}", path.display());
+                            }
+                            self.waiting = Some(scheduled);
+                        }
+                        Err(e) => {
+                            println!("Couldn't schedule {}: {e}", material.name);
+                            self.notice = Some(e);
+                            shared_data.heater_state = HeaterState::Idle;
+                            shared_data.material = Material::None;
+                        }
+                    }
+                }
 
-                    self.last_reading = Instant::now();
+                // Going by the wall clock, so the start follows the clock if it jumps
+                if let Some(scheduled) = &self.waiting
+                    && scheduled.left(wall).is_zero()
+                {
+                    println!("Starting {} as scheduled", scheduled.material);
+                    shared_data.heater_state = HeaterState::Running;
+                    shared_data.heater_started = None;
+                    if let DisplayState::Run(_) | DisplayState::Confirm(_) =
+                        shared_data.display_state
+                    {
+                        shared_data.display_state = DisplayState::Idle;
+                    }
                 }
             }
             HeaterState::Running => {
                 if shared_data.material == Material::None {
-                    if self.fan.is_set_low() {
-                        self.fan.set_high();
+                    self.clear_run();
+                    self.storage = None;
+                    if self.fan.is_on() {
+                        self.fan.off();
                     }
-                    if self.heater.is_set_low() {
-                        self.heater.set_high();
+                    if self.heater.is_on() {
+                        self.heater.off();
                     }
                 } else {
-                    let near_reading = self.near_sensor.read(&mut self.i2c);
-                    let far_reading = self.far_sensor.read(&mut self.i2c);
-
-                    // Just averaging the values of the two sensors for now
-                    self.last_temp = (near_reading.0 + far_reading.0) / 2.0;
-                    self.last_hum = (near_reading.1 + far_reading.1) / 2.0;
-
-                    self.last_reading = Instant::now();
+                    // Buttons don't know the time, the run starts on the first update after confirm
+                    if shared_data.heater_started.is_none() {
+                        self.pid.reset();
+                        self.heater_pwm.reset();
+                        self.notice = None;
+                        self.storage = None;
+                        let material = shared_data.dry_table.get(shared_data.material);
+                        self.run = Some(ProfileRun::new(
+                            material,
+                            self.config.plateau,
+                            self.last_temp,
+                            now,
+                        ));
+                        shared_data.heater_started = Some(now);
+                    }
 
-                    // Poll fan, ensure running
-                    if self.fan.is_set_high() {
-                        self.fan.set_low();
+                    if let Some(run) = self.run.as_mut()
+                        && run.is_paused()
+                    {
+                        run.resume(now);
                     }
 
-                    // Temperature target has a 3 degree window
-                    // Heater is on for +1.5 degrees
-                    // Heater is off for -1.5 degrees
-                    let target_temp = shared_data.material.get().temp as f32;
-                    if self.last_temp < target_temp - 1.5 {
-                        if self.heater.is_set_high() {
-                            self.heater.set_low();
+                    let output = self
+                        .run
+                        .as_mut()
+                        .and_then(|run| run.update(self.last_temp, self.last_hum, now));
+                    match output {
+                        Some(Output::Heat(setpoint)) => self.heat(setpoint, now),
+                        // Heating again after a cool down starts the PID from scratch
+                        Some(Output::Cool) => {
+                            self.pid.reset();
+                            self.heater_pwm.reset();
+                            if self.heater.is_on() {
+                                self.heater.off();
+                            }
                         }
-                    } else if self.last_temp > target_temp + 1.5 {
-                        if self.heater.is_set_low() {
-                            self.heater.set_high();
+                        // Shutdown after the last stage, or keep the spools dry from here on
+                        None => {
+                            self.clear_run();
+                            let material = shared_data.dry_table.get(shared_data.material);
+                            if self.config.storage.enabled && material.kind == ProfileKind::Dry {
+                                let storage = Storage::new(self.config.storage, material.store_rh);
+                                println!("Keep dry: storing under {:.0}%rh", storage.rh());
+                                self.storage = Some(storage);
+                                shared_data.heater_state = HeaterState::Storage;
+                            }
+                            shared_data.material = Material::None;
+                            shared_data.hovered_material = Material::None;
+                            if let DisplayState::Run(_) | DisplayState::Confirm(_) =
+                                shared_data.display_state
+                            {
+                                shared_data.display_state = DisplayState::Idle;
+                            }
                         }
                     }
 
-                    // Shutdown at end of time
-                    if (now - shared_data.heater_started) > shared_data.material.get().time {
-                        shared_data.material = Material::None;
-                        shared_data.hovered_material = Material::None;
+                    // Drying always runs the fan, annealing only while the heater is on
+                    if let Some(run) = &self.run {
+                        let fan = run.material().kind == ProfileKind::Dry || self.heater.is_on();
+                        if fan && !self.fan.is_on() {
+                            self.fan.on();
+                        } else if !fan && self.fan.is_on() {
+                            self.fan.off();
+                        }
                     }
                 }
             }
-        }
+            HeaterState::Paused => match self.run.as_mut() {
+                Some(run) => {
+                    run.pause(now);
+                    self.pid.reset();
+                    self.heater_pwm.reset();
+                    if self.heater.is_on() {
+                        self.heater.off();
+                    }
+                    // Annealed parts sit in still air, a draught while paused could warp them
+                    let fan = run.material().kind == ProfileKind::Dry;
+                    if fan && !self.fan.is_on() {
+                        self.fan.on();
+                    } else if !fan && self.fan.is_on() {
+                        self.fan.off();
+                    }
+                }
+                // Nothing to hold, e.g. the run ended just as pause was confirmed
+                None => shared_data.heater_state = HeaterState::Idle,
+            },
+            HeaterState::Storage => {
+                let heating = match self.storage.as_mut() {
+                    Some(storage) => storage.update(self.last_hum, now),
+                    None => false,
+                };
+                if heating {
+                    if !self.fan.is_on() {
+                        self.fan.on();
+                    }
+                    self.heat(self.config.storage.temp, now);
+                } else {
+                    // Each burst starts the PID from scratch
+                    self.pid.reset();
+                    self.heater_pwm.reset();
+                    if self.heater.is_on() {
+                        self.heater.off();
+                    }
+                    if self.fan.is_on() {
+                        self.fan.off();
+                    }
+                }
+            }
+            HeaterState::Autotune => {
+                if shared_data.heater_started.is_none() {
+                    shared_data.heater_started = Some(now);
+                    self.autotuner = Some(Autotuner::new(self.config.autotune, now));
+                    self.notice = None;
+                }
 
-        // Update display
-        // Only update the display at 1Hz
-        // Change to a diffing function later to update display whenever the internal state changes
-        // Display holds stored value forever
-        if now - self.display_update > Duration::from_secs(1) {
-            // Write out material and Arrows
-            // write out current reading
-            // write out menu
-            match shared_data.display_state {
-                DisplayState::Idle => {
-                    let line1: String;
+                if !self.fan.is_on() {
+                    self.fan.on();
+                }
 
-                    if shared_data.material == Material::None {
-                        line1 = format!("Idle");
-                    } else {
-                        let remaining =
-                            shared_data.material.get().time - (now - shared_data.heater_started);
-
-                        line1 = format!(
-                            "{}: {}:{:.2}:{:.2}",
-                            shared_data.material.get().name,
-                            remaining.as_secs() / 3600,
-                            (remaining.as_secs() % 3600) / 60,
-                            remaining.as_secs() % 60
+                let status = match self.autotuner.as_mut() {
+                    Some(autotuner) => autotuner.update(self.last_temp, now),
+                    None => AutotuneStatus::Failed("Tune not started"),
+                };
+                match status {
+                    AutotuneStatus::Running { heater } => {
+                        let heater = heater && self.ceiling.over().is_none();
+                        if heater && !self.heater.is_on() {
+                            self.heater.on();
+                        } else if !heater && self.heater.is_on() {
+                            self.heater.off();
+                        }
+                    }
+                    AutotuneStatus::Done(gains) => {
+                        println!(
+                            "Autotune done: kp {} ki {} kd {}",
+                            gains.kp, gains.ki, gains.kd
                         );
+                        self.pid.set_gains(gains);
+                        self.notice = Some(match gains.save(&self.config.gains_path()) {
+                            Ok(()) => String::from("Tune saved"),
+                            Err(e) => {
+                                println!("Couldn't save PID gains: {e}");
+                                String::from("Tune not saved")
+                            }
+                        });
+                        shared_data.heater_state = HeaterState::Idle;
+                    }
+                    AutotuneStatus::Failed(reason) => {
+                        println!("Autotune failed: {reason}");
+                        self.notice = Some(String::from(reason));
+                        shared_data.heater_state = HeaterState::Idle;
                     }
-
-                    // Temperature C Humidity %rh
-                    let line2 = format!("{:.2}C {:.2}%rh", self.last_temp, self.last_hum);
-
-                    // Reset the display
-                    lcd_interface::clear(&mut self.i2c)?;
-                    lcd_interface::home(&mut self.i2c)?;
-
-                    // Line 1
-                    lcd_interface::print(&mut self.i2c, line1.as_str())?;
-
-                    // Line 2
-                    lcd_interface::set_cursor(&mut self.i2c, 0, 1)?;
-                    lcd_interface::print(&mut self.i2c, line2.as_str())?;
                 }
-                DisplayState::Menu => {
-                    // Show current hovered option
-                    let line1 = format!("<   {}     >", shared_data.hovered_material.get().name);
-                    let line2 = format!(
-                        "{}C {}hrs  ",
-                        shared_data.hovered_material.get().temp,
-                        shared_data.hovered_material.get().time.as_secs() / 3600
-                    );
 
-                    let _ = lcd_interface::clear(&mut self.i2c);
-                    let _ = lcd_interface::home(&mut self.i2c);
-
-                    let _ = lcd_interface::print(&mut self.i2c, line1.as_str());
+                // Done either way, the run menu has nothing left to act on
+                if shared_data.heater_state != HeaterState::Autotune {
+                    self.autotuner = None;
+                    if let DisplayState::Run(_) | DisplayState::Confirm(_) =
+                        shared_data.display_state
+                    {
+                        shared_data.display_state = DisplayState::Idle;
+                    }
+                }
+            }
+            HeaterState::Fault(_) => {
+                // Latched until reset from the fault screen
+                // Fan keeps going to get heat off the element
+                if self.heater.is_on() {
+                    self.heater.off();
+                }
+                if !self.fan.is_on() {
+                    self.fan.on();
+                }
+            }
+        }
 
-                    let _ = lcd_interface::set_cursor(&mut self.i2c, 0, 1);
-                    let _ = lcd_interface::print(&mut self.i2c, line2.as_str());
+        // Written out every so often and whenever it is paused or resumed,
+        // so a power cut only loses a little of the run
+        if let Some(run) = &self.run
+            && let Some(RunPhase::Running | RunPhase::Paused) = shared_data.run_phase()
+        {
+            let paused = run.is_paused();
+            let period = Duration::from_secs(self.config.resume.checkpoint_seconds);
+            let due = match self.last_checkpoint {
+                Some((at, was_paused)) => now - at >= period || was_paused != paused,
+                None => true,
+            };
+            if due {
+                let checkpoint =
+                    Checkpoint::new(run.material(), run.state(now), self.wall_time(now));
+                let path = self.config.checkpoint_path();
+                if let Err(e) = checkpoint.save(&path) {
+                    println!("Couldn't save {}: {e}", path.display());
                 }
+                self.last_checkpoint = Some((now, paused));
             }
         }
+
+        self.draw(&mut shared_data, now)?;
+
         // Printing for debugging purposes
+        if !self.debug {
+            return Ok(());
+        }
         println!("Current Dryer State:");
         println!(
-            "Heater: {} Fan: {}",
-            self.heater.is_set_low(),
-            self.fan.is_set_low()
+            "Heater: {} Fan: {} Duty: {:.2}",
+            self.heater.is_on(),
+            self.fan.is_on(),
+            self.pid.output()
         );
 
-        println!("Current Material: {}", shared_data.material.get().name);
+        for (sensor, reading) in self.sensors.iter().zip(&self.readings) {
+            match reading {
+                Ok(reading) => println!(
+                    "{} ({:?}): {:.2}C {:.2}%rh",
+                    sensor.name, sensor.role, reading.temp, reading.hum
+                ),
+                Err(e) => println!("{} ({:?}): {e}", sensor.name, sensor.role),
+            }
+        }
+
+        println!(
+            "Current Material: {}",
+            shared_data.dry_table.get(shared_data.material).name
+        );
         println!(
             "Hovered Material: {}",
-            shared_data.hovered_material.get().name
+            shared_data.dry_table.get(shared_data.hovered_material).name
         );
         println!();
         Ok(())
     }
+
+    // Redraws the screen without the rest of update, so it keeps up with the buttons
+    // between updates
+    pub fn refresh(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let data = self.data.clone();
+        let mut shared_data = data.lock().unwrap();
+        self.draw(&mut shared_data, now)
+    }
+
+    // Draws the screen for the state things are in and sends what changed to the LCD
+    fn draw(&mut self, shared_data: &mut SharedData, now: Instant) -> Result<(), Box<dyn Error>> {
+        let wall = self.wall_time(now);
+
+        // Presses keep the backlight on, a fault or a failed sensor turns it on whatever
+        if std::mem::take(&mut shared_data.pressed) {
+            self.backlight.input(now);
+        }
+        let alarm = matches!(shared_data.heater_state, HeaterState::Fault(_))
+            || self.readings.iter().any(Result::is_err);
+        let asleep = self.backlight.asleep(alarm, now);
+        if asleep && !shared_data.asleep {
+            println!("Backlight timed out");
+        }
+        shared_data.asleep = asleep;
+        self.display.set_backlight(self.backlight.lit(alarm, now));
+
+        // Pages and swapping lines move on once a second however often this is called
+        if now - self.display_update >= Duration::from_secs(1) {
+            self.display_update = now;
+            self.display_ticks += 1;
+        }
+
+        let cols = self.display.cols();
+        let rows = self.display.rows();
+        let lines = match shared_data.display_state {
+            DisplayState::Idle => {
+                let line1 = if let HeaterState::Fault(fault) = shared_data.heater_state {
+                    format!("FAULT {}", fault.name())
+                } else if let Some(autotuner) = &self.autotuner {
+                    let (done, cycles) = autotuner.progress();
+                    format!("Tune {}{DEGREE}C {}/{}", autotuner.setpoint(), done, cycles)
+                } else if let (HeaterState::Storage, Some(storage)) =
+                    (shared_data.heater_state, &self.storage)
+                {
+                    match storage.burst_left(now) {
+                        Some(left) => format!("Keep dry {}", hms(left)),
+                        None => format!("Keep dry <{:.0}%rh", storage.rh()),
+                    }
+                } else if shared_data.material == Material::None {
+                    self.notice.clone().unwrap_or_else(|| String::from("Idle"))
+                } else if let (HeaterState::Waiting, Some(scheduled)) =
+                    (shared_data.heater_state, &self.waiting)
+                {
+                    // Swaps between the countdown and the material with its start time
+                    if (self.display_ticks / 3).is_multiple_of(2) {
+                        format!("Starts {}", hms(scheduled.left(wall)))
+                    } else {
+                        format!(
+                            "{:.10} {}",
+                            scheduled.material,
+                            schedule::clock(scheduled.start())
+                        )
+                    }
+                } else if let Some(run) = self.run.as_ref().filter(|run| run.is_paused()) {
+                    format!("Paused {}", hms(run.total_left(now)))
+                } else if let Some(run) = &self.run {
+                    // Multi-stage runs swap between the stage and the whole profile
+                    let material = shared_data.dry_table.get(shared_data.material);
+                    match run.stage() {
+                        Some((stage, n, count))
+                            if count > 1 && (self.display_ticks / 3).is_multiple_of(2) =>
+                        {
+                            format!("{} {n}/{count} {}", stage.name(), hms(run.stage_left(now)))
+                        }
+                        _ => format!("{}: {}", material.name, hms(run.total_left(now))),
+                    }
+                } else {
+                    shared_data
+                        .dry_table
+                        .get(shared_data.material)
+                        .name
+                        .to_string()
+                };
+
+                // Temperature C Humidity %rh
+                // The fused reading, then each sensor in turn, then how far the run has got,
+                // 3 seconds each
+                // A display with a 3rd row shows the sensors there instead
+                let sensor_pages = if self.sensors.len() < 2 || rows > 2 {
+                    0
+                } else {
+                    self.sensors.len()
+                };
+                let progress = self
+                    .run
+                    .as_ref()
+                    .filter(|run| !run.is_paused())
+                    .map(|run| run.progress(now));
+                let pages = 1 + sensor_pages + usize::from(progress.is_some());
+                let page = (self.display_ticks / 3) % pages;
+                // The fault and ceiling warnings take the line over from the pages
+                let warning = if let HeaterState::Fault(_) = shared_data.heater_state {
+                    Some(String::from("Confirm to reset"))
+                } else {
+                    self.ceiling
+                        .over()
+                        .map(|limit| format!("Over {limit:.0}{DEGREE}C, off"))
+                };
+                let line2 = if page == 0 {
+                    format!("{:.1}{DEGREE}C {DROP}{:.1}%", self.last_temp, self.last_hum)
+                } else if page <= sensor_pages {
+                    let name = &self.sensors[page - 1].name;
+                    match &self.readings[page - 1] {
+                        Ok(reading) => {
+                            format!("{name:.5} {:.0}{DEGREE}C {:.0}%", reading.temp, reading.hum)
+                        }
+                        Err(_) => format!("{name:.6} failed"),
+                    }
+                } else {
+                    let progress = progress.unwrap_or(0.0);
+                    format!("{} {:>3.0}%", glyph::bar(progress, 9), progress * 100.0)
+                };
+                let heater = self.heater.is_on();
+                let line2 =
+                    glyph::status(&line2, warning.as_deref(), heater, self.fan.is_on(), cols);
+
+                // Every sensor at once, then what the heater is doing
+                let sensors = self
+                    .sensors
+                    .iter()
+                    .zip(&self.readings)
+                    .map(|(sensor, reading)| match reading {
+                        Ok(reading) => format!("{:.4} {:.1}{DEGREE}", sensor.name, reading.temp),
+                        Err(_) => format!("{:.4} --", sensor.name),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let duty = match (
+                    shared_data.heater_state,
+                    self.target(shared_data.heater_state, now),
+                ) {
+                    // Autotune switches the heater itself rather than through the PID
+                    (HeaterState::Autotune, Some(setpoint)) => {
+                        format!(
+                            "Heat {:>3}% to {setpoint:.0}{DEGREE}C",
+                            if heater { 100 } else { 0 }
+                        )
+                    }
+                    (_, Some(setpoint)) => format!(
+                        "Heat {:>3.0}% to {setpoint:.0}{DEGREE}C",
+                        self.pid.output() * 100.0
+                    ),
+                    (_, None) => String::from("Heater off"),
+                };
+
+                vec![line1, line2, sensors, duty]
+            }
+            DisplayState::Menu
+            | DisplayState::Editor
+            | DisplayState::Schedule
+            | DisplayState::Run(_)
+            | DisplayState::Confirm(_) => {
+                // Show current hovered option, or whatever the editor, start screen or run
+                // menu is on
+                let hovered = shared_data.dry_table.get(shared_data.hovered_material);
+                let running = shared_data.dry_table.get(shared_data.material);
+                let left = match (&self.run, &self.waiting) {
+                    (Some(run), _) => hms(run.total_left(now)),
+                    (None, Some(scheduled)) => format!("in {}", hms(scheduled.left(wall))),
+                    (None, None) => String::new(),
+                };
+                let (line1, line2) = match (
+                    shared_data.display_state,
+                    &shared_data.editor,
+                    &shared_data.picker,
+                    shared_data.hovered_material,
+                ) {
+                    (DisplayState::Run(action), _, _, _) => (
+                        glyph::choice(action.name(), cols),
+                        format!("{}: {left}", running.name),
+                    ),
+                    (DisplayState::Confirm(action), _, _, _) => (
+                        format!("{}?", action.name()),
+                        String::from("Confirm or back"),
+                    ),
+                    (_, Some(editor), _, _) => editor.lines(&shared_data.dry_table, cols),
+                    (_, _, Some(picker), _) => {
+                        let material = shared_data.dry_table.get(picker.material());
+                        picker.lines(&material.name, material.time(self.last_temp), wall, cols)
+                    }
+                    (_, None, _, Material::Autotune) => (
+                        glyph::choice(&hovered.name, cols),
+                        format!("{}{DEGREE}C autotune", self.config.autotune.setpoint),
+                    ),
+                    (_, None, _, Material::Edit) => (
+                        glyph::choice(&hovered.name, cols),
+                        String::from("New profile"),
+                    ),
+                    (_, None, _, _) => (
+                        glyph::choice(&hovered.name, cols),
+                        // Ramps are timed from the chamber as it is now
+                        format!(
+                            "{}{DEGREE}C {}",
+                            hovered.temp(),
+                            hm(hovered.time(self.last_temp))
+                        ),
+                    ),
+                };
+
+                vec![line1, line2]
+            }
+        };
+
+        self.display.draw(&lines);
+        self.display.flush(&mut self.i2c)
+    }
+}
+
+// Reads every sensor once, retrying as the config says
+fn read_sensors<I: I2cBus, S: TempHumSensor<I>>(
+    sensors: &mut [Sensor<S>],
+    i2c: &mut I,
+    config: &Config,
+) -> Vec<(Role, Result<Reading, SensorError>)> {
+    sensors
+        .iter_mut()
+        .map(|sensor| {
+            (
+                sensor.role,
+                config.sensor_retry.read(&mut sensor.driver, i2c),
+            )
+        })
+        .collect()
+}
+
+// The readings fusion gets, in the order the control sensors were named to it
+fn control_readings(
+    readings: &[(Role, Result<Reading, SensorError>)],
+) -> Vec<Result<Reading, SensorError>> {
+    readings
+        .iter()
+        .filter(|(role, _)| *role == Role::Control)
+        .map(|(_, reading)| reading.clone())
+        .collect()
+}
+
+// State files go when what they were keeping is over
+fn remove_state(path: &Path) {
+    if path.exists()
+        && let Err(e) = fs::remove_file(path)
+    {
+        println!("Couldn't remove {}: {e}", path.display());
+    }
+}
+
+// Countdown on the idle screen
+fn hms(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
+// Profile length on the menu
+fn hm(time: Duration) -> String {
+    let minutes = time.as_secs() / 60;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
 }
@@ -0,0 +1,290 @@
+use std::time::{Duration, Instant};
+
+use crate::dryer::pid::PidGains;
+
+// Relay feedback autotune (Astrom-Hagglund)
+// The heater is bang-banged around the setpoint, which makes the chamber oscillate at its
+// ultimate period. The period and amplitude give the ultimate gain, and the PID gains come from that.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutotuneConfig {
+    pub setpoint: f32,
+    // Band around the setpoint so sensor noise doesn't flip the relay
+    pub hysteresis: f32,
+    // Cycles measured after the first one, which is thrown away since it starts from cold
+    // Needs to be at least 2
+    pub cycles: usize,
+    pub timeout: Duration,
+}
+
+impl AutotuneConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.cycles < 2 {
+            return Err(format!(
+                "autotune needs at least 2 cycles to time one, not {}",
+                self.cycles
+            ));
+        }
+        if !self.setpoint.is_finite() || !self.hysteresis.is_finite() || self.hysteresis < 0.0 {
+            return Err(format!(
+                "autotune setpoint {} or hysteresis {} isn't usable",
+                self.setpoint, self.hysteresis
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for AutotuneConfig {
+    fn default() -> Self {
+        Self {
+            setpoint: 60.0,
+            hysteresis: 0.5,
+            cycles: 4,
+            timeout: Duration::from_secs(3 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutotuneStatus {
+    // Relay output for the heater
+    Running { heater: bool },
+    Done(PidGains),
+    Failed(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Autotuner {
+    config: AutotuneConfig,
+    started: Instant,
+    heater: bool,
+    // Extremes of the half cycle in progress
+    high: f32,
+    low: f32,
+    // Times the relay switched off, one per full cycle
+    switch_offs: Vec<Instant>,
+    peaks: Vec<f32>,
+    troughs: Vec<f32>,
+}
+
+impl Autotuner {
+    pub fn new(config: AutotuneConfig, now: Instant) -> Self {
+        Self {
+            config,
+            started: now,
+            heater: true,
+            high: f32::MIN,
+            low: f32::MAX,
+            switch_offs: Vec::new(),
+            peaks: Vec::new(),
+            troughs: Vec::new(),
+        }
+    }
+
+    pub fn setpoint(&self) -> f32 {
+        self.config.setpoint
+    }
+
+    // Completed cycles out of the ones needed, the warmup cycle doesn't count
+    pub fn progress(&self) -> (usize, usize) {
+        (
+            self.switch_offs
+                .len()
+                .saturating_sub(1)
+                .min(self.config.cycles),
+            self.config.cycles,
+        )
+    }
+
+    pub fn update(&mut self, temp: f32, now: Instant) -> AutotuneStatus {
+        if now - self.started > self.config.timeout {
+            return AutotuneStatus::Failed("Tune timed out");
+        }
+
+        let AutotuneConfig {
+            setpoint,
+            hysteresis,
+            ..
+        } = self.config;
+
+        self.high = self.high.max(temp);
+        self.low = self.low.min(temp);
+
+        if self.heater && temp > setpoint + hysteresis {
+            // Top of the cycle, the peak is still coming since the chamber lags the heater
+            self.heater = false;
+            self.switch_offs.push(now);
+            if self.switch_offs.len() > 1 {
+                self.troughs.push(self.low);
+            }
+            self.high = temp;
+        } else if !self.heater && temp < setpoint - hysteresis {
+            self.heater = true;
+            self.peaks.push(self.high);
+            self.low = temp;
+        }
+
+        // One switch off per cycle plus the warmup
+        if self.switch_offs.len() > self.config.cycles && !self.heater {
+            return self.finish();
+        }
+
+        AutotuneStatus::Running {
+            heater: self.heater,
+        }
+    }
+
+    fn finish(&self) -> AutotuneStatus {
+        // Period from the switch offs after warmup
+        let cycles = self.config.cycles;
+        let period =
+            (self.switch_offs[cycles] - self.switch_offs[1]).as_secs_f32() / (cycles - 1) as f32;
+
+        // Skip the warmup peak, it overshoots more than the steady oscillation
+        let peaks = &self.peaks[1..];
+        let peak = peaks.iter().sum::<f32>() / peaks.len() as f32;
+        let trough = self.troughs.iter().sum::<f32>() / self.troughs.len() as f32;
+        let amplitude = (peak - trough) / 2.0;
+
+        // An infinite reading from a broken sensor would give zero gains, that isn't a tune either
+        if !period.is_finite() || !amplitude.is_finite() {
+            return AutotuneStatus::Failed("No oscillation");
+        }
+        if amplitude <= self.config.hysteresis || period <= 0.0 {
+            return AutotuneStatus::Failed("No oscillation");
+        }
+
+        // Relay swings the duty between 0 and 1, so d is 0.5
+        // Ku = 4d / (pi * sqrt(a^2 - h^2)), the root corrects for the hysteresis band
+        let relay = 0.5;
+        let hysteresis = self.config.hysteresis;
+        let ultimate_gain =
+            4.0 * relay / (std::f32::consts::PI * (amplitude.powi(2) - hysteresis.powi(2)).sqrt());
+
+        // Ziegler-Nichols "no overshoot" rule, overshoot is the thing we are trying to avoid
+        // Kp = 0.2Ku, Ti = Tu/2, Td = Tu/3
+        let kp = 0.2 * ultimate_gain;
+        let ki = kp / (period / 2.0);
+        let kd = kp * period / 3.0;
+
+        if [kp, ki, kd].iter().any(|gain| !gain.is_finite()) {
+            return AutotuneStatus::Failed("No usable gains");
+        }
+        AutotuneStatus::Done(PidGains { kp, ki, kd })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds a chamber swinging 2C either side of the setpoint every 100s, a reading a second
+    fn run(tuner: &mut Autotuner, start: Instant, secs: u64) -> AutotuneStatus {
+        let mut status = AutotuneStatus::Running { heater: true };
+        for sec in 0..secs {
+            let phase = sec as f32 / 100.0 * std::f32::consts::TAU;
+            status = tuner.update(60.0 + 2.0 * phase.sin(), start + Duration::from_secs(sec));
+            if !matches!(status, AutotuneStatus::Running { .. }) {
+                break;
+            }
+        }
+        status
+    }
+
+    #[test]
+    fn gains_from_a_steady_oscillation() {
+        let start = Instant::now();
+        let mut tuner = Autotuner::new(AutotuneConfig::default(), start);
+
+        let AutotuneStatus::Done(gains) = run(&mut tuner, start, 1000) else {
+            panic!("tune didn't finish");
+        };
+        assert_eq!(tuner.progress(), (4, 4));
+
+        let ultimate_gain = 2.0 / (std::f32::consts::PI * (2.0f32.powi(2) - 0.25).sqrt());
+        let kp = 0.2 * ultimate_gain;
+        assert!((gains.kp - kp).abs() < 0.005, "{gains:?}");
+        assert!((gains.ki - kp / 50.0).abs() < 0.0005, "{gains:?}");
+        assert!((gains.kd - kp * 100.0 / 3.0).abs() < 0.2, "{gains:?}");
+    }
+
+    #[test]
+    fn relay_follows_the_hysteresis_band() {
+        let start = Instant::now();
+        let mut tuner = Autotuner::new(AutotuneConfig::default(), start);
+
+        // Heats until over the band, then stays off until under it
+        assert_eq!(
+            tuner.update(60.4, start),
+            AutotuneStatus::Running { heater: true }
+        );
+        assert_eq!(
+            tuner.update(60.6, start),
+            AutotuneStatus::Running { heater: false }
+        );
+        assert_eq!(
+            tuner.update(59.6, start),
+            AutotuneStatus::Running { heater: false }
+        );
+        assert_eq!(
+            tuner.update(59.4, start),
+            AutotuneStatus::Running { heater: true }
+        );
+        // The warmup cycle doesn't count
+        assert_eq!(tuner.progress(), (0, 4));
+    }
+
+    #[test]
+    fn times_out_without_oscillating() {
+        let start = Instant::now();
+        let config = AutotuneConfig {
+            timeout: Duration::from_secs(60),
+            ..AutotuneConfig::default()
+        };
+        let mut tuner = Autotuner::new(config, start);
+
+        // A heater that can't reach the setpoint
+        assert_eq!(
+            tuner.update(40.0, start + Duration::from_secs(60)),
+            AutotuneStatus::Running { heater: true }
+        );
+        assert_eq!(
+            tuner.update(40.0, start + Duration::from_secs(61)),
+            AutotuneStatus::Failed("Tune timed out")
+        );
+    }
+
+    #[test]
+    fn an_infinite_reading_fails_the_tune() {
+        let start = Instant::now();
+        let mut tuner = Autotuner::new(AutotuneConfig::default(), start);
+
+        let mut status = AutotuneStatus::Running { heater: true };
+        for sec in 0..1000 {
+            let phase = sec as f32 / 100.0 * std::f32::consts::TAU;
+            // On the second peak
+            let temp = if sec == 125 {
+                f32::INFINITY
+            } else {
+                60.0 + 2.0 * phase.sin()
+            };
+            status = tuner.update(temp, start + Duration::from_secs(sec));
+            if !matches!(status, AutotuneStatus::Running { .. }) {
+                break;
+            }
+        }
+        assert_eq!(status, AutotuneStatus::Failed("No oscillation"));
+    }
+
+    #[test]
+    fn needs_two_cycles_to_time() {
+        assert!(AutotuneConfig::default().check().is_ok());
+        for cycles in [0, 1] {
+            let config = AutotuneConfig {
+                cycles,
+                ..AutotuneConfig::default()
+            };
+            assert!(config.check().is_err(), "{cycles}");
+        }
+    }
+}
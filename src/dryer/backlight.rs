@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BacklightConfig {
+    // Seconds without a button press before the backlight goes off, 0 keeps it on
+    pub timeout_secs: u64,
+    // Alarms and faults flash the backlight a second on, a second off, rather than holding it on
+    pub flash: bool,
+}
+
+impl Default for BacklightConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 300,
+            flash: false,
+        }
+    }
+}
+
+// Works out whether the backlight should be on from the last press and whether anything is
+// wrong
+#[derive(Debug)]
+pub struct Backlight {
+    config: BacklightConfig,
+    last_input: Instant,
+}
+
+impl Backlight {
+    pub fn new(config: BacklightConfig, now: Instant) -> Self {
+        Self {
+            config,
+            last_input: now,
+        }
+    }
+
+    // A button was pressed, the timeout starts again
+    pub fn input(&mut self, now: Instant) {
+        self.last_input = now;
+    }
+
+    // Timed out with nothing wrong, the next press only has to wake the screen
+    pub fn asleep(&self, alarm: bool, now: Instant) -> bool {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        !alarm && !timeout.is_zero() && now - self.last_input >= timeout
+    }
+
+    pub fn lit(&self, alarm: bool, now: Instant) -> bool {
+        if alarm && self.config.flash {
+            return (now - self.last_input).as_secs().is_multiple_of(2);
+        }
+        !self.asleep(alarm, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn goes_off_after_the_timeout() {
+        let start = Instant::now();
+        let backlight = Backlight::new(BacklightConfig::default(), start);
+
+        assert!(backlight.lit(false, start));
+        assert!(backlight.lit(false, start + 5 * MINUTE - Duration::from_secs(1)));
+        assert!(!backlight.lit(false, start + 5 * MINUTE));
+        assert!(backlight.asleep(false, start + 5 * MINUTE));
+    }
+
+    #[test]
+    fn a_press_wakes_it_and_starts_the_timeout_again() {
+        let start = Instant::now();
+        let mut backlight = Backlight::new(BacklightConfig::default(), start);
+
+        let press = start + 10 * MINUTE;
+        assert!(backlight.asleep(false, press));
+        backlight.input(press);
+        assert!(!backlight.asleep(false, press));
+        assert!(backlight.lit(false, press + 4 * MINUTE));
+        assert!(!backlight.lit(false, press + 5 * MINUTE));
+    }
+
+    #[test]
+    fn zero_keeps_it_on() {
+        let start = Instant::now();
+        let config = BacklightConfig {
+            timeout_secs: 0,
+            ..BacklightConfig::default()
+        };
+        let backlight = Backlight::new(config, start);
+
+        assert!(backlight.lit(false, start + 24 * 60 * MINUTE));
+        assert!(!backlight.asleep(false, start + 24 * 60 * MINUTE));
+    }
+
+    #[test]
+    fn alarms_keep_it_on() {
+        let start = Instant::now();
+        let backlight = Backlight::new(BacklightConfig::default(), start);
+
+        let late = start + 60 * MINUTE;
+        assert!(backlight.lit(true, late));
+        // A press while something is wrong does what it says, there is nothing to wake
+        assert!(!backlight.asleep(true, late));
+    }
+
+    #[test]
+    fn alarms_flash_a_second_on_and_a_second_off() {
+        let start = Instant::now();
+        let config = BacklightConfig {
+            flash: true,
+            ..BacklightConfig::default()
+        };
+        let backlight = Backlight::new(config, start);
+
+        let late = start + 60 * MINUTE;
+        assert!(backlight.lit(true, late));
+        assert!(!backlight.lit(true, late + Duration::from_secs(1)));
+        assert!(backlight.lit(true, late + Duration::from_secs(2)));
+        // Nothing wrong, it times out as usual
+        assert!(!backlight.lit(false, late));
+    }
+}
@@ -1,92 +1,437 @@
 use crate::dryer::HeaterState;
-use crate::dryer::display::DisplayState;
+use crate::dryer::display::{DisplayState, RunAction, RunPhase};
+use crate::dryer::dry_table::Material;
+use crate::dryer::hal::Button;
+use crate::dryer::profile_editor::ProfileEditor;
+use crate::dryer::schedule::SchedulePicker;
 use crate::dryer::shared_data::SharedData;
 
-use rppal::gpio::{Gpio, InputPin, Trigger};
 use std::{
+    error::Error,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
 };
 
+// Buttons are never read, they only have to be kept alive to hold their callbacks
+// A press on a dark screen only lights it, so nothing happens that can't be seen
 #[derive(Debug)]
-pub struct ButtonCluster {
-    back: InputPin,
-    confirm: InputPin,
-    right: InputPin,
-    left: InputPin,
+pub struct ButtonCluster<B: Button> {
+    _back: B,
+    _confirm: B,
+    _right: B,
+    _left: B,
 }
 
-impl ButtonCluster {
-    pub fn new(data: &Arc<Mutex<SharedData>>) -> Self {
-        let gpio = Gpio::new().unwrap();
-
+impl<B: Button> ButtonCluster<B> {
+    pub fn new(
+        mut back: B,
+        mut confirm: B,
+        mut right: B,
+        mut left: B,
+        data: &Arc<Mutex<SharedData>>,
+    ) -> Result<Self, Box<dyn Error>> {
         // Back Button
         let back_data = data.clone();
-        let mut back_pin = gpio.get(17).unwrap().into_input_pulldown(); // Physical Pin 11
-        let _ = back_pin.set_async_interrupt(
-            Trigger::FallingEdge,
-            Some(Duration::from_millis(50)),
-            move |_event| {
-                let mut shared_state = back_data.lock().unwrap();
-                // Go back to the menu, otherwise do nothing
-                if shared_state.display_state == DisplayState::Idle {
+        back.on_press(Box::new(move || {
+            let mut shared_state = back_data.lock().unwrap();
+            if shared_state.wake() {
+                println!("Back woke the screen");
+                return;
+            }
+            // Go back to the menu, or to what can be done with a run in progress
+            // Nothing can be started while there is a fault, so the menu is locked out too
+            let faulted = matches!(shared_state.heater_state, HeaterState::Fault(_));
+            match (shared_state.display_state, shared_state.run_phase()) {
+                (DisplayState::Idle, Some(phase)) => {
+                    shared_state.display_state = DisplayState::Run(RunAction::first(phase));
+                }
+                (DisplayState::Idle, None) if !faulted => {
                     shared_state.display_state = DisplayState::Menu;
                 }
-                println!("Back Pressed");
-            },
-        );
+                (DisplayState::Run(_), _) => shared_state.display_state = DisplayState::Idle,
+                (DisplayState::Confirm(action), _) => {
+                    shared_state.display_state = DisplayState::Run(action);
+                }
+                (DisplayState::Schedule, _) => {
+                    if let Some(picker) = shared_state.picker.as_mut()
+                        && picker.back()
+                    {
+                        shared_state.picker = None;
+                        shared_state.display_state = DisplayState::Menu;
+                    }
+                }
+                _ => {
+                    if let Some(editor) = shared_state.editor.as_mut()
+                        && editor.back()
+                    {
+                        shared_state.editor = None;
+                        shared_state.display_state = DisplayState::Menu;
+                    }
+                }
+            }
+            println!("Back Pressed");
+        }))?;
 
         // Confirm Button
         let confirm_data = data.clone();
-        let mut confirm_pin = gpio.get(27).unwrap().into_input_pulldown(); // Physical Pin 13
-        let _ = confirm_pin.set_async_interrupt(
-            Trigger::FallingEdge,
-            Some(Duration::from_millis(50)),
-            move |_event| {
-                let mut shared_state = confirm_data.lock().unwrap();
-                // Select the current hovered material as current one, turn on heater module, and
-                // change menu to idle state
-                if shared_state.display_state == DisplayState::Menu {
+        confirm.on_press(Box::new(move || {
+            let mut shared_state = confirm_data.lock().unwrap();
+            if shared_state.wake() {
+                println!("Confirm woke the screen");
+                return;
+            }
+            // Select the current hovered material as current one, turn on heater module, and
+            // change menu to idle state
+            let state = &mut *shared_state;
+            if let HeaterState::Fault(_) = state.heater_state {
+                // Explicit reset of a safety fault
+                state.heater_state = HeaterState::Idle;
+            } else if let Some(editor) = state.editor.as_mut() {
+                // Last step hands the profile over to be saved
+                if let Some(profile) = editor.confirm(&state.dry_table) {
+                    state.new_profile = Some(profile);
+                    state.editor = None;
+                    state.display_state = DisplayState::Idle;
+                }
+            } else if let Some(picker) = state.picker.as_mut() {
+                // Starting now goes the same way as before there was a start screen
+                if picker.confirm()
+                    && let Some(picker) = state.picker.take()
+                {
+                    state.material = picker.material();
+                    state.heater_started = None;
+                    state.display_state = DisplayState::Idle;
+                    if picker.starts_now() {
+                        state.heater_state = HeaterState::Running;
+                    } else {
+                        state.heater_state = HeaterState::Waiting;
+                        state.new_schedule = Some(picker);
+                    }
+                }
+            } else if let DisplayState::Run(action) = state.display_state {
+                state.display_state = if action.needs_confirm() {
+                    DisplayState::Confirm(action)
+                } else {
+                    DisplayState::Menu
+                };
+            } else if let DisplayState::Confirm(action) = state.display_state {
+                // The run may have finished, or started, while the question was up
+                if let Some(phase) = state.run_phase() {
+                    match action {
+                        RunAction::Pause => state.heater_state = HeaterState::Paused,
+                        RunAction::Resume => state.heater_state = HeaterState::Running,
+                        RunAction::StartNow if phase == RunPhase::Waiting => {
+                            state.heater_state = HeaterState::Running;
+                            state.heater_started = None;
+                        }
+                        RunAction::StartNow => {}
+                        RunAction::Cancel => {
+                            state.heater_state = HeaterState::Idle;
+                            state.material = Material::None;
+                            state.hovered_material = Material::None;
+                        }
+                        RunAction::Materials => {}
+                    }
+                    println!("{} confirmed", action.name());
+                }
+                state.display_state = DisplayState::Idle;
+            } else if state.display_state == DisplayState::Menu
+                && state.hovered_material == Material::Edit
+            {
+                state.editor = Some(ProfileEditor::new());
+                state.display_state = DisplayState::Editor;
+            } else if state.display_state == DisplayState::Menu
+                && let Material::Profile(_) = state.hovered_material
+            {
+                // Profiles ask when to start first
+                state.picker = Some(SchedulePicker::new(
+                    state.hovered_material,
+                    state.wall_clock,
+                ));
+                state.display_state = DisplayState::Schedule;
+            } else if shared_state.display_state == DisplayState::Menu {
+                if shared_state.hovered_material == Material::Autotune {
+                    shared_state.material = Material::None;
+                    shared_state.heater_state = HeaterState::Autotune;
+                } else {
                     shared_state.material = shared_state.hovered_material;
                     shared_state.heater_state = HeaterState::Running;
-                    shared_state.display_state = DisplayState::Idle;
-                    shared_state.heater_started = Instant::now();
                 }
-                println!("Confirm Pressed");
-            },
-        );
+                shared_state.display_state = DisplayState::Idle;
+                shared_state.heater_started = None;
+            }
+            println!("Confirm Pressed");
+        }))?;
 
         // Right Button
         let right_data = data.clone();
-        let mut right_pin = gpio.get(10).unwrap().into_input_pulldown(); // Physical Pin 19
-        let _ = right_pin.set_async_interrupt(
-            Trigger::FallingEdge,
-            Some(Duration::from_millis(50)),
-            move |_event| {
-                let mut shared_state = right_data.lock().unwrap();
-                shared_state.hovered_material = shared_state.hovered_material.next();
-                println!("Right Pressed");
-            },
-        );
+        right.on_press(Box::new(move || {
+            let mut shared_state = right_data.lock().unwrap();
+            if shared_state.wake() {
+                println!("Right woke the screen");
+                return;
+            }
+            let state = &mut *shared_state;
+            if let Some(editor) = state.editor.as_mut() {
+                editor.right(&state.dry_table);
+            } else if let Some(picker) = state.picker.as_mut() {
+                picker.right();
+            } else if let (DisplayState::Run(action), Some(phase)) =
+                (state.display_state, state.run_phase())
+            {
+                state.display_state = DisplayState::Run(action.next(phase));
+            } else {
+                let count = state.dry_table.len();
+                state.hovered_material = state.hovered_material.next(count);
+            }
+            println!("Right Pressed");
+        }))?;
 
         // Left Button
         let left_data = data.clone();
-        let mut left_pin = gpio.get(9).unwrap().into_input_pulldown(); // Physical Pin 21
-        let _ = left_pin.set_async_interrupt(
-            Trigger::FallingEdge,
-            Some(Duration::from_millis(50)),
-            move |_event| {
-                let mut shared_state = left_data.lock().unwrap();
-                shared_state.hovered_material = shared_state.hovered_material.prev();
-                println!("Left Pressed");
-            },
+        left.on_press(Box::new(move || {
+            let mut shared_state = left_data.lock().unwrap();
+            if shared_state.wake() {
+                println!("Left woke the screen");
+                return;
+            }
+            let state = &mut *shared_state;
+            if let Some(editor) = state.editor.as_mut() {
+                editor.left(&state.dry_table);
+            } else if let Some(picker) = state.picker.as_mut() {
+                picker.left();
+            } else if let (DisplayState::Run(action), Some(phase)) =
+                (state.display_state, state.run_phase())
+            {
+                state.display_state = DisplayState::Run(action.prev(phase));
+            } else {
+                let count = state.dry_table.len();
+                state.hovered_material = state.hovered_material.prev(count);
+            }
+            println!("Left Pressed");
+        }))?;
+
+        Ok(Self {
+            _back: back,
+            _confirm: confirm,
+            _right: right,
+            _left: left,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::dry_table::{_Material, DryTable};
+
+    type Callback = Box<dyn FnMut() + Send>;
+
+    // Holds on to its callback so the test can press it
+    #[derive(Clone, Default)]
+    struct TestButton {
+        callback: Arc<Mutex<Option<Callback>>>,
+    }
+
+    impl TestButton {
+        fn press(&self) {
+            (self.callback.lock().unwrap().as_mut().unwrap())();
+        }
+    }
+
+    impl Button for TestButton {
+        fn on_press(&mut self, callback: Callback) -> Result<(), Box<dyn Error>> {
+            *self.callback.lock().unwrap() = Some(callback);
+            Ok(())
+        }
+    }
+
+    struct Buttons {
+        back: TestButton,
+        confirm: TestButton,
+        right: TestButton,
+        left: TestButton,
+        data: Arc<Mutex<SharedData>>,
+        _cluster: ButtonCluster<TestButton>,
+    }
+
+    // PLA running, on the idle screen
+    fn running() -> Buttons {
+        let table = DryTable::new(vec![_Material::PLA], 85.0).unwrap();
+        let data = Arc::new(Mutex::new(SharedData::new(table)));
+        {
+            let mut state = data.lock().unwrap();
+            state.material = Material::Profile(0);
+            state.hovered_material = Material::Profile(0);
+            state.heater_state = HeaterState::Running;
+        }
+        let (back, confirm, right, left) = Default::default();
+        let cluster = ButtonCluster::new(
+            TestButton::clone(&back),
+            TestButton::clone(&confirm),
+            TestButton::clone(&right),
+            TestButton::clone(&left),
+            &data,
+        )
+        .unwrap();
+        Buttons {
+            back,
+            confirm,
+            right,
+            left,
+            data,
+            _cluster: cluster,
+        }
+    }
+
+    impl Buttons {
+        fn states(&self) -> (HeaterState, DisplayState) {
+            let state = self.data.lock().unwrap();
+            (state.heater_state, state.display_state)
+        }
+    }
+
+    #[test]
+    fn pause_and_resume_ask_first() {
+        let buttons = running();
+
+        buttons.back.press();
+        assert_eq!(
+            buttons.states(),
+            (HeaterState::Running, DisplayState::Run(RunAction::Pause))
+        );
+        buttons.confirm.press();
+        assert_eq!(
+            buttons.states(),
+            (
+                HeaterState::Running,
+                DisplayState::Confirm(RunAction::Pause)
+            )
+        );
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Paused, DisplayState::Idle));
+
+        // Paused, the first choice turns into resume
+        buttons.back.press();
+        assert_eq!(
+            buttons.states(),
+            (HeaterState::Paused, DisplayState::Run(RunAction::Resume))
+        );
+        buttons.confirm.press();
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Running, DisplayState::Idle));
+    }
+
+    #[test]
+    fn back_from_the_question_leaves_the_run_alone() {
+        let buttons = running();
+
+        buttons.back.press();
+        buttons.right.press();
+        buttons.confirm.press();
+        assert_eq!(
+            buttons.states(),
+            (
+                HeaterState::Running,
+                DisplayState::Confirm(RunAction::Cancel)
+            )
+        );
+        buttons.back.press();
+        assert_eq!(
+            buttons.states(),
+            (HeaterState::Running, DisplayState::Run(RunAction::Cancel))
         );
+        buttons.back.press();
+        assert_eq!(buttons.states(), (HeaterState::Running, DisplayState::Idle));
+    }
+
+    #[test]
+    fn cancel_goes_straight_to_idle() {
+        let buttons = running();
+
+        buttons.back.press();
+        buttons.right.press();
+        buttons.confirm.press();
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Idle, DisplayState::Idle));
+        let state = buttons.data.lock().unwrap();
+        assert_eq!(state.material, Material::None);
+        assert_eq!(state.run_phase(), None);
+    }
 
-        Self {
-            back: back_pin,
-            confirm: confirm_pin,
-            right: right_pin,
-            left: left_pin,
+    #[test]
+    fn a_tune_can_be_cancelled() {
+        let buttons = running();
+        {
+            let mut state = buttons.data.lock().unwrap();
+            state.material = Material::None;
+            state.heater_state = HeaterState::Autotune;
         }
+
+        // Nothing to pause, cancel comes first and right goes round the two choices
+        buttons.back.press();
+        assert_eq!(
+            buttons.states(),
+            (HeaterState::Autotune, DisplayState::Run(RunAction::Cancel))
+        );
+        buttons.right.press();
+        buttons.right.press();
+        buttons.confirm.press();
+        assert_eq!(
+            buttons.states(),
+            (
+                HeaterState::Autotune,
+                DisplayState::Confirm(RunAction::Cancel)
+            )
+        );
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Idle, DisplayState::Idle));
+    }
+
+    #[test]
+    fn materials_opens_the_menu_without_asking() {
+        let buttons = running();
+
+        buttons.back.press();
+        // Left wraps round to the last choice
+        buttons.left.press();
+        assert_eq!(
+            buttons.states(),
+            (
+                HeaterState::Running,
+                DisplayState::Run(RunAction::Materials)
+            )
+        );
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Running, DisplayState::Menu));
+    }
+
+    #[test]
+    fn a_run_that_ends_under_the_question_is_left_ended() {
+        let buttons = running();
+
+        buttons.back.press();
+        buttons.confirm.press();
+        buttons.data.lock().unwrap().heater_state = HeaterState::Idle;
+        buttons.confirm.press();
+        assert_eq!(buttons.states(), (HeaterState::Idle, DisplayState::Idle));
+    }
+
+    #[test]
+    fn a_press_on_a_dark_screen_only_wakes_it() {
+        let buttons = running();
+        buttons.data.lock().unwrap().asleep = true;
+
+        buttons.back.press();
+        assert_eq!(buttons.states(), (HeaterState::Running, DisplayState::Idle));
+        {
+            let mut state = buttons.data.lock().unwrap();
+            assert!(!state.asleep);
+            assert!(std::mem::take(&mut state.pressed));
+        }
+        buttons.back.press();
+        assert_eq!(
+            buttons.states(),
+            (HeaterState::Running, DisplayState::Run(RunAction::Pause))
+        );
     }
 }
@@ -0,0 +1,192 @@
+use serde::Deserialize;
+
+// Which character ROM the HD44780 was made with, printed on the chip as the suffix
+// e.g. HD44780UA00, most cheap 1602 modules are A00
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharRom {
+    // ASCII with a yen sign and arrows in place of \ ~ and DEL, katakana and Greek above
+    #[default]
+    A00,
+    // ASCII, symbols below the space and roughly Latin-1 above 0xA0
+    A02,
+}
+
+// Characters outside the ROM's ASCII, with their codes
+const A00: &[(char, u8)] = &[
+    ('¥', 0x5C),
+    ('→', 0x7E),
+    ('←', 0x7F),
+    ('。', 0xA1),
+    ('「', 0xA2),
+    ('」', 0xA3),
+    ('、', 0xA4),
+    ('·', 0xA5),
+    ('°', 0xDF),
+    ('α', 0xE0),
+    ('ä', 0xE1),
+    ('β', 0xE2),
+    ('ε', 0xE3),
+    ('µ', 0xE4),
+    ('σ', 0xE5),
+    ('ρ', 0xE6),
+    ('√', 0xE8),
+    ('¢', 0xEC),
+    ('ñ', 0xEE),
+    ('ö', 0xEF),
+    ('θ', 0xF2),
+    ('∞', 0xF3),
+    ('Ω', 0xF4),
+    ('ü', 0xF5),
+    ('Σ', 0xF6),
+    ('π', 0xF7),
+    ('÷', 0xFD),
+    ('█', 0xFF),
+];
+
+// Latin-1 from 0xA1 up is at its own code, so only the rest are listed
+const A02: &[(char, u8)] = &[
+    ('▶', 0x10),
+    ('◀', 0x11),
+    ('“', 0x12),
+    ('”', 0x13),
+    ('↑', 0x18),
+    ('↓', 0x19),
+    ('→', 0x1A),
+    ('←', 0x1B),
+    ('≤', 0x1C),
+    ('≥', 0x1D),
+    ('▲', 0x1E),
+    ('▼', 0x1F),
+    ('⌂', 0x7F),
+];
+
+impl CharRom {
+    // Code for a character, None if the ROM doesn't have it
+    fn exact(self, c: char) -> Option<u8> {
+        let table = match self {
+            CharRom::A00 => A00,
+            CharRom::A02 => A02,
+        };
+        if let Some((_, code)) = table.iter().find(|(rom, _)| *rom == c) {
+            return Some(*code);
+        }
+        match (self, c) {
+            (CharRom::A00, '\\' | '~') => None,
+            (_, ' '..='~') => Some(c as u8),
+            (CharRom::A02, '\u{A1}'..='\u{FF}') => Some(c as u8),
+            _ => None,
+        }
+    }
+
+    // What a character shows as, the character itself, then without its accent or the nearest
+    // ASCII, then ? so what can't be shown always looks the same
+    pub fn code(self, c: char) -> u8 {
+        self.exact(c)
+            .or_else(|| fold(c).and_then(|c| self.exact(c)))
+            .unwrap_or(b'?')
+    }
+
+    // The character at a code, for reading back what is on screen
+    #[cfg(any(feature = "sim", test))]
+    pub fn char(self, code: u8) -> Option<char> {
+        let table = match self {
+            CharRom::A00 => A00,
+            CharRom::A02 => A02,
+        };
+        table
+            .iter()
+            .find(|(_, rom)| *rom == code)
+            .map(|(c, _)| *c)
+            .or_else(|| Some(code as char).filter(|c| self.exact(*c) == Some(code)))
+    }
+}
+
+// Nearest plain character, for names like Grün on a ROM without ü
+fn fold(c: char) -> Option<char> {
+    Some(match c {
+        'À'..='Å' => 'A',
+        'à'..='å' => 'a',
+        'Ç' => 'C',
+        'ç' => 'c',
+        'È'..='Ë' => 'E',
+        'è'..='ë' => 'e',
+        'Ì'..='Ï' => 'I',
+        'ì'..='ï' => 'i',
+        'Ñ' => 'N',
+        'ñ' => 'n',
+        'Ò'..='Ö' | 'Ø' => 'O',
+        'ò'..='ö' | 'ø' => 'o',
+        'Ù'..='Ü' => 'U',
+        'ù'..='ü' => 'u',
+        'Ý' => 'Y',
+        'ý' | 'ÿ' => 'y',
+        'ß' => 's',
+        // Greek mu looks the same as the micro sign
+        'μ' => 'µ',
+        '‘' | '’' | '´' => '\'',
+        '“' | '”' | '„' => '"',
+        '–' | '—' | '~' => '-',
+        '\\' => '/',
+        '×' => 'x',
+        '…' => '.',
+        '\u{A0}' => ' ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_is_itself() {
+        for rom in [CharRom::A00, CharRom::A02] {
+            for c in ' '..='}' {
+                if c != '\\' {
+                    assert_eq!(rom.code(c), c as u8, "{rom:?} {c}");
+                }
+            }
+        }
+    }
+
+    // A00 has a yen sign and arrows where \ and ~ would be
+    #[test]
+    fn a00_swaps() {
+        assert_eq!(CharRom::A00.code('\\'), b'/');
+        assert_eq!(CharRom::A00.code('~'), b'-');
+        assert_eq!(CharRom::A00.code('¥'), 0x5C);
+        assert_eq!(CharRom::A00.code('→'), 0x7E);
+        assert_eq!(CharRom::A00.code('←'), 0x7F);
+        assert_eq!(CharRom::A02.code('\\'), b'\\');
+        assert_eq!(CharRom::A02.code('~'), b'~');
+    }
+
+    #[test]
+    fn fallbacks() {
+        // In the ROM, without its accent, then ?
+        assert_eq!(CharRom::A00.code('ü'), 0xF5);
+        assert_eq!(CharRom::A00.code('é'), b'e');
+        assert_eq!(CharRom::A02.code('é'), 0xE9);
+        assert_eq!(CharRom::A00.code('“'), b'"');
+        assert_eq!(CharRom::A02.code('“'), 0x12);
+        assert_eq!(CharRom::A00.code('μ'), 0xE4);
+        assert_eq!(CharRom::A00.code('猫'), b'?');
+        assert_eq!(CharRom::A02.code('猫'), b'?');
+    }
+
+    // Reading back a code gives the character that was asked for
+    #[test]
+    fn char_reverses_code() {
+        for rom in [CharRom::A00, CharRom::A02] {
+            for code in 0x10..=0xFF {
+                if let Some(c) = rom.char(code) {
+                    assert_eq!(rom.code(c), code, "{rom:?} {code:#04x} {c}");
+                }
+            }
+        }
+        assert_eq!(CharRom::A00.char(0x7E), Some('→'));
+        assert_eq!(CharRom::A00.char(0x80), None);
+        assert_eq!(CharRom::A02.char(0xB0), Some('°'));
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::dryer::dry_table::{_Material, Entry};
+use crate::dryer::profile_run::RunState;
+
+// What happens at startup to a run that was going when the power went
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResumeMode {
+    // Heater straight back on
+    Auto,
+    // Back paused, with the resume question on screen
+    Prompt,
+    // Dropped
+    Off,
+}
+
+// How the time the power was off counts
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DowntimePolicy {
+    // Carries on where it stopped, the downtime doesn't count
+    Resume,
+    // As if it had kept running, so it still ends on time
+    Count,
+    // The stage it was on starts over
+    RestartStage,
+    // The whole profile starts over
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResumeConfig {
+    pub mode: ResumeMode,
+    pub downtime: DowntimePolicy,
+    // Off for longer than this and the spool has soaked up the room again, the run is dropped
+    pub max_downtime_minutes: u64,
+    // How often a run is written out, lost time after a power cut is at most this
+    pub checkpoint_seconds: u64,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        Self {
+            mode: ResumeMode::Auto,
+            downtime: DowntimePolicy::Resume,
+            max_downtime_minutes: 4 * 60,
+            checkpoint_seconds: 60,
+        }
+    }
+}
+
+// A run written to disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // Unix seconds when it was written
+    saved_at: u64,
+    run: RunState,
+    // Copied whole so the run goes on the same even if the profiles file has changed
+    profile: Entry,
+}
+
+impl Checkpoint {
+    pub fn new(material: &_Material, run: RunState, now: SystemTime) -> Self {
+        Self {
+            saved_at: now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+            run,
+            profile: Entry::from(material),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        write(path, &toml::to_string(self)?)
+    }
+
+    pub fn material(&self) -> Result<_Material, String> {
+        self.profile.clone().material()
+    }
+
+    // How long the dryer was off, zero if the clock has gone backwards,
+    // which a Pi without a network or RTC can do after a reboot
+    pub fn downtime(&self, now: SystemTime) -> Duration {
+        let saved_at = UNIX_EPOCH + Duration::from_secs(self.saved_at);
+        now.duration_since(saved_at).unwrap_or(Duration::ZERO)
+    }
+
+    // Where to pick the run up from, None if it was off for too long
+    // Restarting from the beginning is left to the caller, only it knows the chamber temp
+    pub fn resume(&self, config: &ResumeConfig, now: SystemTime) -> Option<RunState> {
+        let downtime = self.downtime(now);
+        if downtime > Duration::from_secs(config.max_downtime_minutes * 60) {
+            return None;
+        }
+        let mut run = self.run;
+        match config.downtime {
+            DowntimePolicy::Resume | DowntimePolicy::Restart => {}
+            // A paused run's clock wasn't going anyway
+            DowntimePolicy::Count if run.paused => {}
+            DowntimePolicy::Count => run.elapsed += downtime.as_secs(),
+            DowntimePolicy::RestartStage => run.elapsed = 0,
+        }
+        Some(run)
+    }
+}
+
+// Written next to the old file and renamed over it, so a power cut mid-write
+// leaves the last one rather than half of this one
+pub fn write(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(paused: bool) -> Checkpoint {
+        let run = RunState {
+            stage: 1,
+            elapsed: 600,
+            from: 45.0,
+            paused,
+        };
+        Checkpoint::new(
+            &_Material::PLA_ANNEAL,
+            run,
+            UNIX_EPOCH + Duration::from_secs(1000),
+        )
+    }
+
+    fn after(minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1000 + minutes * 60)
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = checkpoint(true);
+        let back: Checkpoint = toml::from_str(&toml::to_string(&saved).unwrap()).unwrap();
+        assert_eq!(back.saved_at, saved.saved_at);
+        assert_eq!(back.run, saved.run);
+        assert_eq!(back.material().unwrap(), _Material::PLA_ANNEAL);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("pi_dry_checkpoint_{}", std::process::id()));
+        let path = dir.join("active_run.toml");
+        let saved = checkpoint(false);
+        saved.save(&path).unwrap();
+        let back = Checkpoint::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(back.run, saved.run);
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn downtime_policies() {
+        let resume = |downtime| {
+            let config = ResumeConfig {
+                downtime,
+                ..ResumeConfig::default()
+            };
+            checkpoint(false)
+                .resume(&config, after(10))
+                .unwrap()
+                .elapsed
+        };
+        assert_eq!(resume(DowntimePolicy::Resume), 600);
+        assert_eq!(resume(DowntimePolicy::Count), 600 + 10 * 60);
+        assert_eq!(resume(DowntimePolicy::RestartStage), 0);
+
+        // The clock of a paused run wasn't going
+        let config = ResumeConfig {
+            downtime: DowntimePolicy::Count,
+            ..ResumeConfig::default()
+        };
+        let run = checkpoint(true).resume(&config, after(10)).unwrap();
+        assert_eq!(run.elapsed, 600);
+    }
+
+    #[test]
+    fn too_long_off_is_dropped() {
+        let config = ResumeConfig::default();
+        let limit = config.max_downtime_minutes;
+        assert!(checkpoint(false).resume(&config, after(limit)).is_some());
+        assert!(
+            checkpoint(false)
+                .resume(&config, after(limit + 1))
+                .is_none()
+        );
+    }
+
+    // A clock that went backwards counts as no time off
+    #[test]
+    fn clock_behind_the_save() {
+        let before = UNIX_EPOCH + Duration::from_secs(10);
+        assert_eq!(checkpoint(false).downtime(before), Duration::ZERO);
+    }
+}
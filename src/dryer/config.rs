@@ -0,0 +1,285 @@
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::dryer::autotune::AutotuneConfig;
+use crate::dryer::backlight::BacklightConfig;
+use crate::dryer::checkpoint::ResumeConfig;
+use crate::dryer::dry_table::MIN_TEMP;
+use crate::dryer::fusion::FusionConfig;
+use crate::dryer::lcd_interface::LcdConfig;
+use crate::dryer::pid::PidConfig;
+use crate::dryer::plateau::PlateauConfig;
+use crate::dryer::retry::RetryPolicy;
+use crate::dryer::safety::SafetyConfig;
+use crate::dryer::storage::StorageConfig;
+use crate::dryer::temp_sensor::sht3x::Sht3xConfig;
+use crate::dryer::temp_sensor::{Role, SensorConfig, SensorSpec};
+
+// Settings for a dryer build
+// Anything missing from the config file keeps its default,
+// the skipped sections can only be changed in code for now
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub pid: PidConfig,
+    #[serde(skip)]
+    pub autotune: AutotuneConfig,
+    #[serde(skip)]
+    pub safety: SafetyConfig,
+    pub sensor_retry: RetryPolicy,
+    pub sht3x: Sht3xConfig,
+    // In the order they are read, shown and logged
+    pub sensors: Vec<SensorSpec>,
+    pub fusion: FusionConfig,
+    pub storage: StorageConfig,
+    pub plateau: PlateauConfig,
+    pub resume: ResumeConfig,
+    pub lcd: LcdConfig,
+    pub backlight: BacklightConfig,
+    // Most the enclosure takes, the heater is held off above it
+    // Has to be under the safety max_temp, which latches a fault
+    pub max_chamber_temp: f32,
+    // Material profiles, .toml or .json, the built-in table is used without one
+    pub profiles: Option<PathBuf>,
+    // Where anything the dryer writes at runtime goes
+    pub state_dir: PathBuf,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // Catches sensor lists the dryer can't run with
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if !self
+            .sensors
+            .iter()
+            .any(|sensor| sensor.role == Role::Control)
+        {
+            return Err("config needs at least one sensor with role = \"control\"".into());
+        }
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            if self.sensors[..i]
+                .iter()
+                .any(|other| other.name == sensor.name)
+            {
+                return Err(format!("two sensors are called {}", sensor.name).into());
+            }
+        }
+        // Weights go by position among the control sensors, a count that doesn't match means
+        // they would land on the wrong ones
+        let control = self
+            .sensors
+            .iter()
+            .filter(|sensor| sensor.role == Role::Control)
+            .count();
+        let weights = self.fusion.weights.len();
+        if weights != 0 && weights != control {
+            return Err(format!(
+                "fusion has {weights} weights for {control} control sensors, \
+                 they go one per control sensor in order"
+            )
+            .into());
+        }
+        self.pid.check()?;
+        self.autotune.check()?;
+        self.lcd.check()?;
+        self.sensor_retry.check()?;
+        if self.sensor_retry.max_temp <= self.safety.max_temp {
+            return Err(format!(
+                "sensor_retry max_temp has to be over the {}C overtemp fault, \
+                 or a chamber that hot would only look like a failed read",
+                self.safety.max_temp
+            )
+            .into());
+        }
+        if self.max_chamber_temp >= self.safety.max_temp {
+            return Err(format!(
+                "max_chamber_temp has to be under the {}C overtemp fault",
+                self.safety.max_temp
+            )
+            .into());
+        }
+        // Nothing could run under a limit at or below the lowest setpoint a profile can have
+        if self.max_chamber_temp.is_nan() || self.max_chamber_temp <= MIN_TEMP as f32 {
+            return Err(format!(
+                "max_chamber_temp has to be over {MIN_TEMP}C, the lowest profile temperature"
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // Profiles made in the editor, added to the end of the menu at startup
+    pub fn custom_profiles_path(&self) -> PathBuf {
+        self.state_dir.join("custom_profiles.toml")
+    }
+
+    // The run in progress, so it can carry on after a power cut
+    pub fn checkpoint_path(&self) -> PathBuf {
+        self.state_dir.join("active_run.toml")
+    }
+
+    // A run set to start later, so it still starts after a restart
+    pub fn schedule_path(&self) -> PathBuf {
+        self.state_dir.join("scheduled_run.toml")
+    }
+
+    // Gains found by autotune, loaded over the ones in pid at startup
+    pub fn gains_path(&self) -> PathBuf {
+        self.state_dir.join("pid_gains.toml")
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pid: PidConfig::default(),
+            autotune: AutotuneConfig::default(),
+            safety: SafetyConfig::default(),
+            sensor_retry: RetryPolicy::default(),
+            sht3x: Sht3xConfig::default(),
+            // The original build, two SHT3x with the address pin low and high
+            sensors: vec![
+                SensorSpec::new("near", Role::Control, SensorConfig::Sht3x { addr: 0x44 }),
+                SensorSpec::new("far", Role::Control, SensorConfig::Sht3x { addr: 0x45 }),
+            ],
+            fusion: FusionConfig::default(),
+            storage: StorageConfig::default(),
+            plateau: PlateauConfig::default(),
+            resume: ResumeConfig::default(),
+            lcd: LcdConfig::default(),
+            backlight: BacklightConfig::default(),
+            max_chamber_temp: 85.0,
+            profiles: None,
+            state_dir: PathBuf::from("state"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::temp_sensor::sht3x::{Mps, Repeatability, Sht3xMode};
+    use std::time::Duration;
+
+    #[test]
+    fn max_chamber_temp_has_to_leave_room_for_a_profile() {
+        assert!(Config::default().check().is_ok());
+        for max in [0.0, -10.0, MIN_TEMP as f32, f32::NAN] {
+            let config = Config {
+                max_chamber_temp: max,
+                ..Config::default()
+            };
+            assert!(config.check().is_err(), "{max}");
+        }
+    }
+
+    #[test]
+    fn weights_count_control_sensors_only() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[sensors]]
+            name = "room"
+            role = "ambient"
+            type = "aht20"
+
+            [[sensors]]
+            name = "near"
+            role = "control"
+            type = "sht3x"
+            addr = 0x44
+
+            [[sensors]]
+            name = "far"
+            role = "control"
+            type = "sht3x"
+            addr = 0x45
+
+            [fusion]
+            strategy = "weighted"
+            weights = [2.0, 1.0]
+            "#,
+        )
+        .unwrap();
+        assert!(config.check().is_ok());
+        config.fusion.weights = vec![1.0, 2.0, 1.0];
+        assert!(config.check().is_err());
+        config.fusion.weights.clear();
+        assert!(config.check().is_ok());
+    }
+
+    #[test]
+    fn pid_section_is_loaded_and_checked() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [pid]
+            kp = 0.5
+            window_secs = 20
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.pid.kp, 0.5);
+        assert_eq!(config.pid.ki, PidConfig::default().ki);
+        assert_eq!(config.pid.window(), Duration::from_secs(20));
+        assert!(config.check().is_ok());
+        config.pid.kd = -1.0;
+        assert!(config.check().is_err());
+        config.pid = PidConfig {
+            output_min: 0.8,
+            output_max: 0.2,
+            ..PidConfig::default()
+        };
+        assert!(config.check().is_err());
+        config.pid = PidConfig {
+            window_secs: 0,
+            ..PidConfig::default()
+        };
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn retry_range_has_to_reach_the_overtemp() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [sensor_retry]
+            attempts = 5
+            max_backoff_ms = 1000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sensor_retry.attempts, 5);
+        assert_eq!(config.sensor_retry.max_backoff(), Duration::from_secs(1));
+        assert!(config.check().is_ok());
+        // Hotter readings would be retried as bad data and the fault never seen
+        config.sensor_retry.max_temp = config.safety.max_temp;
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn sht3x_section_is_loaded() {
+        let config: Config = toml::from_str(
+            r#"
+            [sht3x]
+            repeatability = "low"
+            mode = "periodic-0.5"
+
+            [sht3x.decondense]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sht3x.repeatability, Repeatability::Low);
+        assert_eq!(config.sht3x.mode, Sht3xMode::Periodic(Mps::Half));
+        assert!(!config.sht3x.decondense.enabled);
+        assert_eq!(config.sht3x.decondense.heat_for(), Duration::from_secs(10));
+
+        let mode = toml::from_str::<Config>("sht3x.mode = \"periodic-3\"");
+        assert!(mode.is_err());
+    }
+}
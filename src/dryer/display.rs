@@ -1,18 +1,362 @@
-#[derive(Debug, PartialEq)]
+use std::error::Error;
+
+use crate::dryer::char_rom::CharRom;
+use crate::dryer::glyph;
+use crate::dryer::hal::I2cBus;
+use crate::dryer::lcd_interface::{Lcd, LcdConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DisplayState {
     Idle,
     Menu,
+    // Making a profile, the ProfileEditor in SharedData has the rest
+    Editor,
+    // When to start the material picked, the SchedulePicker in SharedData has the rest
+    Schedule,
+    // Picking what to do with the run in progress
+    Run(RunAction),
+    // Asking before doing it, confirm goes ahead and back goes back to Run
+    Confirm(RunAction),
+}
+
+// Where the run the run menu is about has got to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunPhase {
+    // Scheduled for later, nothing has started
+    Waiting,
+    Running,
+    Paused,
+    // Autotune, it can only be stopped or replaced
+    Tuning,
+}
+
+// What back offers while a profile is running or waiting to, or autotune is going
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunAction {
+    Pause,
+    Resume,
+    StartNow,
+    Cancel,
+    // Over to the material menu, picking one there replaces the run
+    Materials,
+}
+
+impl RunAction {
+    // Pause while running, resume while paused, start now while waiting
+    fn choices(phase: RunPhase) -> &'static [RunAction] {
+        match phase {
+            RunPhase::Waiting => &[RunAction::StartNow, RunAction::Cancel, RunAction::Materials],
+            RunPhase::Running => &[RunAction::Pause, RunAction::Cancel, RunAction::Materials],
+            RunPhase::Paused => &[RunAction::Resume, RunAction::Cancel, RunAction::Materials],
+            RunPhase::Tuning => &[RunAction::Cancel, RunAction::Materials],
+        }
+    }
+
+    pub fn first(phase: RunPhase) -> Self {
+        Self::choices(phase)[0]
+    }
+
+    pub fn next(self, phase: RunPhase) -> Self {
+        let choices = Self::choices(phase);
+        let i = choices.iter().position(|c| *c == self).unwrap_or(0);
+        choices[(i + 1) % choices.len()]
+    }
+
+    pub fn prev(self, phase: RunPhase) -> Self {
+        let choices = Self::choices(phase);
+        let i = choices.iter().position(|c| *c == self).unwrap_or(0);
+        choices[(i + choices.len() - 1) % choices.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RunAction::Pause => "Pause",
+            RunAction::Resume => "Resume",
+            RunAction::StartNow => "Start now",
+            RunAction::Cancel => "Cancel run",
+            RunAction::Materials => "Materials",
+        }
+    }
+
+    // Only the ones that change the run get asked about
+    pub fn needs_confirm(self) -> bool {
+        self != RunAction::Materials
+    }
 }
 
+// A row of LCD codes for each row of the screen
+type Frame = Vec<Vec<u8>>;
+
+// Shadow of the screen, screens are drawn into the frame and only the cells that differ
+// from what the LCD is showing get sent
 #[derive(Debug)]
 pub struct Display {
-    state: DisplayState,
+    lcd: Lcd,
+    frame: Frame,
+    // What the LCD has, None when it isn't known, at startup or after a failed write
+    shown: Option<Frame>,
+    // Where the LCD will put the next character, it moves on by itself after each one
+    cursor: Option<(usize, usize)>,
+    rom: CharRom,
+    // Whether the backlight should be on, sent with the next flush
+    backlight: bool,
 }
 
 impl Display {
-    pub fn new() -> Self {
+    pub fn new(config: &LcdConfig) -> Self {
+        let lcd = Lcd::new(config);
         Self {
-            state: DisplayState::Idle,
+            frame: vec![vec![b' '; lcd.cols()]; lcd.rows()],
+            lcd,
+            shown: None,
+            cursor: None,
+            rom: config.rom,
+            backlight: true,
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.lcd.cols()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.lcd.rows()
+    }
+
+    // Starts the LCD up and loads the glyphs
+    pub fn init(&mut self, i2c: &mut impl I2cBus) -> Result<(), Box<dyn Error>> {
+        // Whatever happens, the next flush clears the screen and puts the cursor back in DDRAM
+        self.shown = None;
+        self.cursor = None;
+        self.lcd.init(i2c)?;
+        glyph::load(&self.lcd, i2c, self.rom)
+    }
+
+    // Replaces the frame, lines are cut or padded to the width and missing ones are blank
+    pub fn draw(&mut self, lines: &[String]) {
+        for (i, row) in self.frame.iter_mut().enumerate() {
+            let mut chars = lines.get(i).map(|line| line.chars());
+            for cell in row.iter_mut() {
+                let c = chars.as_mut().and_then(Iterator::next);
+                *cell = c.map_or(b' ', |c| glyph::code(c, self.rom));
+            }
+        }
+    }
+
+    pub fn set_backlight(&mut self, on: bool) {
+        self.backlight = on;
+    }
+
+    // Sends the cells that have changed since the last flush, and the backlight if it changed
+    pub fn flush(&mut self, i2c: &mut impl I2cBus) -> Result<(), Box<dyn Error>> {
+        let result = self.send(i2c);
+        if result.is_err() {
+            // Part of it may have gone, start again from a clear screen next time
+            self.shown = None;
+            self.cursor = None;
+        }
+        result
+    }
+
+    fn send(&mut self, i2c: &mut impl I2cBus) -> Result<(), Box<dyn Error>> {
+        if self.lcd.backlight() != self.backlight {
+            self.lcd.set_backlight(i2c, self.backlight)?;
+        }
+        let cols = self.lcd.cols();
+        if self.shown.is_none() {
+            self.lcd.clear(i2c)?;
+            self.cursor = Some((0, 0));
         }
+        let shown = self
+            .shown
+            .get_or_insert_with(|| vec![vec![b' '; cols]; self.frame.len()]);
+        for (row, (line, shown)) in self.frame.iter().zip(shown.iter_mut()).enumerate() {
+            let mut col = 0;
+            while col < cols {
+                if line[col] == shown[col] {
+                    col += 1;
+                    continue;
+                }
+                // The run of changed cells from here goes in one write
+                let end = (col..cols)
+                    .find(|&end| line[end] == shown[end])
+                    .unwrap_or(cols);
+                if self.cursor != Some((col, row)) {
+                    self.lcd.set_cursor(i2c, col as u8, row as u8)?;
+                }
+                self.lcd.write(i2c, &line[col..end])?;
+                shown[col..end].copy_from_slice(&line[col..end]);
+                self.cursor = Some((end, row));
+                col = end;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::hal::BusError;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Sent {
+        Command(u8),
+        Data(u8),
+    }
+
+    // Decodes what the backpack would clock into the LCD, a nibble on each falling enable
+    #[derive(Default)]
+    struct Recorder {
+        enable: bool,
+        high: Option<u8>,
+        sent: Vec<Sent>,
+        fail: bool,
+    }
+
+    impl Recorder {
+        fn take(&mut self) -> Vec<Sent> {
+            std::mem::take(&mut self.sent)
+        }
+    }
+
+    impl I2cBus for Recorder {
+        fn set_slave_address(&mut self, _addr: u16) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn write(&mut self, buf: &[u8]) -> Result<(), BusError> {
+            if self.fail {
+                return Err(BusError::Nack);
+            }
+            for byte in buf {
+                let enable = byte & 0x04 != 0;
+                if self.enable && !enable {
+                    let nibble = byte >> 4;
+                    match self.high.take() {
+                        None => self.high = Some(nibble),
+                        Some(high) if byte & 0x01 != 0 => {
+                            self.sent.push(Sent::Data(high << 4 | nibble))
+                        }
+                        Some(high) => self.sent.push(Sent::Command(high << 4 | nibble)),
+                    }
+                }
+                self.enable = enable;
+            }
+            Ok(())
+        }
+        fn read(&mut self, _buf: &mut [u8]) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn delay(&mut self, _duration: std::time::Duration) {}
+    }
+
+    fn data(text: &str) -> Vec<Sent> {
+        text.bytes().map(Sent::Data).collect()
+    }
+
+    #[test]
+    fn first_flush_clears_and_sends_the_text() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["Hi".into(), "".into()]);
+        display.flush(&mut lcd).unwrap();
+        // Blank cells are already blank after the clear
+        let mut expected = vec![Sent::Command(0x01)];
+        expected.extend(data("Hi"));
+        assert_eq!(lcd.take(), expected);
+    }
+
+    #[test]
+    fn unchanged_frame_sends_nothing() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["Idle".into(), "45.0C 20%".into()]);
+        display.flush(&mut lcd).unwrap();
+        lcd.take();
+        display.draw(&["Idle".into(), "45.0C 20%".into()]);
+        display.flush(&mut lcd).unwrap();
+        assert_eq!(lcd.take(), vec![]);
+    }
+
+    #[test]
+    fn only_changed_cells_are_rewritten() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["Left 5:59:58".into(), "45.0C 20%".into()]);
+        display.flush(&mut lcd).unwrap();
+        lcd.take();
+
+        display.draw(&["Left 5:59:57".into(), "45.1C 20%".into()]);
+        display.flush(&mut lcd).unwrap();
+        let mut expected = vec![Sent::Command(0x80 | 11)];
+        expected.extend(data("7"));
+        expected.push(Sent::Command(0x80 | 0x40 | 3));
+        expected.extend(data("1"));
+        assert_eq!(lcd.take(), expected);
+    }
+
+    #[test]
+    fn runs_go_in_one_write_and_the_cursor_only_moves_over_gaps() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["aaaaaaaaaaaaaaaa".into(), "bbbbbbbbbbbbbbbb".into()]);
+        display.flush(&mut lcd).unwrap();
+        lcd.take();
+
+        // Two runs on the top line, with a move to each
+        display.draw(&["aXYaaaaaaaaaaaaZ".into(), "bbbbbbbbbbbbbbbb".into()]);
+        display.flush(&mut lcd).unwrap();
+        let mut expected = vec![Sent::Command(0x80 | 1)];
+        expected.extend(data("XY"));
+        expected.push(Sent::Command(0x80 | 15));
+        expected.extend(data("Z"));
+        assert_eq!(lcd.take(), expected);
+
+        // Written up to the end of a row, the next run still moves the cursor
+        display.draw(&["aXYaaaaaaaaaaaaW".into(), "Cbbbbbbbbbbbbbbb".into()]);
+        display.flush(&mut lcd).unwrap();
+        let mut expected = vec![Sent::Command(0x80 | 15)];
+        expected.extend(data("W"));
+        expected.push(Sent::Command(0x80 | 0x40));
+        expected.extend(data("C"));
+        assert_eq!(lcd.take(), expected);
+    }
+
+    #[test]
+    fn cursor_is_left_where_the_lcd_moved_it() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["Hi".into(), "".into()]);
+        display.flush(&mut lcd).unwrap();
+        lcd.take();
+        // The LCD's cursor is already just after the i
+        display.draw(&["Hi!".into(), "".into()]);
+        display.flush(&mut lcd).unwrap();
+        assert_eq!(lcd.take(), data("!"));
+    }
+
+    #[test]
+    fn failed_write_sends_everything_again() {
+        let mut display = Display::new(&LcdConfig::default());
+        let mut lcd = Recorder::default();
+
+        display.draw(&["Idle".into(), "".into()]);
+        display.flush(&mut lcd).unwrap();
+        lcd.take();
+
+        lcd.fail = true;
+        display.draw(&["Menu".into(), "".into()]);
+        assert!(display.flush(&mut lcd).is_err());
+        lcd.fail = false;
+        display.draw(&["Menu".into(), "".into()]);
+        display.flush(&mut lcd).unwrap();
+        let mut expected = vec![Sent::Command(0x01)];
+        expected.extend(data("Menu"));
+        assert_eq!(lcd.take(), expected);
     }
 }
@@ -1,100 +1,945 @@
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, cmp::Reverse, error::Error, fs, path::Path, time::Duration};
 
-#[derive(Debug)]
+use crate::dryer::checkpoint;
+
+// Longest name that fits between the arrows on the menu
+pub const MAX_NAME: usize = 14;
+// Anything cooler isn't drying, it's just keeping the spool warm
+pub const MIN_TEMP: u32 = 30;
+// Longest run the idle screen can count down
+pub const MAX_TIME: Duration = Duration::from_secs(99 * 60 * 60);
+
+// Cow can't be borrowed out of a const, DryTable::get hands these out instead
+static NONE: _Material = _Material::NONE;
+static AUTOTUNE: _Material = _Material::AUTOTUNE;
+static EDIT: _Material = _Material::EDIT;
+
+// Fastest ramp a profile can ask for, the chamber can't follow anything quicker anyway
+pub const MAX_RATE: f32 = 10.0;
+// Ramps are timed from here when checking a profile, before anything has been read
+const ROOM_TEMP: f32 = 20.0;
+
+// One part of a profile, they run in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    // Moves the setpoint from where the last stage left it to temp at rate C per minute
+    Ramp { temp: u32, rate: f32 },
+    // Holds temp for time, a soak straight after another one steps the setpoint
+    Soak { temp: u32, time: Duration },
+    // Heater off with the fan on, so the spool is cool when it comes out
+    Cool { time: Duration },
+}
+
+impl Stage {
+    // Short enough to share the top line with the time left
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Ramp { .. } => "Ramp",
+            Stage::Soak { .. } => "Soak",
+            Stage::Cool { .. } => "Cool",
+        }
+    }
+
+    // Setpoint at the end of the stage, None if it doesn't heat
+    pub fn temp(&self) -> Option<u32> {
+        match self {
+            Stage::Ramp { temp, .. } | Stage::Soak { temp, .. } => Some(*temp),
+            Stage::Cool { .. } => None,
+        }
+    }
+
+    // How long it runs when the setpoint starts at from
+    pub fn time(&self, from: f32) -> Duration {
+        match self {
+            Stage::Ramp { temp, rate } => {
+                Duration::from_secs_f32((*temp as f32 - from).abs() / rate * 60.0)
+            }
+            Stage::Soak { time, .. } | Stage::Cool { time } => *time,
+        }
+    }
+
+    fn check(&self, max_temp: f32) -> Result<(), String> {
+        if let Some(temp) = self.temp() {
+            if temp < MIN_TEMP {
+                return Err(format!("temp {temp}C is below {MIN_TEMP}C"));
+            }
+            if temp as f32 >= max_temp {
+                return Err(format!(
+                    "temp {temp}C is at or above the {max_temp}C chamber limit"
+                ));
+            }
+        }
+        match self {
+            Stage::Ramp { rate, .. } if !(*rate > 0.0 && *rate <= MAX_RATE) => Err(format!(
+                "ramp has to be more than 0 and at most {MAX_RATE}C per minute"
+            )),
+            Stage::Soak { time, .. } | Stage::Cool { time } if time.is_zero() => {
+                Err(String::from("time is zero, give hours or minutes"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// What a profile is for, which decides what the fan does
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    // Fan always on, to carry the moisture out
+    #[default]
+    Dry,
+    // Printed parts, the fan only runs while the heater does so they heat and cool evenly
+    // in still air and don't warp
+    Anneal,
+}
+
+// Time to run through stages when the setpoint starts at from
+// After a cool down the next ramp is counted from wherever the last heated stage ended
+pub fn time(stages: &[Stage], mut from: f32) -> Duration {
+    let mut total = Duration::ZERO;
+    for stage in stages {
+        total += stage.time(from);
+        from = stage.temp().map_or(from, |temp| temp as f32);
+    }
+    total
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct _Material {
-    pub name: &'static str,
-    pub temp: u32,
-    pub time: Duration,
+    // Borrowed for the built-in table, owned for profiles loaded from a file
+    pub name: Cow<'static, str>,
+    pub kind: ProfileKind,
+    pub stages: Cow<'static, [Stage]>,
+    // RH that starts a keep-dry burst after the profile, None for the one in the config
+    pub store_rh: Option<f32>,
+    // Ends the main soak early once the filament is dry, its time becomes the longest it runs
+    pub plateau: Option<Plateau>,
+    // Hottest the chamber may get with this spool in it, e.g. under PLA's glass transition
+    // None leaves it to the chamber limit in the config
+    pub max_temp: Option<u32>,
+}
+
+// When the humidity says a soak is done
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plateau {
+    // RH has to have settled under this
+    pub rh: f32,
+    // Soonest the soak can end, counted from its start
+    pub min_time: Duration,
 }
 
 impl _Material {
-    pub const PLA: _Material = _Material {
-        name: "PLA",
-        temp: 45,
-        time: Duration::from_secs(60 * 60 * 6),
-    };
-
-    pub const PVB: _Material = _Material {
-        name: "PVB",
-        temp: 45,
-        time: Duration::from_secs(60 * 60 * 8),
-    };
-
-    pub const PETG: _Material = _Material {
-        name: "PETG",
-        temp: 55,
-        time: Duration::from_secs(60 * 60 * 6),
-    };
-
-    pub const ASA: _Material = _Material {
-        name: "ASA",
-        temp: 80,
-        time: Duration::from_secs(60 * 60 * 4),
-    };
-
-    pub const TPU: _Material = _Material {
-        name: "TPU",
-        temp: 60,
-        time: Duration::from_secs(60 * 60 * 4),
-    };
-
-    pub const NONE: _Material = _Material {
-        name: "IDLE",
-        temp: 0,
-        time: Duration::from_secs(0),
-    };
-
-    pub const DEMO: _Material = _Material {
-        name: "DEMO",
-        temp: 45,
-        time: Duration::from_secs(60 * 5),
-    };
+    const fn new(
+        name: &'static str,
+        stages: &'static [Stage],
+        store_rh: Option<f32>,
+        max_temp: Option<u32>,
+    ) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            kind: ProfileKind::Dry,
+            stages: Cow::Borrowed(stages),
+            store_rh,
+            plateau: None,
+            max_temp,
+        }
+    }
+
+    const fn anneal(name: &'static str, stages: &'static [Stage], max_temp: u32) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            kind: ProfileKind::Anneal,
+            stages: Cow::Borrowed(stages),
+            store_rh: None,
+            plateau: None,
+            max_temp: Some(max_temp),
+        }
+    }
+
+    pub const PLA: _Material = _Material::new(
+        "PLA",
+        &[Stage::Soak {
+            temp: 45,
+            time: Duration::from_secs(60 * 60 * 6),
+        }],
+        Some(20.0),
+        Some(50),
+    );
+
+    pub const PVB: _Material = _Material::new(
+        "PVB",
+        &[Stage::Soak {
+            temp: 45,
+            time: Duration::from_secs(60 * 60 * 8),
+        }],
+        Some(15.0),
+        Some(55),
+    );
+
+    pub const PETG: _Material = _Material::new(
+        "PETG",
+        &[Stage::Soak {
+            temp: 55,
+            time: Duration::from_secs(60 * 60 * 6),
+        }],
+        Some(15.0),
+        Some(65),
+    );
+
+    pub const ASA: _Material = _Material::new(
+        "ASA",
+        &[Stage::Soak {
+            temp: 80,
+            time: Duration::from_secs(60 * 60 * 4),
+        }],
+        Some(20.0),
+        Some(90),
+    );
+
+    pub const TPU: _Material = _Material::new(
+        "TPU",
+        &[Stage::Soak {
+            temp: 60,
+            time: Duration::from_secs(60 * 60 * 4),
+        }],
+        Some(15.0),
+        Some(70),
+    );
+
+    pub const NONE: _Material = _Material::new("IDLE", &[], None, None);
+
+    // Not a material, runs the PID autotune at the setpoint from the config
+    pub const AUTOTUNE: _Material = _Material::new("TUNE", &[], None, None);
+
+    // Not a material either, opens the profile editor
+    pub const EDIT: _Material = _Material::new("EDIT", &[], None, None);
+
+    pub const DEMO: _Material = _Material::new(
+        "DEMO",
+        &[Stage::Soak {
+            temp: 45,
+            time: Duration::from_secs(60 * 5),
+        }],
+        None,
+        Some(50),
+    );
+
+    // Up past the glass transition slowly, an hour there, then down slowly enough that the
+    // chamber can follow with the fan off
+    pub const PLA_ANNEAL: _Material = _Material::anneal(
+        "PLA anneal",
+        &[
+            Stage::Ramp {
+                temp: 70,
+                rate: 1.0,
+            },
+            Stage::Soak {
+                temp: 70,
+                time: Duration::from_secs(60 * 60),
+            },
+            Stage::Ramp {
+                temp: 40,
+                rate: 0.5,
+            },
+        ],
+        80,
+    );
+
+    pub const PETG_ANNEAL: _Material = _Material::anneal(
+        "PETG anneal",
+        &[
+            Stage::Ramp {
+                temp: 80,
+                rate: 1.0,
+            },
+            Stage::Soak {
+                temp: 80,
+                time: Duration::from_secs(60 * 60),
+            },
+            Stage::Ramp {
+                temp: 40,
+                rate: 0.5,
+            },
+        ],
+        85,
+    );
+
+    // Menu order when there is no profiles file
+    pub const BUILT_IN: [_Material; 8] = [
+        _Material::DEMO,
+        _Material::PLA,
+        _Material::PVB,
+        _Material::PETG,
+        _Material::ASA,
+        _Material::TPU,
+        _Material::PLA_ANNEAL,
+        _Material::PETG_ANNEAL,
+    ];
+
+    // Hottest setpoint in the profile, what the menu shows
+    pub fn temp(&self) -> u32 {
+        self.stages
+            .iter()
+            .filter_map(Stage::temp)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Whole profile, with the first ramp starting from the chamber at from
+    pub fn time(&self, from: f32) -> Duration {
+        time(&self.stages, from)
+    }
+
+    // The longest soak, the one the editor changes
+    pub fn main_soak(&self) -> Option<usize> {
+        self.stages
+            .iter()
+            .enumerate()
+            .filter(|(_, stage)| matches!(stage, Stage::Soak { .. }))
+            // The first one wins a tie
+            .max_by_key(|(i, stage)| (stage.time(0.0), Reverse(*i)))
+            .map(|(i, _)| i)
+    }
+
+    // Says what is wrong with a profile, if anything
+    // max_temp is the chamber limit from the config, a profile at it would be held off every run
+    pub fn check(&self, max_temp: f32) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err(String::from("name is empty"));
+        }
+        if self.name.chars().count() > MAX_NAME {
+            return Err(format!("name is longer than {MAX_NAME} characters"));
+        }
+        // Anything else gets shown as near as the LCD's character ROM can
+        if self.name.chars().any(char::is_control) {
+            return Err(String::from("name can't have control characters"));
+        }
+        let reserved = [&NONE.name, &AUTOTUNE.name, &EDIT.name];
+        if reserved
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&self.name))
+        {
+            return Err(format!("{} is used by the menu", self.name));
+        }
+        // Held off over its own limit, a setpoint at it would never be reached
+        if let Some(max_temp) = self.max_temp
+            && let Some(temp) = self
+                .stages
+                .iter()
+                .filter_map(Stage::temp)
+                .find(|temp| *temp >= max_temp)
+        {
+            return Err(format!(
+                "temp {temp}C is at or above its max_temp of {max_temp}C"
+            ));
+        }
+        if let Some(rh) = self.store_rh
+            && !(rh > 0.0 && rh < 100.0)
+        {
+            return Err(format!("store_rh {rh}% has to be between 0 and 100%"));
+        }
+        let Some(main) = self.main_soak() else {
+            return Err(String::from("needs a soak stage"));
+        };
+        // Parts warp if the temperature jumps, so annealing has to ramp both ways
+        if self.kind == ProfileKind::Anneal {
+            if self.store_rh.is_some() || self.plateau.is_some() {
+                return Err(String::from(
+                    "store_rh and plateau are for drying, not annealing",
+                ));
+            }
+            let ramp = |stage: Option<&Stage>| matches!(stage, Some(Stage::Ramp { .. }));
+            if !ramp(self.stages.first()) || !ramp(self.stages.last()) {
+                return Err(String::from(
+                    "annealing has to start with a ramp up and end with a ramp down",
+                ));
+            }
+        }
+        if let Some(plateau) = self.plateau {
+            if !(plateau.rh > 0.0 && plateau.rh < 100.0) {
+                return Err(format!(
+                    "plateau rh {}% has to be between 0 and 100%",
+                    plateau.rh
+                ));
+            }
+            if plateau.min_time > self.stages[main].time(0.0) {
+                return Err(String::from(
+                    "plateau min time is longer than the soak it ends",
+                ));
+            }
+        }
+        for (i, stage) in self.stages.iter().enumerate() {
+            // A plain temp and time profile is one stage, numbering it would only confuse
+            let error = |e: String| match self.stages.len() {
+                1 => e,
+                _ => format!("stage {}: {e}", i + 1),
+            };
+            stage.check(max_temp).map_err(error)?;
+        }
+        if self.time(ROOM_TEMP) > MAX_TIME {
+            return Err(format!("time is over {} hours", MAX_TIME.as_secs() / 3600));
+        }
+        Ok(())
+    }
+}
+
+// One [[material]] in a profiles file, hours and minutes are added together
+// Either a temp and time for a single soak, or a list of stages
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    name: String,
+    #[serde(default, skip_serializing_if = "is_dry")]
+    kind: ProfileKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temp: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    hours: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    minutes: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    store_rh: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_temp: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plateau: Option<PlateauEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stage: Vec<StageEntry>,
+}
+
+// [material.plateau]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct PlateauEntry {
+    rh: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    min_hours: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    min_minutes: f32,
 }
 
+// One [[material.stage]], which kind it is depends on what is given:
+// ramp and temp, temp and a time to soak, or cool = true and a time
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct StageEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temp: Option<u32>,
+    // C per minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ramp: Option<f32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    hours: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    minutes: f32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    cool: bool,
+}
+
+impl Entry {
+    // Not checked yet, that is left to DryTable
+    pub fn material(self) -> Result<_Material, String> {
+        let stages = if self.stage.is_empty() {
+            let temp = self.temp.ok_or("temp is missing")?;
+            vec![Stage::Soak {
+                temp,
+                time: duration(self.hours, self.minutes)?,
+            }]
+        } else if self.temp.is_some() || self.hours != 0.0 || self.minutes != 0.0 {
+            return Err(String::from("give a temp and time or stages, not both"));
+        } else {
+            self.stage
+                .iter()
+                .enumerate()
+                .map(|(i, stage)| stage.stage().map_err(|e| format!("stage {}: {e}", i + 1)))
+                .collect::<Result<_, _>>()?
+        };
+        let plateau = match self.plateau {
+            Some(plateau) => Some(Plateau {
+                rh: plateau.rh,
+                min_time: duration(plateau.min_hours, plateau.min_minutes)?,
+            }),
+            None => None,
+        };
+        Ok(_Material {
+            name: Cow::Owned(self.name),
+            kind: self.kind,
+            stages: Cow::Owned(stages),
+            store_rh: self.store_rh,
+            plateau,
+            max_temp: self.max_temp,
+        })
+    }
+}
+
+impl From<&_Material> for Entry {
+    // A single soak is written the short way
+    fn from(material: &_Material) -> Self {
+        let name = material.name.to_string();
+        let plateau = material.plateau.map(|plateau| PlateauEntry {
+            rh: plateau.rh,
+            min_hours: 0.0,
+            min_minutes: plateau.min_time.as_secs() as f32 / 60.0,
+        });
+        if let [Stage::Soak { temp, time }] = *material.stages {
+            return Entry {
+                name,
+                kind: material.kind,
+                temp: Some(temp),
+                hours: 0.0,
+                minutes: time.as_secs() as f32 / 60.0,
+                store_rh: material.store_rh,
+                max_temp: material.max_temp,
+                plateau,
+                stage: Vec::new(),
+            };
+        }
+        let stage = material
+            .stages
+            .iter()
+            .map(|stage| match *stage {
+                Stage::Ramp { temp, rate } => StageEntry {
+                    temp: Some(temp),
+                    ramp: Some(rate),
+                    ..StageEntry::default()
+                },
+                Stage::Soak { temp, time } => StageEntry {
+                    temp: Some(temp),
+                    minutes: time.as_secs() as f32 / 60.0,
+                    ..StageEntry::default()
+                },
+                Stage::Cool { time } => StageEntry {
+                    minutes: time.as_secs() as f32 / 60.0,
+                    cool: true,
+                    ..StageEntry::default()
+                },
+            })
+            .collect();
+        Entry {
+            name,
+            kind: material.kind,
+            temp: None,
+            hours: 0.0,
+            minutes: 0.0,
+            store_rh: material.store_rh,
+            max_temp: material.max_temp,
+            plateau,
+            stage,
+        }
+    }
+}
+
+impl StageEntry {
+    fn stage(&self) -> Result<Stage, String> {
+        let time = duration(self.hours, self.minutes)?;
+        match (self.cool, self.ramp, self.temp) {
+            (true, None, None) => Ok(Stage::Cool { time }),
+            (true, _, _) => Err(String::from("a cool stage has no temp or ramp")),
+            (false, Some(_), _) if !time.is_zero() => Err(String::from(
+                "a ramp ends when it gets to its temp, it has no time",
+            )),
+            (false, Some(rate), Some(temp)) => Ok(Stage::Ramp { temp, rate }),
+            (false, None, Some(temp)) => Ok(Stage::Soak { temp, time }),
+            (false, _, None) => Err(String::from("temp is missing")),
+        }
+    }
+}
+
+fn is_dry(kind: &ProfileKind) -> bool {
+    *kind == ProfileKind::Dry
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+fn duration(hours: f32, minutes: f32) -> Result<Duration, String> {
+    let seconds = hours * 3600.0 + minutes * 60.0;
+    if seconds < 0.0 {
+        return Err(String::from("time can't be negative"));
+    }
+    Duration::try_from_secs_f32(seconds).map_err(|_| format!("{hours}h {minutes}m is too long"))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    material: Vec<Entry>,
+}
+
+// The materials on the menu, in the order they are shown
+// Profiles made on the device come after the ones from the built-in table or profiles file
+#[derive(Debug, Clone)]
+pub struct DryTable {
+    materials: Vec<_Material>,
+    // Index of the first custom profile
+    custom: usize,
+    // Chamber limit every profile has to stay under
+    max_temp: f32,
+}
+
+impl DryTable {
+    // The built-in profiles, less any the chamber limit would hold off on every run
+    pub fn built_in(max_temp: f32) -> Result<Self, String> {
+        let materials: Vec<_Material> = _Material::BUILT_IN
+            .into_iter()
+            .filter(|material| match material.check(max_temp) {
+                Ok(()) => true,
+                Err(e) => {
+                    println!("Leaving out {}: {e}", material.name);
+                    false
+                }
+            })
+            .collect();
+        if materials.is_empty() {
+            return Err(format!(
+                "every built-in profile is over the {max_temp}C chamber limit"
+            ));
+        }
+        Self::new(materials, max_temp)
+    }
+
+    // Checks every profile, errors name the profile and what is wrong with it
+    pub fn new(materials: Vec<_Material>, max_temp: f32) -> Result<Self, String> {
+        if materials.is_empty() {
+            return Err(String::from("no materials"));
+        }
+        for (i, material) in materials.iter().enumerate() {
+            let error = |e: String| format!("material {} ({}): {e}", i + 1, material.name);
+            material.check(max_temp).map_err(error)?;
+            let repeat = materials[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&material.name));
+            if repeat {
+                return Err(error(String::from("name is used twice")));
+            }
+        }
+        Ok(Self {
+            custom: materials.len(),
+            materials,
+            max_temp,
+        })
+    }
+
+    pub fn load(path: &Path, max_temp: f32) -> Result<Self, Box<dyn Error>> {
+        Self::new(read(path)?, max_temp).map_err(|e| format!("{}: {e}", path.display()).into())
+    }
+
+    // Adds the profiles saved from the editor to the end of the menu
+    // Ones that clash with the table or are out of limits are skipped with a message
+    pub fn load_custom(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        for material in read(path)? {
+            if let Err(e) = self.add_custom(material.clone()) {
+                println!("Skipping custom profile {}: {e}", material.name);
+            }
+        }
+        Ok(())
+    }
+
+    // Adds a profile made on the device, or replaces the custom profile with the same name
+    // Returns where it ended up
+    pub fn add_custom(&mut self, material: _Material) -> Result<usize, String> {
+        material.check(self.max_temp)?;
+        let existing = self
+            .materials
+            .iter()
+            .position(|other| other.name.eq_ignore_ascii_case(&material.name));
+        match existing {
+            Some(i) if i < self.custom => Err(format!("{} is taken", material.name)),
+            Some(i) => {
+                self.materials[i] = material;
+                Ok(i)
+            }
+            None => {
+                self.materials.push(material);
+                Ok(self.materials.len() - 1)
+            }
+        }
+    }
+
+    // Writes every custom profile out in the same format a profiles file uses
+    pub fn save_custom(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = ProfilesFile {
+            material: self.materials[self.custom..]
+                .iter()
+                .map(Entry::from)
+                .collect(),
+        };
+        // The only copy of what was made on the device, so it can't be left half written
+        checkpoint::write(path, &toml::to_string(&file)?)
+    }
+
+    pub fn max_temp(&self) -> f32 {
+        self.max_temp
+    }
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    // Menu spot of the profile with this name
+    pub fn find(&self, name: &str) -> Option<Material> {
+        self.materials
+            .iter()
+            .position(|material| material.name.eq_ignore_ascii_case(name))
+            .map(Material::Profile)
+    }
+
+    pub fn get(&self, material: Material) -> &_Material {
+        match material {
+            Material::None => &NONE,
+            Material::Profile(i) => self.materials.get(i).unwrap_or(&NONE),
+            Material::Autotune => &AUTOTUNE,
+            Material::Edit => &EDIT,
+        }
+    }
+}
+
+// Profiles from a file, not checked yet
+// .json files are read as JSON, anything else as TOML
+fn read(path: &Path) -> Result<Vec<_Material>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let file: ProfilesFile = match path.extension() {
+        Some(ext) if ext == "json" => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        _ => toml::from_str(&text).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    file.material
+        .into_iter()
+        .map(|entry| {
+            let name = entry.name.clone();
+            entry
+                .material()
+                .map_err(|e| format!("{}: {name}: {e}", path.display()).into())
+        })
+        .collect()
+}
+
+// A spot on the menu, profiles are indexes into the DryTable
+// The menu wraps: IDLE, every profile in order, TUNE, EDIT, back to IDLE
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Material {
     None,
-    Demo,
-    Pla,
-    Pvb,
-    Petg,
-    Asa,
-    Tpu,
+    Profile(usize),
+    Autotune,
+    Edit,
 }
 
 impl Material {
-    pub fn get(&self) -> _Material {
+    // count is how many profiles are in the table
+    pub fn next(self, count: usize) -> Self {
         match self {
-            Self::None => _Material::NONE,
-            Self::Demo => _Material::DEMO,
-            Self::Pla => _Material::PLA,
-            Self::Pvb => _Material::PVB,
-            Self::Petg => _Material::PETG,
-            Self::Asa => _Material::ASA,
-            Self::Tpu => _Material::TPU,
+            Self::None if count > 0 => Self::Profile(0),
+            Self::Profile(i) if i + 1 < count => Self::Profile(i + 1),
+            Self::None | Self::Profile(_) => Self::Autotune,
+            Self::Autotune => Self::Edit,
+            Self::Edit => Self::None,
         }
     }
-    pub fn next(self) -> Self {
+
+    pub fn prev(self, count: usize) -> Self {
         match self {
-            Self::None => Self::Demo,
-            Self::Demo => Self::Pla,
-            Self::Pla => Self::Pvb,
-            Self::Pvb => Self::Petg,
-            Self::Petg => Self::Asa,
-            Self::Asa => Self::Tpu,
-            Self::Tpu => Self::None,
+            Self::None => Self::Edit,
+            Self::Profile(0) => Self::None,
+            Self::Profile(i) => Self::Profile(i.min(count).saturating_sub(1)),
+            Self::Autotune if count > 0 => Self::Profile(count - 1),
+            Self::Autotune => Self::None,
+            Self::Edit => Self::Autotune,
         }
     }
+}
 
-    pub fn prev(self) -> Self {
-        match self {
-            Self::None => Self::Tpu,
-            Self::Demo => Self::None,
-            Self::Pla => Self::Demo,
-            Self::Pvb => Self::Pla,
-            Self::Petg => Self::Pvb,
-            Self::Asa => Self::Petg,
-            Self::Tpu => Self::Asa,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<_Material>, String> {
+        let file: ProfilesFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        file.material.into_iter().map(Entry::material).collect()
+    }
+
+    #[test]
+    fn single_soak_adds_hours_and_minutes() {
+        let materials = parse(
+            r#"
+            [[material]]
+            name = "PA-CF"
+            temp = 80
+            hours = 8
+            minutes = 30
+            max_temp = 90
+            "#,
+        )
+        .unwrap();
+        let material = &materials[0];
+        assert_eq!(material.name, "PA-CF");
+        assert_eq!(
+            *material.stages,
+            [Stage::Soak {
+                temp: 80,
+                time: Duration::from_secs(8 * 3600 + 30 * 60),
+            }]
+        );
+        assert_eq!(material.max_temp, Some(90));
+        assert_eq!(material.kind, ProfileKind::Dry);
+    }
+
+    #[test]
+    fn stages() {
+        let materials = parse(
+            r#"
+            [[material]]
+            name = "Nylon"
+            [[material.stage]]
+            temp = 70
+            ramp = 2
+            [[material.stage]]
+            temp = 70
+            hours = 6
+            [[material.stage]]
+            cool = true
+            minutes = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            *materials[0].stages,
+            [
+                Stage::Ramp {
+                    temp: 70,
+                    rate: 2.0
+                },
+                Stage::Soak {
+                    temp: 70,
+                    time: Duration::from_secs(6 * 3600),
+                },
+                Stage::Cool {
+                    time: Duration::from_secs(30 * 60)
+                },
+            ]
+        );
+        // 25 minutes up from room temperature, then the soak and cool down
+        assert_eq!(
+            materials[0].time(20.0),
+            Duration::from_secs(25 * 60 + 6 * 3600 + 30 * 60)
+        );
+    }
+
+    #[test]
+    fn bad_entries() {
+        let cases = [
+            // Both a temp and stages
+            "name = \"A\"\ntemp = 50\nhours = 1\n[[material.stage]]\ntemp = 50\nhours = 1",
+            // A ramp with a time
+            "name = \"A\"\n[[material.stage]]\ntemp = 50\nramp = 1\nhours = 1",
+            // A cool stage with a temp
+            "name = \"A\"\n[[material.stage]]\ncool = true\ntemp = 50\nhours = 1",
+            // No temp
+            "name = \"A\"\nhours = 1",
+            // Negative time
+            "name = \"A\"\ntemp = 50\nhours = -1",
+            // Too long to hold in a Duration
+            "name = \"A\"\ntemp = 50\nhours = 1e20",
+            "name = \"A\"\ntemp = 50\nhours = inf",
+            // Misspelt field
+            "name = \"A\"\ntemp = 50\nhour = 1",
+        ];
+        for case in cases {
+            assert!(parse(&format!("[[material]]\n{case}")).is_err(), "{case}");
+        }
+    }
+
+    #[test]
+    fn json() {
+        let file: ProfilesFile =
+            serde_json::from_str(r#"{"material": [{"name": "PA-CF", "temp": 80, "hours": 8}]}"#)
+                .unwrap();
+        let material = file
+            .material
+            .into_iter()
+            .next()
+            .unwrap()
+            .material()
+            .unwrap();
+        assert_eq!(material.temp(), 80);
+    }
+
+    #[test]
+    fn check() {
+        let soak = |name: &str, temp, max_temp| _Material {
+            name: Cow::Owned(String::from(name)),
+            kind: ProfileKind::Dry,
+            stages: Cow::Owned(vec![Stage::Soak {
+                temp,
+                time: Duration::from_secs(3600),
+            }]),
+            store_rh: None,
+            plateau: None,
+            max_temp,
+        };
+        assert!(soak("PLA", 45, Some(50)).check(85.0).is_ok());
+        // Below the lowest temp, at the chamber limit, at its own limit
+        assert!(soak("PLA", MIN_TEMP - 1, None).check(85.0).is_err());
+        assert!(soak("PLA", 85, None).check(85.0).is_err());
+        assert!(soak("PLA", 50, Some(50)).check(85.0).is_err());
+        // Names
+        assert!(soak("", 45, None).check(85.0).is_err());
+        assert!(
+            soak("A name that is too long", 45, None)
+                .check(85.0)
+                .is_err()
+        );
+        assert!(soak("tune", 45, None).check(85.0).is_err());
+        assert!(soak("PETG Grün", 45, None).check(85.0).is_ok());
+
+        let twice = vec![soak("PLA", 45, None), soak("pla", 50, None)];
+        assert!(DryTable::new(twice, 85.0).is_err());
+        assert!(DryTable::new(Vec::new(), 85.0).is_err());
+    }
+
+    // What the editor saves has to load back the same
+    #[test]
+    fn entries_round_trip() {
+        for material in _Material::BUILT_IN {
+            let file = ProfilesFile {
+                material: vec![Entry::from(&material)],
+            };
+            let text = toml::to_string(&file).unwrap();
+            let back = parse(&text).unwrap();
+            assert_eq!(back[0], material, "{text}");
+        }
+    }
+
+    #[test]
+    fn menu_wraps() {
+        let order = [
+            Material::None,
+            Material::Profile(0),
+            Material::Profile(1),
+            Material::Autotune,
+            Material::Edit,
+        ];
+        for (i, material) in order.iter().enumerate() {
+            assert_eq!(material.next(2), order[(i + 1) % order.len()]);
+            assert_eq!(order[(i + 1) % order.len()].prev(2), *material);
+        }
+    }
+
+    #[test]
+    fn built_in_leaves_out_profiles_over_the_limit() {
+        let table = DryTable::built_in(70.0).unwrap();
+        assert!(table.find("ASA").is_none());
+        assert!(table.find("PLA anneal").is_none());
+        assert!(table.find("PLA").is_some());
+        for i in 0..table.len() {
+            let material = table.get(Material::Profile(i));
+            assert!(material.check(70.0).is_ok(), "{}", material.name);
         }
+        assert_eq!(
+            DryTable::built_in(85.0).unwrap().len(),
+            _Material::BUILT_IN.len()
+        );
+        assert!(DryTable::built_in(40.0).is_err());
     }
 }
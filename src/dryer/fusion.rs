@@ -0,0 +1,295 @@
+use serde::Deserialize;
+
+use crate::dryer::hal::{Reading, SensorError};
+
+// How the readings from all the sensors become the one the controller sees
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FusionStrategy {
+    Average,
+    // Hottest and most humid reading, the heater backs off as soon as any spot gets hot
+    Max,
+    // Average using the weights from the config
+    Weighted,
+    Median,
+    // Controls off the first control sensor, the rest are only watched by the supervisor
+    // Falls back to averaging the rest if the first one is out
+    Primary,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FusionConfig {
+    pub strategy: FusionStrategy,
+    // Only used by weighted, one per control sensor in the order they come in the sensor
+    // list, sensors with any other role aren't counted
+    // Empty gives every sensor 1
+    pub weights: Vec<f32>,
+    // With 3 or more sensors, readings this far from the median are left out
+    pub outlier_limit: f32,
+    // A sensor that jumps more than this between reads is left out,
+    // unless it keeps reading that way for max_rejects reads in a row
+    pub max_jump: f32,
+    pub max_rejects: u32,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        Self {
+            strategy: FusionStrategy::Average,
+            weights: Vec::new(),
+            outlier_limit: 5.0,
+            max_jump: 5.0,
+            max_rejects: 3,
+        }
+    }
+}
+
+// What fusion knows about one sensor between reads
+#[derive(Debug, Default)]
+struct Track {
+    last: Option<Reading>,
+    rejects: u32,
+    // Left out of the last fused reading
+    dropped: bool,
+}
+
+#[derive(Debug)]
+pub struct Fusion {
+    config: FusionConfig,
+    names: Vec<String>,
+    tracks: Vec<Track>,
+}
+
+impl Fusion {
+    // names are only for the log, one per sensor in the order readings are passed in
+    pub fn new(config: FusionConfig, names: Vec<String>) -> Self {
+        Self {
+            config,
+            tracks: names.iter().map(|_| Track::default()).collect(),
+            names,
+        }
+    }
+
+    // One reading from every sensor in, one reading out
+    // None if every sensor failed or got filtered out
+    pub fn fuse(&mut self, readings: &[Result<Reading, SensorError>]) -> Option<Reading> {
+        let mut usable: Vec<(usize, Reading)> = Vec::new();
+        let mut reasons: Vec<Option<String>> = vec![None; readings.len()];
+
+        for (i, reading) in readings.iter().enumerate() {
+            let track = &mut self.tracks[i];
+            match reading {
+                Err(e) => reasons[i] = Some(e.to_string()),
+                Ok(reading) => {
+                    // Chamber air can't change this fast, it's a glitch unless it sticks
+                    let jumped = track.last.is_some_and(|last| {
+                        (reading.temp - last.temp).abs() > self.config.max_jump
+                    });
+                    if jumped && track.rejects < self.config.max_rejects {
+                        track.rejects += 1;
+                        reasons[i] = Some(format!("jumped to {:.2}C", reading.temp));
+                    } else {
+                        track.last = Some(*reading);
+                        track.rejects = 0;
+                        usable.push((i, *reading));
+                    }
+                }
+            }
+        }
+
+        // Enough sensors to outvote one that has wandered off
+        if usable.len() >= 3 {
+            let median = median(usable.iter().map(|(_, r)| r.temp).collect());
+            usable.retain(|(i, reading)| {
+                let outlier = (reading.temp - median).abs() > self.config.outlier_limit;
+                if outlier {
+                    reasons[*i] =
+                        Some(format!("{:.2}C is too far from {median:.2}C", reading.temp));
+                }
+                !outlier
+            });
+        }
+
+        // Only log changes, a dead sensor would fill the log every second otherwise
+        for (i, reason) in reasons.iter().enumerate() {
+            let track = &mut self.tracks[i];
+            match reason {
+                Some(reason) if !track.dropped => {
+                    println!("{} sensor left out: {reason}", self.names[i]);
+                }
+                None if track.dropped => println!("{} sensor back", self.names[i]),
+                _ => {}
+            }
+            track.dropped = reason.is_some();
+        }
+
+        if usable.is_empty() {
+            return None;
+        }
+
+        let readings: Vec<Reading> = usable.iter().map(|(_, r)| *r).collect();
+        Some(match self.config.strategy {
+            FusionStrategy::Average => average(&readings),
+            FusionStrategy::Max => Reading {
+                temp: readings.iter().map(|r| r.temp).fold(f32::MIN, f32::max),
+                hum: readings.iter().map(|r| r.hum).fold(f32::MIN, f32::max),
+            },
+            FusionStrategy::Weighted => {
+                let weight = |i: usize| self.config.weights.get(i).copied().unwrap_or(1.0);
+                let total: f32 = usable.iter().map(|(i, _)| weight(*i)).sum();
+                if total <= 0.0 {
+                    average(&readings)
+                } else {
+                    let sum = |value: fn(&Reading) -> f32| {
+                        usable
+                            .iter()
+                            .map(|(i, r)| weight(*i) * value(r))
+                            .sum::<f32>()
+                            / total
+                    };
+                    Reading {
+                        temp: sum(|r| r.temp),
+                        hum: sum(|r| r.hum),
+                    }
+                }
+            }
+            FusionStrategy::Median => Reading {
+                temp: median(readings.iter().map(|r| r.temp).collect()),
+                hum: median(readings.iter().map(|r| r.hum).collect()),
+            },
+            FusionStrategy::Primary => match usable.first() {
+                Some((0, reading)) => *reading,
+                _ => average(&readings),
+            },
+        })
+    }
+}
+
+fn average(readings: &[Reading]) -> Reading {
+    let n = readings.len() as f32;
+    Reading {
+        temp: readings.iter().map(|r| r.temp).sum::<f32>() / n,
+        hum: readings.iter().map(|r| r.hum).sum::<f32>() / n,
+    }
+}
+
+// Middle value, or the average of the middle two
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fusion(strategy: FusionStrategy, count: usize) -> Fusion {
+        let config = FusionConfig {
+            strategy,
+            ..FusionConfig::default()
+        };
+        Fusion::new(config, (0..count).map(|i| format!("s{i}")).collect())
+    }
+
+    fn ok(temp: f32, hum: f32) -> Result<Reading, SensorError> {
+        Ok(Reading { temp, hum })
+    }
+
+    #[test]
+    fn strategies() {
+        let readings = [ok(40.0, 20.0), ok(44.0, 10.0)];
+        let fused = |strategy| fusion(strategy, 2).fuse(&readings).unwrap();
+        assert_eq!(
+            fused(FusionStrategy::Average),
+            Reading {
+                temp: 42.0,
+                hum: 15.0
+            }
+        );
+        assert_eq!(
+            fused(FusionStrategy::Max),
+            Reading {
+                temp: 44.0,
+                hum: 20.0
+            }
+        );
+        assert_eq!(
+            fused(FusionStrategy::Median),
+            Reading {
+                temp: 42.0,
+                hum: 15.0
+            }
+        );
+        assert_eq!(
+            fused(FusionStrategy::Primary),
+            Reading {
+                temp: 40.0,
+                hum: 20.0
+            }
+        );
+
+        let mut weighted = fusion(FusionStrategy::Weighted, 2);
+        weighted.config.weights = vec![3.0, 1.0];
+        assert_eq!(
+            weighted.fuse(&readings).unwrap(),
+            Reading {
+                temp: 41.0,
+                hum: 17.5
+            }
+        );
+    }
+
+    #[test]
+    fn failed_sensors_are_left_out() {
+        let mut primary = fusion(FusionStrategy::Primary, 3);
+        let readings = [Err(SensorError::Nack), ok(40.0, 20.0), ok(42.0, 10.0)];
+        // The primary is out, the rest are averaged
+        assert_eq!(
+            primary.fuse(&readings).unwrap(),
+            Reading {
+                temp: 41.0,
+                hum: 15.0
+            }
+        );
+        let none = [Err(SensorError::Nack), Err(SensorError::Checksum)];
+        assert_eq!(fusion(FusionStrategy::Average, 2).fuse(&none), None);
+    }
+
+    // A weight belongs to its sensor even when one before it is out
+    #[test]
+    fn weights_follow_their_sensor() {
+        let mut weighted = fusion(FusionStrategy::Weighted, 3);
+        weighted.config.weights = vec![1.0, 3.0, 1.0];
+        let readings = [Err(SensorError::Nack), ok(40.0, 20.0), ok(44.0, 20.0)];
+        assert_eq!(weighted.fuse(&readings).unwrap().temp, 41.0);
+    }
+
+    #[test]
+    fn outliers_are_left_out_with_three_sensors() {
+        let mut average = fusion(FusionStrategy::Average, 3);
+        let readings = [ok(40.0, 20.0), ok(41.0, 20.0), ok(60.0, 20.0)];
+        assert_eq!(average.fuse(&readings).unwrap().temp, 40.5);
+    }
+
+    #[test]
+    fn jumps_are_rejected_until_they_stick() {
+        let mut average = fusion(FusionStrategy::Average, 1);
+        average.fuse(&[ok(40.0, 20.0)]);
+        for _ in 0..average.config.max_rejects {
+            assert_eq!(average.fuse(&[ok(50.0, 20.0)]), None);
+        }
+        assert_eq!(average.fuse(&[ok(50.0, 20.0)]).unwrap().temp, 50.0);
+    }
+
+    #[test]
+    fn median_of_even_and_odd() {
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(vec![4.0, 1.0, 2.0, 3.0]), 2.5);
+    }
+}
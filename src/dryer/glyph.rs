@@ -0,0 +1,176 @@
+use std::error::Error;
+
+use crate::dryer::char_rom::CharRom;
+use crate::dryer::hal::I2cBus;
+use crate::dryer::lcd_interface::Lcd;
+
+// Screens write these characters, the Display Module turns them into LCD codes
+pub const DEGREE: char = '°';
+pub const DROP: char = '💧';
+pub const FLAME: char = '🔥';
+pub const FAN: char = '🌀';
+pub const LEFT: char = '←';
+pub const RIGHT: char = '→';
+pub const FULL: char = '█';
+
+// A cell with 1 to 4 of its 5 columns filled, for the progress bar
+const PARTS: [char; 4] = ['▏', '▎', '▌', '▊'];
+
+// The 8 user characters, in their CGRAM slots, 5 pixels a row from the top
+// The arrows and full block are in the A00 ROM, which leaves the slots for the bar
+const GLYPHS: [(char, [u8; 8]); 8] = [
+    (
+        DEGREE,
+        [
+            0b01100, 0b10010, 0b10010, 0b01100, 0b00000, 0b00000, 0b00000, 0b00000,
+        ],
+    ),
+    (
+        DROP,
+        [
+            0b00100, 0b00100, 0b01010, 0b01010, 0b10001, 0b10001, 0b01110, 0b00000,
+        ],
+    ),
+    (
+        FLAME,
+        [
+            0b00010, 0b00100, 0b01100, 0b01110, 0b11111, 0b11011, 0b01110, 0b00000,
+        ],
+    ),
+    (
+        FAN,
+        [
+            0b00000, 0b11001, 0b01011, 0b00100, 0b11010, 0b10011, 0b00000, 0b00000,
+        ],
+    ),
+    (PARTS[0], [0b10000; 8]),
+    (PARTS[1], [0b11000; 8]),
+    (PARTS[2], [0b11100; 8]),
+    (PARTS[3], [0b11110; 8]),
+];
+
+// The user characters for a ROM
+pub fn glyphs(rom: CharRom) -> [(char, [u8; 8]); 8] {
+    let mut glyphs = GLYPHS;
+    // A02 has a degree sign but no full block
+    if rom == CharRom::A02 {
+        glyphs[0] = (FULL, [0b11111; 8]);
+    }
+    glyphs
+}
+
+// Writes the glyphs into CGRAM, they stay there until the LCD loses power
+pub fn load(lcd: &Lcd, i2c: &mut impl I2cBus, rom: CharRom) -> Result<(), Box<dyn Error>> {
+    for (slot, (_, rows)) in glyphs(rom).iter().enumerate() {
+        lcd.set_glyph(i2c, slot as u8, rows)?;
+    }
+    Ok(())
+}
+
+// LCD code for any character, one of ours or whatever the ROM makes of it
+pub fn code(c: char, rom: CharRom) -> u8 {
+    glyphs(rom)
+        .iter()
+        .position(|(glyph, _)| *glyph == c)
+        .map_or_else(|| rom.code(c), |slot| slot as u8)
+}
+
+// A menu choice between the arrows, on a whole line cols wide
+pub fn choice(name: &str, cols: usize) -> String {
+    let width = cols - 2;
+    format!("{LEFT}{name:^width$}{RIGHT}")
+}
+
+// The second line of the idle screen, with the heater and fan in the last two columns while
+// either is on. A warning needs the whole line, so it goes without them.
+pub fn status(line: &str, warning: Option<&str>, heater: bool, fan: bool, cols: usize) -> String {
+    if let Some(warning) = warning {
+        return warning.to_string();
+    }
+    if !heater && !fan {
+        return line.to_string();
+    }
+    let width = cols - 2;
+    format!(
+        "{line:<width$.width$}{}{}",
+        if heater { FLAME } else { ' ' },
+        if fan { FAN } else { ' ' }
+    )
+}
+
+// cells wide, filled a fifth of a cell at a time
+pub fn bar(fraction: f32, cells: usize) -> String {
+    let fifths = (fraction.clamp(0.0, 1.0) * (cells * 5) as f32).round() as usize;
+    let mut bar: String = std::iter::repeat_n(FULL, fifths / 5).collect();
+    if let Some(part) = (fifths % 5).checked_sub(1) {
+        bar.push(PARTS[part]);
+    }
+    format!("{bar:<cells$}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_fills_a_fifth_at_a_time() {
+        assert_eq!(bar(0.0, 4), "    ");
+        assert_eq!(bar(1.0, 4), "████");
+        assert_eq!(bar(0.5, 4), "██  ");
+        // 4 cells are 20 fifths, so 0.3 is 6 of them
+        assert_eq!(bar(0.3, 4), "█▏  ");
+        assert_eq!(bar(0.2, 1), "▏");
+        assert_eq!(bar(0.8, 1), "▊");
+    }
+
+    #[test]
+    fn bar_keeps_its_width() {
+        assert_eq!(bar(-1.0, 9), " ".repeat(9));
+        assert_eq!(bar(2.0, 9), "█".repeat(9));
+        for step in 0..=20 {
+            assert_eq!(bar(step as f32 / 20.0, 9).chars().count(), 9, "{step}");
+        }
+    }
+
+    #[test]
+    fn every_bar_char_has_a_code() {
+        for rom in [CharRom::A00, CharRom::A02] {
+            for step in 0..=5 {
+                for c in bar(step as f32 / 5.0, 1).chars().filter(|c| *c != ' ') {
+                    assert_ne!(code(c, rom), b'?', "{c}");
+                }
+            }
+        }
+        assert_eq!(code(DEGREE, CharRom::A00), 0);
+        assert_eq!(code(PARTS[3], CharRom::A00), 7);
+        assert_eq!(code(FULL, CharRom::A00), 0xFF);
+        assert_eq!(code('A', CharRom::A00), b'A');
+    }
+
+    #[test]
+    fn icons_take_the_last_two_columns() {
+        let line = "45.0°C 💧20.0%";
+        assert_eq!(status(line, None, false, false, 16), line);
+        assert_eq!(status(line, None, true, false, 16), "45.0°C 💧20.0% 🔥 ");
+        assert_eq!(status(line, None, false, true, 16), "45.0°C 💧20.0%  🌀");
+        // A longer page is cut short to make room
+        assert_eq!(
+            status("PLA 5:59:58 left", None, true, true, 16),
+            "PLA 5:59:58 le🔥🌀"
+        );
+    }
+
+    #[test]
+    fn warnings_win_over_the_icons() {
+        for (heater, fan) in [(false, false), (true, false), (false, true), (true, true)] {
+            assert_eq!(
+                status("45.0°C", Some("Confirm to reset"), heater, fan, 16),
+                "Confirm to reset"
+            );
+            assert_eq!(
+                status("45.0°C", Some("Over 50°C, off"), heater, fan, 16),
+                "Over 50°C, off"
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(feature = "rpi")]
+pub mod rpi;
+#[cfg(feature = "sim")]
+pub mod sim;
+
+use std::{
+    error::Error,
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+// Traits for the hardware that the dryer talks to
+// The control loop only ever goes through these, so it can run against the Pi or anything else
+
+// A relay driving the fan or heater
+// on/off are the state of the load, implementations handle the relay polarity
+pub trait Relay {
+    fn on(&mut self);
+    fn off(&mut self);
+    fn is_on(&self) -> bool;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusError {
+    // Nothing acknowledged the address or a byte, usually a device that is missing or busy
+    Nack,
+    Other(String),
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::Nack => write!(f, "I2C NACK"),
+            BusError::Other(e) => write!(f, "I2C error: {e}"),
+        }
+    }
+}
+
+impl Error for BusError {}
+
+// An I2C bus that can address multiple devices
+// Mirrors the parts of rppal::i2c::I2c that get used
+pub trait I2cBus {
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), BusError>;
+    fn write(&mut self, buf: &[u8]) -> Result<(), BusError>;
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError>;
+
+    // Waits for a device to finish what it was told to do
+    // Real buses sleep, a simulated bus doesn't have to
+    fn delay(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// A push button or knob direction
+// The callback is called from whatever thread the implementation uses
+pub trait Button {
+    fn on_press(&mut self, callback: Box<dyn FnMut() + Send>) -> Result<(), Box<dyn Error>>;
+}
+
+// A GPIO pin for single wire devices like the DHT22
+// The line has a pull-up, so it is either driven low or let go and floats high
+pub trait IoPin {
+    fn set_low(&mut self);
+    fn release(&mut self);
+    fn is_high(&self) -> bool;
+
+    // Same as I2cBus::delay, for waits measured in milliseconds
+    fn delay(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    // Clock for spacing out reads, a simulated pin keeps the model's time
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Temperature in C and relative humidity in %
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub temp: f32,
+    pub hum: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorError {
+    Bus(String),
+    Nack,
+    TempCrc,
+    HumCrc,
+    StatusCrc,
+    // Sensors with one check over the whole frame
+    Checksum,
+    // Decoded fine but can't be real, outside the sensor's range or the range we accept
+    OutOfRange(Reading),
+}
+
+impl fmt::Display for SensorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SensorError::Bus(e) => write!(f, "bus error: {e}"),
+            SensorError::Nack => write!(f, "sensor didn't acknowledge"),
+            SensorError::TempCrc => write!(f, "temperature CRC not valid"),
+            SensorError::HumCrc => write!(f, "humidity CRC not valid"),
+            SensorError::StatusCrc => write!(f, "status CRC not valid"),
+            SensorError::Checksum => write!(f, "checksum not valid"),
+            SensorError::OutOfRange(reading) => write!(
+                f,
+                "reading out of range: {:.2}C {:.2}%rh",
+                reading.temp, reading.hum
+            ),
+        }
+    }
+}
+
+impl Error for SensorError {}
+
+impl From<BusError> for SensorError {
+    fn from(e: BusError) -> Self {
+        match e {
+            BusError::Nack => SensorError::Nack,
+            BusError::Other(e) => SensorError::Bus(e),
+        }
+    }
+}
+
+// Something that can measure temperature and relative humidity
+// Sensors don't hold the bus, it gets passed in on every read
+pub trait TempHumSensor<I: I2cBus> {
+    // Called once at startup before the first read
+    fn init(&mut self, _i2c: &mut I) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError>;
+}
+
+// Lets a sensor picked from the config at runtime go anywhere a sensor is expected
+impl<I: I2cBus, S: TempHumSensor<I> + ?Sized> TempHumSensor<I> for Box<S> {
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        (**self).init(i2c)
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        (**self).read(i2c)
+    }
+}
@@ -0,0 +1,161 @@
+use rppal::{
+    gpio::{self, Bias, Gpio, InputPin, Mode, OutputPin, Trigger},
+    i2c::I2c,
+};
+use std::{error::Error, time::Duration};
+
+use crate::dryer::config::Config;
+use crate::dryer::hal::{BusError, Button, I2cBus, IoPin, Relay, TempHumSensor};
+use crate::dryer::temp_sensor;
+use crate::dryer::{Hardware, Sensor};
+
+pub type RpiHardware = Hardware<I2c, RpiRelay, RpiButton, Box<dyn TempHumSensor<I2c>>>;
+
+// Builds the hardware using the wiring from the readme
+pub fn hardware(config: &Config) -> Result<RpiHardware, Box<dyn Error>> {
+    let gpio = Gpio::new()?;
+
+    Ok(Hardware {
+        // One I2c instance is passed around because
+        // I've had issues with each I2c device holding their own instance
+        i2c: I2c::new()?,
+        fan: RpiRelay::new(&gpio, 14)?,      // Physical Pin 8
+        heater: RpiRelay::new(&gpio, 15)?,   // Physical Pin 10
+        back: RpiButton::new(&gpio, 17)?,    // Physical Pin 11
+        confirm: RpiButton::new(&gpio, 27)?, // Physical Pin 13
+        right: RpiButton::new(&gpio, 10)?,   // Physical Pin 19
+        left: RpiButton::new(&gpio, 9)?,     // Physical Pin 21
+        sensors: config
+            .sensors
+            .iter()
+            .map(|spec| {
+                Ok(Sensor {
+                    name: spec.name.clone(),
+                    role: spec.role,
+                    driver: temp_sensor::build(spec, config.sht3x, |bcm_pin| {
+                        RpiIoPin::new(&gpio, bcm_pin)
+                    })?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?,
+    })
+}
+
+// Relay module on a GPIO pin
+// The SunFounder relays are active low, the relay is open on high
+#[derive(Debug)]
+pub struct RpiRelay {
+    pin: OutputPin,
+}
+
+impl RpiRelay {
+    // Starts with the relay open so nothing turns on at boot
+    pub fn new(gpio: &Gpio, bcm_pin: u8) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            pin: gpio.get(bcm_pin)?.into_output_high(),
+        })
+    }
+}
+
+impl Relay for RpiRelay {
+    fn on(&mut self) {
+        self.pin.set_low();
+    }
+
+    fn off(&mut self) {
+        self.pin.set_high();
+    }
+
+    fn is_on(&self) -> bool {
+        self.pin.is_set_low()
+    }
+}
+
+// The kernel reports a NACK as ENXIO or EREMOTEIO depending on the I2C driver
+const ENXIO: i32 = 6;
+const EREMOTEIO: i32 = 121;
+
+impl From<rppal::i2c::Error> for BusError {
+    fn from(e: rppal::i2c::Error) -> Self {
+        match &e {
+            rppal::i2c::Error::Io(io) if matches!(io.raw_os_error(), Some(ENXIO | EREMOTEIO)) => {
+                BusError::Nack
+            }
+            _ => BusError::Other(e.to_string()),
+        }
+    }
+}
+
+impl I2cBus for I2c {
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), BusError> {
+        I2c::set_slave_address(self, addr)?;
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), BusError> {
+        I2c::write(self, buf)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        I2c::read(self, buf)?;
+        Ok(())
+    }
+}
+
+// Pin for a single wire sensor, switches between output low and input with a pull-up
+#[derive(Debug)]
+pub struct RpiIoPin {
+    pin: gpio::IoPin,
+}
+
+impl RpiIoPin {
+    pub fn new(gpio: &Gpio, bcm_pin: u8) -> Result<Self, Box<dyn Error>> {
+        let mut pin = gpio.get(bcm_pin)?.into_io(Mode::Input);
+        // Most DHT22 boards have their own pull-up, this covers bare sensors
+        pin.set_bias(Bias::PullUp);
+        Ok(Self { pin })
+    }
+}
+
+impl IoPin for RpiIoPin {
+    fn set_low(&mut self) {
+        self.pin.set_low();
+        self.pin.set_mode(Mode::Output);
+    }
+
+    fn release(&mut self) {
+        self.pin.set_mode(Mode::Input);
+    }
+
+    fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+}
+
+// Button wired to a GPIO pin with a pulldown
+// The pin loses its callback when dropped
+#[derive(Debug)]
+pub struct RpiButton {
+    pin: InputPin,
+}
+
+impl RpiButton {
+    pub fn new(gpio: &Gpio, bcm_pin: u8) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            pin: gpio.get(bcm_pin)?.into_input_pulldown(),
+        })
+    }
+}
+
+impl Button for RpiButton {
+    fn on_press(&mut self, mut callback: Box<dyn FnMut() + Send>) -> Result<(), Box<dyn Error>> {
+        // 50ms debounce
+        self.pin.set_async_interrupt(
+            Trigger::FallingEdge,
+            Some(Duration::from_millis(50)),
+            move |_event| callback(),
+        )?;
+        Ok(())
+    }
+}
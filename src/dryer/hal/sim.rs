@@ -0,0 +1,812 @@
+mod bus;
+mod chamber;
+mod sensors;
+
+use chrono::{Local, NaiveTime};
+use std::{
+    cell::Cell,
+    error::Error,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::dryer::char_rom::CharRom;
+use crate::dryer::config::Config;
+use crate::dryer::dry_table::MAX_NAME;
+use crate::dryer::glyph::{DEGREE, LEFT, RIGHT};
+use crate::dryer::hal::{Button, Relay, TempHumSensor};
+use crate::dryer::pid::PidGains;
+use crate::dryer::temp_sensor::sht3x::{Repeatability, Sht3xMode};
+use crate::dryer::temp_sensor::{self, Role, SensorConfig};
+use crate::dryer::{Dryer, Hardware, Sensor};
+use bus::{FakeLcd, SimBus};
+use chamber::{Chamber, ChamberParams, Probe};
+use sensors::{FakeAht20, FakeBme280, FakeDhtPin, FakeSensor, FakeSht3x, FakeSht4x, SensorFaults};
+
+const AHT20_ADDR: u16 = 0x38;
+
+pub type SimHardware = Hardware<SimBus, SimRelay, SimButton, Box<dyn TempHumSensor<SimBus>>>;
+type SimDryer = Dryer<SimBus, SimRelay, SimButton, Box<dyn TempHumSensor<SimBus>>>;
+
+// Relay that just remembers its state so the chamber model can read it
+#[derive(Debug, Clone, Default)]
+pub struct SimRelay {
+    state: Arc<AtomicBool>,
+}
+
+impl Relay for SimRelay {
+    fn on(&mut self) {
+        self.state.store(true, Ordering::Relaxed);
+    }
+
+    fn off(&mut self) {
+        self.state.store(false, Ordering::Relaxed);
+    }
+
+    fn is_on(&self) -> bool {
+        self.state.load(Ordering::Relaxed)
+    }
+}
+
+type Callback = Box<dyn FnMut() + Send>;
+
+// Button pressed by the simulation script
+// Clones share the callback, so a copy can be kept after handing one to the dryer
+#[derive(Clone, Default)]
+pub struct SimButton {
+    callback: Arc<Mutex<Option<Callback>>>,
+}
+
+impl SimButton {
+    pub fn press(&self) {
+        if let Some(callback) = self.callback.lock().unwrap().as_mut() {
+            callback();
+        }
+    }
+}
+
+impl Button for SimButton {
+    fn on_press(&mut self, callback: Callback) -> Result<(), Box<dyn Error>> {
+        *self.callback.lock().unwrap() = Some(callback);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SimButton {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimButton").finish_non_exhaustive()
+    }
+}
+
+// Handles the simulation keeps on everything it gave the dryer
+struct Rig {
+    chamber: Arc<Mutex<Chamber>>,
+    lcd: Arc<Mutex<FakeLcd>>,
+    sensor_faults: Arc<Mutex<SensorFaults>>,
+    fan: SimRelay,
+    heater: SimRelay,
+    back: SimButton,
+    confirm: SimButton,
+    right: SimButton,
+    left: SimButton,
+}
+
+impl Rig {
+    fn new(params: ChamberParams) -> Self {
+        Rig {
+            chamber: Arc::new(Mutex::new(Chamber::new(params))),
+            lcd: Arc::new(Mutex::new(FakeLcd::default())),
+            sensor_faults: Arc::new(Mutex::new(SensorFaults::default())),
+            fan: SimRelay::default(),
+            heater: SimRelay::default(),
+            back: SimButton::default(),
+            confirm: SimButton::default(),
+            right: SimButton::default(),
+            left: SimButton::default(),
+        }
+    }
+
+    fn button(&self, name: &str) -> Option<&SimButton> {
+        match name {
+            "back" => Some(&self.back),
+            "confirm" => Some(&self.confirm),
+            "right" => Some(&self.right),
+            "left" => Some(&self.left),
+            _ => None,
+        }
+    }
+
+    // Hardware wired to the rig, called again after a power cut for a freshly booted set of parts
+    // on the same chamber
+    fn hardware(&self, config: &Config) -> Result<SimHardware, Box<dyn Error>> {
+        let chamber = &self.chamber;
+        let sensor_faults = &self.sensor_faults;
+
+        // The fake LCD is the one the config says is fitted
+        self.lcd.lock().unwrap().config = config.lcd;
+
+        // Fit a fake of whatever part the config asks for, the first control sensor goes by the
+        // heater and the rest of the chamber sensors across by the spool
+        let mut i2c = SimBus::new(chamber.clone(), self.lcd.clone(), sensor_faults.clone());
+        let mut sensors = Vec::new();
+        for spec in &config.sensors {
+            let probe = match spec.role {
+                Role::Control if sensors.is_empty() => Probe::Near,
+                Role::Control | Role::Spool => Probe::Far,
+                Role::Exhaust => Probe::Exhaust,
+                Role::Ambient => Probe::Ambient,
+            };
+            let (addr, part): (u16, Box<dyn FakeSensor>) = match spec.part {
+                SensorConfig::Sht3x { addr } => (addr, Box::new(FakeSht3x::default())),
+                SensorConfig::Sht4x { addr } => (addr, Box::new(FakeSht4x::default())),
+                SensorConfig::Aht20 => (AHT20_ADDR, Box::new(FakeAht20::default())),
+                SensorConfig::Bme280 { addr } => (addr, Box::new(FakeBme280::default())),
+                SensorConfig::Dht22 { .. } => {
+                    let name = spec.name.clone();
+                    let pin = FakeDhtPin::new(chamber.clone(), probe, name, sensor_faults.clone());
+                    sensors.push(Sensor {
+                        name: spec.name.clone(),
+                        role: spec.role,
+                        driver: temp_sensor::build(spec, config.sht3x, |_| Ok(pin))?,
+                    });
+                    continue;
+                }
+            };
+            i2c.add_sensor(addr, spec.mux, &spec.name, probe, part)?;
+            sensors.push(Sensor {
+                name: spec.name.clone(),
+                role: spec.role,
+                driver: temp_sensor::build::<_, FakeDhtPin>(spec, config.sht3x, |_| {
+                    Err("sim: only DHT22s go on a pin".into())
+                })?,
+            });
+        }
+
+        Ok(Hardware {
+            i2c,
+            fan: self.fan.clone(),
+            heater: self.heater.clone(),
+            back: self.back.clone(),
+            confirm: self.confirm.clone(),
+            right: self.right.clone(),
+            left: self.left.clone(),
+            sensors,
+        })
+    }
+}
+
+// Hardware failures the sim can throw at the dryer partway through a run
+// Sensors are picked by name
+#[derive(Debug, Clone)]
+enum Injection {
+    BrokenHeater,
+    DeadSensor(String),
+    FlakySensor(String),
+}
+
+impl Injection {
+    // what@minutes, e.g. heater@30, near@30 or flaky-near@30
+    fn parse(arg: &str) -> Result<(Injection, Duration), Box<dyn Error>> {
+        let (what, minutes) = arg
+            .split_once('@')
+            .ok_or_else(|| format!("expected what@minutes, got {arg}"))?;
+        let injection = match what {
+            "heater" => Injection::BrokenHeater,
+            _ => match what.strip_prefix("flaky-") {
+                Some(name) => Injection::FlakySensor(String::from(name)),
+                None => Injection::DeadSensor(String::from(what)),
+            },
+        };
+        let at = Duration::from_secs_f32(minutes.parse::<f32>()? * 60.0);
+        Ok((injection, at))
+    }
+
+    // Sensor the injection is aimed at, if any
+    fn sensor(&self) -> Option<&str> {
+        match self {
+            Injection::BrokenHeater => None,
+            Injection::DeadSensor(name) | Injection::FlakySensor(name) => Some(name),
+        }
+    }
+
+    fn apply(&self, rig: &Rig) {
+        println!("sim: injecting {self:?}");
+        let mut faults = rig.sensor_faults.lock().unwrap();
+        match self {
+            Injection::BrokenHeater => rig.chamber.lock().unwrap().break_heater(),
+            Injection::DeadSensor(name) => faults.dead.push(name.clone()),
+            Injection::FlakySensor(name) => faults.flaky.push(name.clone()),
+        }
+    }
+}
+
+// A sensor at the usual address for its spot, the near one on the low address
+fn sensor_kind(kind: &str, probe: Probe) -> Result<SensorConfig, Box<dyn Error>> {
+    let near = probe == Probe::Near;
+    Ok(match kind {
+        "sht3x" => SensorConfig::Sht3x {
+            addr: if near { 0x44 } else { 0x45 },
+        },
+        "sht4x" => SensorConfig::Sht4x {
+            addr: if near { 0x44 } else { 0x45 },
+        },
+        "aht20" => SensorConfig::Aht20,
+        "bme280" => SensorConfig::Bme280 {
+            addr: if near { 0x76 } else { 0x77 },
+        },
+        "dht22" => SensorConfig::Dht22 {
+            pin: if near { 4 } else { 22 },
+        },
+        _ => return Err(format!("unknown sensor {kind}").into()),
+    })
+}
+
+#[derive(Debug)]
+struct SimOptions {
+    material: String,
+    hours: f32,
+    // Simulated seconds per real second, 0 runs flat out
+    speed: f32,
+    step: Duration,
+    log_every: Duration,
+    chamber: ChamberParams,
+    config: Config,
+    // Gains given on the command line win over ones saved by a simulated autotune
+    kp: Option<f32>,
+    ki: Option<f32>,
+    kd: Option<f32>,
+    injections: Vec<(Injection, Duration)>,
+    // Buttons pressed in order partway through the run, e.g. back,confirm,confirm@30
+    presses: Vec<(Vec<String>, Duration)>,
+    // Every sensor starts with water condensed on it
+    condensed: bool,
+    // name:temp:minutes, made in the editor before the run starts
+    new_profile: Option<String>,
+    // When the power goes partway through the run and for how long
+    power_cut: Option<(Duration, Duration)>,
+    // Local time the simulated wall clock starts at, the real time if not given
+    clock: Option<NaiveTime>,
+    // Picked on the start screen instead of starting now
+    start_at: Option<NaiveTime>,
+    finish_by: Option<NaiveTime>,
+    // Minutes the wall clock jumps by and when, like NTP syncing after boot
+    clock_jumps: Vec<(i64, Duration)>,
+}
+
+impl SimOptions {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        // A config file is the starting point for everything else, wherever it is given
+        let config = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or("missing value for --config")?;
+                Config::load(path.as_ref())?
+            }
+            None => Config::default(),
+        };
+
+        let mut options = SimOptions {
+            material: String::from("PLA"),
+            hours: 8.0,
+            speed: 0.0,
+            step: Duration::from_secs(1),
+            log_every: Duration::from_secs(60),
+            chamber: ChamberParams::default(),
+            // Keep simulated autotune results away from the real ones
+            config: Config {
+                state_dir: std::env::temp_dir().join("pi_dry_sim"),
+                ..config
+            },
+            kp: None,
+            ki: None,
+            kd: None,
+            injections: Vec::new(),
+            presses: Vec::new(),
+            condensed: false,
+            new_profile: None,
+            power_cut: None,
+            clock: None,
+            start_at: None,
+            finish_by: None,
+            clock_jumps: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--material" => options.material = value()?.clone(),
+                "--hours" => options.hours = value()?.parse()?,
+                "--speed" => options.speed = value()?.parse()?,
+                "--log-every" => {
+                    options.log_every = Duration::from_secs(value()?.parse()?);
+                }
+                "--ambient" => options.chamber.ambient_temp = value()?.parse()?,
+                "--ambient-rh" => options.chamber.ambient_hum = value()?.parse()?,
+                "--water" => options.chamber.filament_water = value()?.parse()?,
+                "--kp" => options.kp = Some(value()?.parse()?),
+                "--ki" => options.ki = Some(value()?.parse()?),
+                "--kd" => options.kd = Some(value()?.parse()?),
+                "--tune-at" => options.config.autotune.setpoint = value()?.parse()?,
+                "--state-dir" => options.config.state_dir = value()?.into(),
+                "--profiles" => options.config.profiles = Some(value()?.into()),
+                "--inject" => options.injections.push(Injection::parse(value()?)?),
+                "--press" => {
+                    let arg = value()?;
+                    let (buttons, minutes) = arg
+                        .split_once('@')
+                        .ok_or_else(|| format!("expected buttons@minutes, got {arg}"))?;
+                    let at = Duration::from_secs_f32(minutes.parse::<f32>()? * 60.0);
+                    let buttons = buttons.split(',').map(String::from).collect();
+                    options.presses.push((buttons, at));
+                }
+                "--sht3x" => {
+                    options.config.sht3x.mode = Sht3xMode::try_from(value()?.clone())?;
+                }
+                "--repeatability" => {
+                    options.config.sht3x.repeatability = match value()?.as_str() {
+                        "low" => Repeatability::Low,
+                        "medium" => Repeatability::Medium,
+                        "high" => Repeatability::High,
+                        r => return Err(format!("unknown repeatability {r}").into()),
+                    }
+                }
+                "--condensed" => options.condensed = true,
+                "--keep-dry" => options.config.storage.enabled = true,
+                "--chamber-max" => options.config.max_chamber_temp = value()?.parse()?,
+                "--lcd-rom" => {
+                    options.config.lcd.rom = match value()?.as_str() {
+                        "a00" => CharRom::A00,
+                        "a02" => CharRom::A02,
+                        rom => return Err(format!("unknown LCD ROM {rom}").into()),
+                    }
+                }
+                "--lcd" => {
+                    let arg = value()?;
+                    let (cols, rows) = arg
+                        .split_once('x')
+                        .ok_or_else(|| format!("expected colsxrows, got {arg}"))?;
+                    options.config.lcd.cols = cols.parse()?;
+                    options.config.lcd.rows = rows.parse()?;
+                }
+                "--lcd-addr" => {
+                    let arg = value()?;
+                    let hex = arg.trim_start_matches("0x");
+                    options.config.lcd.addr = u16::from_str_radix(hex, 16)?;
+                }
+                "--backlight-timeout" => {
+                    options.config.backlight.timeout_secs = value()?.parse()?;
+                }
+                "--backlight-flash" => options.config.backlight.flash = true,
+                "--new-profile" => options.new_profile = Some(value()?.clone()),
+                "--power-cut" => {
+                    let arg = value()?;
+                    let (at, down) = arg
+                        .split_once(':')
+                        .ok_or_else(|| format!("expected at:minutes, got {arg}"))?;
+                    let minutes = |m: &str| -> Result<Duration, Box<dyn Error>> {
+                        Ok(Duration::from_secs_f32(m.parse::<f32>()? * 60.0))
+                    };
+                    options.power_cut = Some((minutes(at)?, minutes(down)?));
+                }
+                "--clock" => options.clock = Some(NaiveTime::parse_from_str(value()?, "%H:%M")?),
+                "--start-at" => {
+                    options.start_at = Some(NaiveTime::parse_from_str(value()?, "%H:%M")?);
+                }
+                "--finish-by" => {
+                    options.finish_by = Some(NaiveTime::parse_from_str(value()?, "%H:%M")?);
+                }
+                "--clock-jump" => {
+                    let arg = value()?;
+                    let (jump, minutes) = arg
+                        .split_once('@')
+                        .ok_or_else(|| format!("expected minutes@minutes, got {arg}"))?;
+                    let at = Duration::from_secs_f32(minutes.parse::<f32>()? * 60.0);
+                    options.clock_jumps.push((jump.parse()?, at));
+                }
+                "--config" => {
+                    value()?;
+                }
+                "--near" => options.set_part("near", sensor_kind(value()?, Probe::Near)?)?,
+                "--far" => options.set_part("far", sensor_kind(value()?, Probe::Far)?)?,
+                _ => return Err(format!("unknown sim option {arg}").into()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    // Swaps the part fitted for the sensor with this name
+    fn set_part(&mut self, name: &str, part: SensorConfig) -> Result<(), Box<dyn Error>> {
+        let spec = self
+            .config
+            .sensors
+            .iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| format!("no sensor called {name} in the config"))?;
+        spec.part = part;
+        Ok(())
+    }
+}
+
+// Starts the dryer on the rig, as at power on
+fn boot(rig: &Rig, options: &SimOptions) -> Result<SimDryer, Box<dyn Error>> {
+    let mut dryer = Dryer::new(rig.hardware(&options.config)?, options.config.clone())?;
+    dryer.set_debug(false);
+
+    let gains = dryer.pid_config().gains();
+    dryer.set_pid_gains(PidGains {
+        kp: options.kp.unwrap_or(gains.kp),
+        ki: options.ki.unwrap_or(gains.ki),
+        kd: options.kd.unwrap_or(gains.kd),
+    });
+    Ok(dryer)
+}
+
+// Steps through the start screen the material just picked opened, if it opened one
+// press has to press the button and step the dryer so the screen catches up
+fn pick_start(
+    rig: &Rig,
+    options: &SimOptions,
+    mut press: impl FnMut(&SimButton),
+) -> Result<(), Box<dyn Error>> {
+    let screen = |row| rig.lcd.lock().unwrap().line(row);
+    if !screen(1).contains("Start now") {
+        return Ok(());
+    }
+    let (rights, time) = match (options.start_at, options.finish_by) {
+        (Some(time), _) => (1, time),
+        (None, Some(time)) => (2, time),
+        (None, None) => {
+            press(&rig.confirm);
+            return Ok(());
+        }
+    };
+    for _ in 0..rights {
+        press(&rig.right);
+    }
+    press(&rig.confirm);
+
+    // Quarter hours round the clock until it shows the time asked for
+    let shown = format!("{LEFT}{}{RIGHT}", time.format("%H:%M"));
+    for _ in 0..96 {
+        if screen(1).contains(&shown) {
+            break;
+        }
+        press(&rig.right);
+    }
+    if !screen(1).contains(&shown) {
+        return Err(
+            format!("sim: start screen can't get to {shown}, it goes in quarter hours").into(),
+        );
+    }
+    println!(
+        "sim: start screen {}|{}",
+        screen(0).trim_end(),
+        screen(1).trim_end()
+    );
+    press(&rig.confirm);
+    Ok(())
+}
+
+// Goes through the profile editor with the buttons, copying the first profile
+// press has to press the button and step the dryer so the screen catches up
+fn make_profile(
+    rig: &Rig,
+    profile: &str,
+    mut press: impl FnMut(&SimButton),
+) -> Result<(), Box<dyn Error>> {
+    let mut parts = profile.rsplitn(3, ':');
+    let (Some(minutes), Some(temp), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("expected name:temp:minutes, got {profile}").into());
+    };
+    let temp: u32 = temp.parse()?;
+    let minutes: u64 = minutes.parse()?;
+    let screen = |row| rig.lcd.lock().unwrap().line(row);
+
+    // EDIT is just before IDLE on the menu
+    press(&rig.back);
+    press(&rig.left);
+    press(&rig.confirm);
+    press(&rig.confirm);
+
+    // Turn the knob until each letter comes up, then the end marker
+    let end = (name.chars().count() < MAX_NAME).then_some(RIGHT);
+    for (i, c) in name.chars().chain(end).enumerate() {
+        for _ in 0..128 {
+            if screen(0).chars().nth(i) == Some(c) {
+                break;
+            }
+            press(&rig.right);
+        }
+        if screen(0).chars().nth(i) != Some(c) {
+            return Err(format!("sim: editor can't type {c}").into());
+        }
+        press(&rig.confirm);
+    }
+
+    // Temperature then time, nudged until the screen shows the one asked for
+    // Times are shown as 6h00m, both come out as plain numbers here
+    let value = || -> Option<u64> {
+        let line = screen(1);
+        let shown = line
+            .split([LEFT, RIGHT])
+            .nth(1)?
+            .trim()
+            .trim_end_matches(['C', 'm', DEGREE]);
+        match shown.split_once('h') {
+            Some((hours, minutes)) => {
+                Some(hours.parse::<u64>().ok()? * 60 + minutes.parse::<u64>().ok()?)
+            }
+            None => shown.parse().ok(),
+        }
+    };
+    for target in [temp as u64, minutes] {
+        for _ in 0..500 {
+            match value() {
+                Some(shown) if shown < target => press(&rig.right),
+                Some(shown) if shown > target => press(&rig.left),
+                _ => break,
+            }
+        }
+        if value() != Some(target) {
+            return Err(format!("sim: editor can't get to {target}").into());
+        }
+        press(&rig.confirm);
+    }
+
+    // On the save screen, confirm saves it
+    press(&rig.confirm);
+    println!("sim: profile editor {}", screen(0).trim_end());
+    Ok(())
+}
+
+// Runs the real control loop against the chamber model
+// pi_dry --sim [--material PLA] [--hours 8] [--speed 0] [--log-every 60]
+//              [--ambient 22] [--ambient-rh 55] [--water 5] [--kp 0.3] [--ki 0.0004] [--kd 4]
+//              [--tune-at 60] [--state-dir /tmp/pi_dry_sim] [--profiles profiles.toml]
+//              [--inject heater|<sensor name>|flaky-<sensor name>@minutes]...
+//              [--sht3x single|periodic-0.5|periodic-1|...|periodic-10]
+//              [--repeatability low|medium|high] [--condensed]
+//              [--near sht3x|sht4x|aht20|bme280|dht22] [--far ...] [--config pi_dry.toml]
+//              [--new-profile name:temp:minutes] [--keep-dry] [--chamber-max 85]
+//              [--press back|confirm|right|left,...@minutes]... [--power-cut at:minutes]
+//              [--clock 20:00] [--start-at 23:00] [--finish-by 06:30]
+//              [--clock-jump minutes@minutes]...
+// --material TUNE runs the autotune, the gains it saves are used by later sim runs
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = SimOptions::parse(args)?;
+    let rig = Rig::new(options.chamber);
+    let names: Vec<String> = options
+        .config
+        .sensors
+        .iter()
+        .map(|spec| spec.name.clone())
+        .collect();
+    for (injection, _) in &options.injections {
+        if let Some(name) = injection.sensor()
+            && !names.iter().any(|n| n == name)
+        {
+            return Err(format!("sim: no sensor called {name} to inject into").into());
+        }
+    }
+    for (buttons, _) in &options.presses {
+        if let Some(button) = buttons.iter().find(|b| rig.button(b).is_none()) {
+            return Err(
+                format!("sim: no {button} button, use back, confirm, right or left").into(),
+            );
+        }
+    }
+    if options.condensed {
+        let mut faults = rig.sensor_faults.lock().unwrap();
+        faults.condensed.extend(names.iter().cloned());
+    }
+
+    // Every sim run is a freshly plugged in box, a run left over from the last one isn't picked up
+    let checkpoint = options.config.checkpoint_path();
+    if checkpoint.exists() {
+        std::fs::remove_file(&checkpoint)?;
+    }
+
+    let mut dryer = boot(&rig, &options)?;
+
+    let mut now = Instant::now();
+    let mut peak_temp = f32::MIN;
+
+    // The wall clock moves with the simulated time, plus whatever it has jumped by
+    let wall_start = match options.clock {
+        Some(time) => Local::now()
+            .date_naive()
+            .and_time(time)
+            .and_local_timezone(Local)
+            .earliest()
+            .ok_or("sim: --clock is skipped by the clocks changing today")?
+            .into(),
+        None => SystemTime::now(),
+    };
+    let clock_start = now;
+    let jumped = Cell::new(0i64);
+    let wall = |now: Instant| {
+        let wall = wall_start + (now - clock_start);
+        match jumped.get() {
+            j if j >= 0 => wall + Duration::from_secs(j as u64),
+            j => wall - Duration::from_secs(j.unsigned_abs()),
+        }
+    };
+
+    let mut step = |dryer: &mut SimDryer, now: &mut Instant| {
+        {
+            let mut chamber = rig.chamber.lock().unwrap();
+            chamber.step(
+                options.step.as_secs_f32(),
+                rig.heater.is_on(),
+                rig.fan.is_on(),
+            );
+            peak_temp = peak_temp.max(chamber.air_temp());
+        }
+        *now += options.step;
+        dryer.sync_clock(*now, wall(*now));
+        if let Err(e) = dryer.update(*now) {
+            println!("sim: update failed: {e}");
+        }
+        if options.speed > 0.0 {
+            thread::sleep(options.step.div_f32(options.speed));
+        }
+    };
+
+    if let Some(profile) = &options.new_profile {
+        make_profile(&rig, profile, |button| {
+            button.press();
+            step(&mut dryer, &mut now);
+        })?;
+    }
+
+    // Editing takes simulated time too, the run is timed from here
+    let start = now;
+    let mut next_log = start;
+    let end = start + Duration::from_secs_f32(options.hours * 3600.0);
+
+    // Pick the material from the menu the same way a person would
+    rig.back.press();
+    step(&mut dryer, &mut now);
+    let mut found = false;
+    for _ in 0..32 {
+        let hovered = rig.lcd.lock().unwrap().line(0);
+        if hovered
+            .trim_matches([LEFT, RIGHT, ' '])
+            .eq_ignore_ascii_case(&options.material)
+        {
+            found = true;
+            break;
+        }
+        // The screen keeps up with the knob without waiting for an update
+        rig.right.press();
+        dryer.refresh(now)?;
+    }
+    if !found {
+        return Err(format!("sim: material {} is not in the menu", options.material).into());
+    }
+    rig.confirm.press();
+    step(&mut dryer, &mut now);
+    pick_start(&rig, &options, |button| {
+        button.press();
+        step(&mut dryer, &mut now);
+    })?;
+
+    let pid = dryer.pid_config();
+    println!("sim: kp {} ki {} kd {}", pid.kp, pid.ki, pid.kd);
+    let columns: String = names
+        .iter()
+        .map(|name| format!("{name}_c,{name}_rh,"))
+        .collect();
+    println!("time,air_c,element_c,{columns}water_g,heater,fan,light,lcd");
+    let mut injections = options.injections.clone();
+    let mut presses = options.presses.clone();
+    let mut power_cut = options.power_cut;
+    let mut clock_jumps = options.clock_jumps.clone();
+    while now < end {
+        step(&mut dryer, &mut now);
+
+        // Everything goes off, the chamber drifts for a while, then the dryer boots again
+        if let Some((at, down)) = power_cut
+            && now - start >= at
+        {
+            power_cut = None;
+            println!("sim: power off for {}m", down.as_secs() / 60);
+            drop(dryer);
+            rig.heater.clone().off();
+            rig.fan.clone().off();
+            let mut off = Duration::ZERO;
+            while off < down {
+                rig.chamber
+                    .lock()
+                    .unwrap()
+                    .step(options.step.as_secs_f32(), false, false);
+                off += options.step;
+            }
+            now += down;
+            next_log = now;
+            println!("sim: power back");
+            dryer = boot(&rig, &options)?;
+        }
+
+        injections.retain(|(injection, at)| {
+            if now - start < *at {
+                return true;
+            }
+            injection.apply(&rig);
+            false
+        });
+
+        clock_jumps.retain(|(minutes, at)| {
+            if now - start < *at {
+                return true;
+            }
+            println!("sim: clock jumps {minutes:+}m");
+            jumped.set(jumped.get() + minutes * 60);
+            false
+        });
+
+        presses.retain(|(buttons, at)| {
+            if now - start < *at {
+                return true;
+            }
+            println!("sim: pressing {}", buttons.join(","));
+            for button in buttons.iter().filter_map(|b| rig.button(b)) {
+                button.press();
+            }
+            false
+        });
+
+        if now >= next_log {
+            next_log += options.log_every;
+            let chamber = rig.chamber.lock().unwrap();
+            let lcd = rig.lcd.lock().unwrap();
+            // What the dryer last read, blank for a sensor whose read failed
+            let readings: String = dryer
+                .sensor_readings()
+                .map(|(_, reading)| match reading {
+                    Some(reading) => format!("{:.2},{:.2},", reading.temp, reading.hum),
+                    None => String::from(",,"),
+                })
+                .collect();
+            let (fan, heater) = dryer.outputs();
+            let elapsed = (now - start).as_secs();
+            println!(
+                "{}:{:02}:{:02},{:.2},{:.2},{readings}{:.3},{},{},{},{}",
+                elapsed / 3600,
+                (elapsed % 3600) / 60,
+                elapsed % 60,
+                chamber.air_temp(),
+                chamber.element_temp(),
+                chamber.filament_water(),
+                heater as u8,
+                fan as u8,
+                lcd.backlight() as u8,
+                lcd.screen(),
+            );
+        }
+    }
+
+    let chamber = rig.chamber.lock().unwrap();
+    println!(
+        "sim: done after {:.1}h, peak air {:.2}C, final RH {:.1}%, {:.2}g of {:.2}g water left",
+        options.hours,
+        peak_temp,
+        chamber.air_hum(),
+        chamber.filament_water(),
+        options.chamber.filament_water,
+    );
+    println!(
+        "sim: {} bytes sent to the LCD",
+        rig.lcd.lock().unwrap().bytes()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::dryer::glyph;
+use crate::dryer::hal::sim::chamber::{Chamber, Probe};
+use crate::dryer::hal::sim::sensors::{FakeSensor, SensorFaults};
+use crate::dryer::hal::{BusError, I2cBus};
+use crate::dryer::lcd_interface::LcdConfig;
+use crate::dryer::temp_sensor::tca9548a::MuxChannel;
+
+const GENERAL_CALL_ADDR: u16 = 0x00;
+const GENERAL_CALL_RESET: u8 = 0x06;
+
+// A fake sensor on the bus and the spot in the chamber it measures
+#[derive(Debug)]
+struct Device {
+    addr: u16,
+    // Only on the bus while its mux channel is switched in
+    mux: Option<MuxChannel>,
+    name: String,
+    probe: Probe,
+    part: Box<dyn FakeSensor>,
+    reads: u32,
+}
+
+// I2C bus with the devices from the real build hanging off it
+// Sensors read from the chamber model, the LCD is decoded so the screen can be printed
+#[derive(Debug)]
+pub struct SimBus {
+    chamber: Arc<Mutex<Chamber>>,
+    addr: u16,
+    devices: Vec<Device>,
+    // TCA9548A muxes by address, with the channels each has switched in
+    muxes: Vec<(u16, u8)>,
+    lcd: Arc<Mutex<FakeLcd>>,
+    // Where the LCD backpack answers
+    lcd_addr: u16,
+    faults: Arc<Mutex<SensorFaults>>,
+    // Time spent in delays, added to the chamber clock so the sensors see time pass during init
+    delayed: f32,
+}
+
+impl SimBus {
+    pub fn new(
+        chamber: Arc<Mutex<Chamber>>,
+        lcd: Arc<Mutex<FakeLcd>>,
+        faults: Arc<Mutex<SensorFaults>>,
+    ) -> Self {
+        let lcd_addr = lcd.lock().unwrap().config.addr;
+        Self {
+            chamber,
+            addr: 0,
+            devices: Vec::new(),
+            muxes: Vec::new(),
+            lcd,
+            lcd_addr,
+            faults,
+            delayed: 0.0,
+        }
+    }
+
+    // Adds a sensor, and the mux it sits behind if that isn't on the bus yet
+    pub fn add_sensor(
+        &mut self,
+        addr: u16,
+        mux: Option<MuxChannel>,
+        name: &str,
+        probe: Probe,
+        part: Box<dyn FakeSensor>,
+    ) -> Result<(), String> {
+        let taken = self
+            .devices
+            .iter()
+            .any(|device| device.addr == addr && device.mux == mux);
+        if taken || (addr == self.lcd_addr && mux.is_none()) {
+            return Err(format!("sim: two devices at {addr:#04x}"));
+        }
+        if let Some(mux) = mux
+            && !self.muxes.iter().any(|(addr, _)| *addr == mux.addr)
+        {
+            self.muxes.push((mux.addr, 0));
+        }
+        self.devices.push(Device {
+            addr,
+            mux,
+            name: String::from(name),
+            probe,
+            part,
+            reads: 0,
+        });
+        Ok(())
+    }
+
+    // Everything that would see a transaction right now
+    fn connected(&mut self) -> impl Iterator<Item = &mut Device> {
+        let muxes = &self.muxes;
+        self.devices
+            .iter_mut()
+            .filter(move |device| match device.mux {
+                None => true,
+                Some(mux) => muxes.iter().any(|(addr, channels)| {
+                    *addr == mux.addr && channels & (1 << mux.channel) != 0
+                }),
+            })
+    }
+
+    // The addressed sensor, or NACK if nothing answers at the address
+    fn device(&mut self) -> Result<&mut Device, BusError> {
+        let addr = self.addr;
+        let faults = self.faults.clone();
+        let mut devices = self.connected().filter(|device| device.addr == addr);
+        let device = devices.next().ok_or(BusError::Nack)?;
+        // Both would drive the bus at once and garble each other
+        if devices.next().is_some() {
+            return Err(BusError::Other(format!(
+                "sim: two devices answered at {addr:#04x}"
+            )));
+        }
+        if faults.lock().unwrap().dead.contains(&device.name) {
+            return Err(BusError::Nack);
+        }
+        Ok(device)
+    }
+
+    fn mux(&mut self) -> Option<&mut u8> {
+        self.muxes
+            .iter_mut()
+            .find(|(addr, _)| *addr == self.addr)
+            .map(|(_, channels)| channels)
+    }
+}
+
+impl I2cBus for SimBus {
+    fn set_slave_address(&mut self, addr: u16) -> Result<(), BusError> {
+        self.addr = addr;
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), BusError> {
+        match self.addr {
+            addr if addr == self.lcd_addr => {
+                for byte in buf {
+                    self.lcd.lock().unwrap().write(*byte);
+                }
+                return Ok(());
+            }
+            GENERAL_CALL_ADDR => {
+                if buf == [GENERAL_CALL_RESET] {
+                    for device in self.connected() {
+                        device.part.general_call_reset();
+                    }
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        if let Some(channels) = self.mux() {
+            *channels = *buf.last().ok_or(BusError::Nack)?;
+            return Ok(());
+        }
+
+        let chamber = self.chamber.clone();
+        let faults = self.faults.clone();
+        let now = chamber.lock().unwrap().elapsed() + self.delayed;
+        let device = self.device()?;
+
+        // Condensation gets picked up on the next command
+        let mut faults = faults.lock().unwrap();
+        if faults.condensed.contains(&device.name) {
+            faults.condensed.retain(|name| *name != device.name);
+            device.part.condense();
+        }
+
+        let sensor = chamber.lock().unwrap().probe(device.probe);
+        device.part.write(buf, sensor, now)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        if let Some(channels) = self.mux() {
+            buf.fill(*channels);
+            return Ok(());
+        }
+        let faults = self.faults.clone();
+        let device = self.device()?;
+        device.part.read(buf)?;
+
+        // Flip a bit in every other reply
+        device.reads += 1;
+        if faults.lock().unwrap().flaky.contains(&device.name) && device.reads.is_multiple_of(2) {
+            buf[0] ^= 0x10;
+        }
+        Ok(())
+    }
+
+    // Simulated devices are always ready, but the time still counts for the sensor heaters
+    fn delay(&mut self, duration: Duration) {
+        self.delayed += duration.as_secs_f32();
+    }
+}
+
+// HD44780 behind a PCF8574 in 4-bit mode
+// Only tracks what is needed to know what text is on screen
+#[derive(Debug)]
+pub struct FakeLcd {
+    ddram: [u8; 0x80],
+    // 8 user characters of 8 rows each
+    cgram: [u8; 64],
+    addr: usize,
+    // Data goes to CGRAM after a set CGRAM address, until the next set DDRAM address
+    in_cgram: bool,
+    last: u8,
+    high_nibble: Option<u8>,
+    // The first 4 nibbles from init come in before 4-bit mode is set
+    init_nibbles: u8,
+    // Commands and characters, to see what redrawing costs
+    bytes: usize,
+    // The display the config says is fitted, for its address, wiring, size and ROM
+    pub config: LcdConfig,
+}
+
+impl Default for FakeLcd {
+    fn default() -> Self {
+        Self {
+            ddram: [b' '; 0x80],
+            cgram: [0; 64],
+            addr: 0,
+            in_cgram: false,
+            last: 0,
+            high_nibble: None,
+            init_nibbles: 0,
+            bytes: 0,
+            config: LcdConfig::default(),
+        }
+    }
+}
+
+impl FakeLcd {
+    // One byte from the PCF8574, with the lines on whichever outputs the config says
+    fn write(&mut self, byte: u8) {
+        let pins = self.config.pins;
+        let pin = |b: u8, pin: u8| b & (1 << pin) != 0;
+
+        // Data is latched on the falling edge of enable
+        if pin(self.last, pins.en) && !pin(byte, pins.en) {
+            let nibble = [pins.d4, pins.d5, pins.d6, pins.d7]
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| pin(byte, **d))
+                .fold(0, |nibble, (bit, _)| nibble | (1 << bit));
+            self.nibble(nibble, pin(byte, pins.rs));
+        }
+        self.last = byte;
+    }
+
+    fn nibble(&mut self, nibble: u8, rs: bool) {
+        if self.init_nibbles < 4 {
+            self.init_nibbles += 1;
+            return;
+        }
+
+        match self.high_nibble.take() {
+            None => self.high_nibble = Some(nibble),
+            Some(high) => self.byte((high << 4) | nibble, rs),
+        }
+    }
+
+    fn byte(&mut self, byte: u8, rs: bool) {
+        self.bytes += 1;
+        if rs && self.in_cgram {
+            self.cgram[self.addr] = byte & 0x1F;
+            self.addr = (self.addr + 1) % self.cgram.len();
+        } else if rs {
+            self.ddram[self.addr] = byte;
+            self.addr = (self.addr + 1) % self.ddram.len();
+        } else if byte & 0x80 != 0 {
+            // Set DDRAM address
+            self.addr = (byte & 0x7F) as usize;
+            self.in_cgram = false;
+        } else if byte & 0x40 != 0 {
+            // Set CGRAM address
+            self.addr = (byte & 0x3F) as usize;
+            self.in_cgram = true;
+        } else if byte == 0x01 {
+            // Clear
+            self.ddram = [b' '; 0x80];
+            self.addr = 0;
+            self.in_cgram = false;
+        } else if byte & 0xFE == 0x02 {
+            // Home
+            self.addr = 0;
+            self.in_cgram = false;
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // The BL pin as of the last byte, every byte carries it
+    pub fn backlight(&self) -> bool {
+        self.last & (1 << self.config.pins.bl) != 0
+    }
+
+    // Every row, split by |
+    pub fn screen(&self) -> String {
+        (0..self.config.rows as usize)
+            .map(|row| self.line(row).trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    // Text shown on a row
+    pub fn line(&self, row: usize) -> String {
+        let cols = self.config.cols as usize;
+        // Rows 3 and 4 carry on from the ends of rows 1 and 2
+        let start = [0x00, 0x40, cols, 0x40 + cols][row];
+        self.ddram[start..start + cols]
+            .iter()
+            .map(|&code| match code {
+                // User characters show as the glyph whose bitmap is in CGRAM, ? if none is
+                0..8 => {
+                    let rows = &self.cgram[code as usize * 8..][..8];
+                    glyph::glyphs(self.config.rom)
+                        .iter()
+                        .find(|(_, bitmap)| bitmap == rows)
+                        .map_or('?', |(c, _)| *c)
+                }
+                code => self.config.rom.char(code).unwrap_or(' '),
+            })
+            .collect()
+    }
+}
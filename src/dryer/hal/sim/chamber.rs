@@ -0,0 +1,216 @@
+// Lumped thermal and moisture model of the drying chamber
+// Numbers are rough fits for the 120W PTC heater in a ~40L storage box, good enough to
+// see overshoot and how the controller reacts, not to predict exact drying times
+
+// PTC heater
+const HEATER_MAX_POWER: f32 = 120.0; // W, cold element
+const HEATER_CURIE_TEMP: f32 = 150.0; // C, power falls off hard around here
+const HEATER_CURIE_WIDTH: f32 = 10.0; // C
+const ELEMENT_HEAT_CAPACITY: f32 = 120.0; // J/K, aluminium fins
+
+// Element to chamber air, the fan makes a huge difference
+const ELEMENT_TO_AIR_FAN_ON: f32 = 4.0; // W/K
+const ELEMENT_TO_AIR_FAN_OFF: f32 = 0.6; // W/K
+
+// Air, spool and walls lumped together
+const CHAMBER_HEAT_CAPACITY: f32 = 2500.0; // J/K
+const CHAMBER_LOSS: f32 = 1.1; // W/K through the walls
+const CHAMBER_LOSS_FAN: f32 = 0.3; // W/K extra through the vents with the fan running
+
+// Moisture
+const CHAMBER_VOLUME: f32 = 0.04; // m^3
+const AIR_EXCHANGE_FAN_ON: f32 = 6.0 / 3600.0; // chamber volumes per second
+const AIR_EXCHANGE_FAN_OFF: f32 = 0.5 / 3600.0;
+const DESORPTION_RATE_45C: f32 = 4.6e-5; // fraction of filament water released per second at 45C
+const DESORPTION_E_FOLD: f32 = 15.0; // C for an e-fold increase in release rate
+
+// Sensors lag behind the air
+const SENSOR_TIME_CONSTANT: f32 = 15.0; // s
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChamberParams {
+    pub ambient_temp: f32,
+    pub ambient_hum: f32,
+    // Grams of water in the loaded filament, ~5g is a wet 1kg spool
+    pub filament_water: f32,
+}
+
+impl Default for ChamberParams {
+    fn default() -> Self {
+        Self {
+            ambient_temp: 22.0,
+            ambient_hum: 55.0,
+            filament_water: 5.0,
+        }
+    }
+}
+
+// Where a sensor sits in the chamber
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Probe {
+    // In the air coming off the heater
+    Near,
+    // Across the chamber by the spool
+    Far,
+    // In the vent, sees the air as it leaves
+    Exhaust,
+    // Outside the box
+    Ambient,
+}
+
+// A sensor somewhere in the chamber
+// element_coupling is how much of the element temperature it picks up, near the heater sees more
+#[derive(Debug, Clone, Copy)]
+pub struct SimSensor {
+    pub temp: f32,
+    pub hum: f32,
+    element_coupling: f32,
+}
+
+#[derive(Debug)]
+pub struct Chamber {
+    params: ChamberParams,
+    element_temp: f32,
+    air_temp: f32,
+    water_vapor: f32, // g/m^3
+    filament_water: f32,
+    near: SimSensor,
+    far: SimSensor,
+    exhaust: SimSensor,
+    // Element gone open circuit, the relay clicks but nothing heats
+    heater_broken: bool,
+    // Simulated seconds since the start
+    elapsed: f32,
+}
+
+impl Chamber {
+    pub fn new(params: ChamberParams) -> Self {
+        let vapor = params.ambient_hum / 100.0 * saturation_vapor(params.ambient_temp);
+        let sensor = |element_coupling| SimSensor {
+            temp: params.ambient_temp,
+            hum: params.ambient_hum,
+            element_coupling,
+        };
+
+        Self {
+            params,
+            element_temp: params.ambient_temp,
+            air_temp: params.ambient_temp,
+            water_vapor: vapor,
+            filament_water: params.filament_water,
+            near: sensor(0.03),
+            far: sensor(0.0),
+            exhaust: sensor(0.0),
+            heater_broken: false,
+            elapsed: 0.0,
+        }
+    }
+
+    // Advances the model by dt seconds
+    // Steps longer than a couple seconds get split up to keep the integration stable
+    pub fn step(&mut self, dt: f32, heater_on: bool, fan_on: bool) {
+        let substeps = (dt / 0.5).ceil().max(1.0) as u32;
+        let h = dt / substeps as f32;
+        for _ in 0..substeps {
+            self.substep(h, heater_on, fan_on);
+        }
+        self.elapsed += dt;
+    }
+
+    fn substep(&mut self, dt: f32, heater_on: bool, fan_on: bool) {
+        let ambient = self.params.ambient_temp;
+
+        // Heat
+        let heater_power = if heater_on && !self.heater_broken {
+            HEATER_MAX_POWER
+                / (1.0 + ((self.element_temp - HEATER_CURIE_TEMP) / HEATER_CURIE_WIDTH).exp())
+        } else {
+            0.0
+        };
+        let element_to_air = if fan_on {
+            ELEMENT_TO_AIR_FAN_ON
+        } else {
+            ELEMENT_TO_AIR_FAN_OFF
+        } * (self.element_temp - self.air_temp);
+        let loss = if fan_on {
+            CHAMBER_LOSS + CHAMBER_LOSS_FAN
+        } else {
+            CHAMBER_LOSS
+        } * (self.air_temp - ambient);
+
+        self.element_temp += (heater_power - element_to_air) / ELEMENT_HEAT_CAPACITY * dt;
+        self.air_temp += (element_to_air - loss) / CHAMBER_HEAT_CAPACITY * dt;
+
+        // Moisture, the filament gives up water faster when hot and when the air is dry
+        let rh = self.air_hum();
+        let desorption = self.filament_water
+            * DESORPTION_RATE_45C
+            * ((self.air_temp - 45.0) / DESORPTION_E_FOLD).exp()
+            * (1.0 - rh / 100.0).max(0.0);
+        let exchange = if fan_on {
+            AIR_EXCHANGE_FAN_ON
+        } else {
+            AIR_EXCHANGE_FAN_OFF
+        };
+        let ambient_vapor =
+            self.params.ambient_hum / 100.0 * saturation_vapor(self.params.ambient_temp);
+
+        self.filament_water = (self.filament_water - desorption * dt).max(0.0);
+        self.water_vapor +=
+            (desorption / CHAMBER_VOLUME - exchange * (self.water_vapor - ambient_vapor)) * dt;
+
+        // Sensors
+        let alpha = (dt / SENSOR_TIME_CONSTANT).min(1.0);
+        for sensor in [&mut self.near, &mut self.far, &mut self.exhaust] {
+            let target =
+                self.air_temp + sensor.element_coupling * (self.element_temp - self.air_temp);
+            sensor.temp += (target - sensor.temp) * alpha;
+            let target_hum =
+                (100.0 * self.water_vapor / saturation_vapor(sensor.temp)).clamp(0.0, 100.0);
+            sensor.hum += (target_hum - sensor.hum) * alpha;
+        }
+    }
+
+    pub fn break_heater(&mut self) {
+        self.heater_broken = true;
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    pub fn air_temp(&self) -> f32 {
+        self.air_temp
+    }
+
+    pub fn air_hum(&self) -> f32 {
+        (100.0 * self.water_vapor / saturation_vapor(self.air_temp)).clamp(0.0, 100.0)
+    }
+
+    pub fn element_temp(&self) -> f32 {
+        self.element_temp
+    }
+
+    pub fn filament_water(&self) -> f32 {
+        self.filament_water
+    }
+
+    pub fn probe(&self, probe: Probe) -> SimSensor {
+        match probe {
+            Probe::Near => self.near,
+            Probe::Far => self.far,
+            Probe::Exhaust => self.exhaust,
+            Probe::Ambient => SimSensor {
+                temp: self.params.ambient_temp,
+                hum: self.params.ambient_hum,
+                element_coupling: 0.0,
+            },
+        }
+    }
+}
+
+// Water vapor density at saturation in g/m^3, Magnus formula
+fn saturation_vapor(temp: f32) -> f32 {
+    let pressure = 6.112 * (17.62 * temp / (243.12 + temp)).exp(); // hPa
+    216.7 * pressure / (temp + 273.15)
+}
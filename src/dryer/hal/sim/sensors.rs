@@ -0,0 +1,494 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::dryer::hal::sim::chamber::{Chamber, Probe, SimSensor};
+use crate::dryer::hal::{BusError, IoPin};
+use crate::dryer::temp_sensor::{self, bme280::Calibration};
+
+// Shared so the sim script can break sensors partway through a run
+// Sensors are picked by the name they have in the config
+#[derive(Debug, Default)]
+pub struct SensorFaults {
+    // Sensors that stop answering
+    pub dead: Vec<String>,
+    // Sensors where every other reply gets a bit flipped on the wire
+    pub flaky: Vec<String>,
+    // Sensors that get water condensed on them, picked up on their next command
+    pub condensed: Vec<String>,
+}
+
+// Register level fake of an I2C sensor
+pub trait FakeSensor: Debug + Send {
+    // now is simulated seconds, for parts that keep time
+    fn write(&mut self, cmd: &[u8], sensor: SimSensor, now: f32) -> Result<(), BusError>;
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError>;
+    fn general_call_reset(&mut self) {}
+    // Only parts with a heater to dry themselves out bother modelling this
+    fn condense(&mut self) {}
+}
+
+// Hands over a pending reply, the real parts NACK a read while they have nothing to send
+fn take_output(output: &mut Vec<u8>, buf: &mut [u8]) -> Result<(), BusError> {
+    if output.len() < buf.len() {
+        return Err(BusError::Nack);
+    }
+    buf.copy_from_slice(&output[..buf.len()]);
+    output.clear();
+    Ok(())
+}
+
+// Seconds of on-chip heating it takes to dry out a condensed sensor
+const CONDENSATION_HEAT: f32 = 25.0;
+// How much warmer the on-chip heater makes the sensor read
+const HEATER_RISE: f32 = 3.0;
+
+const STATUS_HEATER: u16 = 1 << 13;
+const STATUS_RESET: u16 = 1 << 4;
+const STATUS_COMMAND_FAILED: u16 = 1 << 1;
+// Everything clear status clears: alert pending, RH alert, T alert, reset detected
+const STATUS_CLEARABLE: u16 = (1 << 15) | (1 << 11) | (1 << 10) | STATUS_RESET;
+
+// SHT3x with the commands the driver uses, measuring whatever the chamber says
+#[derive(Debug)]
+pub struct FakeSht3x {
+    output: Vec<u8>,
+    status: u16,
+    // Seconds between measurements and when the next one is ready, while in periodic mode
+    periodic: Option<(f32, f32)>,
+    heater: bool,
+    // Seconds of heating left before condensation is gone
+    condensed: f32,
+    last_tick: f32,
+}
+
+impl Default for FakeSht3x {
+    fn default() -> Self {
+        Self {
+            output: Vec::new(),
+            // Reset detected is set at power up
+            status: STATUS_RESET,
+            periodic: None,
+            heater: false,
+            condensed: 0.0,
+            last_tick: 0.0,
+        }
+    }
+}
+
+impl FakeSensor for FakeSht3x {
+    fn general_call_reset(&mut self) {
+        self.reset();
+    }
+
+    fn condense(&mut self) {
+        self.condensed = CONDENSATION_HEAT;
+    }
+
+    fn write(&mut self, cmd: &[u8], sensor: SimSensor, now: f32) -> Result<(), BusError> {
+        // The heater dries the sensor out for as long as it has been on
+        if self.heater {
+            self.condensed = (self.condensed - (now - self.last_tick)).max(0.0);
+        }
+        self.last_tick = now;
+
+        let cmd = match cmd {
+            [msb, lsb] => u16::from_be_bytes([*msb, *lsb]),
+            _ => return self.fail(cmd),
+        };
+
+        // Periodic mode only listens to fetch and break
+        if let Some((period, next)) = self.periodic {
+            return match cmd {
+                0xE000 => {
+                    self.output.clear();
+                    if now >= next {
+                        self.measure(sensor);
+                        self.periodic = Some((period, now + period));
+                    }
+                    Ok(())
+                }
+                0x3093 => {
+                    self.periodic = None;
+                    Ok(())
+                }
+                _ => Err(BusError::Nack),
+            };
+        }
+
+        self.status &= !STATUS_COMMAND_FAILED;
+        match cmd {
+            // Single shot, no clock stretching, high/medium/low repeatability
+            0x2400 | 0x240B | 0x2416 => self.measure(sensor),
+            // Periodic, high/medium/low repeatability at each rate
+            0x2032 | 0x2024 | 0x202F => self.start_periodic(2.0, now),
+            0x2130 | 0x2126 | 0x212D => self.start_periodic(1.0, now),
+            0x2236 | 0x2220 | 0x222B => self.start_periodic(0.5, now),
+            0x2334 | 0x2322 | 0x2329 => self.start_periodic(0.25, now),
+            0x2737 | 0x2721 | 0x272A => self.start_periodic(0.1, now),
+            0x30A2 => self.reset(),
+            0x306D => {
+                self.heater = true;
+                self.status |= STATUS_HEATER;
+            }
+            0x3066 => {
+                self.heater = false;
+                self.status &= !STATUS_HEATER;
+            }
+            0xF32D => {
+                self.output.clear();
+                push_word(&mut self.output, self.status);
+            }
+            0x3041 => self.status &= !STATUS_CLEARABLE,
+            _ => return self.fail(&cmd.to_be_bytes()),
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        take_output(&mut self.output, buf)
+    }
+}
+
+impl FakeSht3x {
+    fn reset(&mut self) {
+        self.output.clear();
+        self.status = STATUS_RESET;
+        self.periodic = None;
+        self.heater = false;
+    }
+
+    fn fail(&mut self, cmd: &[u8]) -> Result<(), BusError> {
+        self.status |= STATUS_COMMAND_FAILED;
+        Err(BusError::Other(format!(
+            "sim: SHT3x command {cmd:02x?} not supported"
+        )))
+    }
+
+    fn start_periodic(&mut self, period: f32, now: f32) {
+        // First measurement starts straight away
+        self.periodic = Some((period, now));
+    }
+
+    fn measure(&mut self, sensor: SimSensor) {
+        let (temp, hum) = if self.condensed > 0.0 {
+            (sensor.temp, 100.0)
+        } else if self.heater {
+            (sensor.temp + HEATER_RISE, sensor.hum * 0.85)
+        } else {
+            (sensor.temp, sensor.hum)
+        };
+
+        self.output.clear();
+        let temp_raw = ((temp + 45.0) / 175.0 * 65535.0).clamp(0.0, 65535.0) as u16;
+        let hum_raw = (hum / 100.0 * 65535.0).clamp(0.0, 65535.0) as u16;
+        push_word(&mut self.output, temp_raw);
+        push_word(&mut self.output, hum_raw);
+    }
+}
+
+// Sensor words go out MSB, LSB, CRC
+fn push_word(out: &mut Vec<u8>, word: u16) {
+    let bytes = word.to_be_bytes();
+    out.extend_from_slice(&bytes);
+    out.push(temp_sensor::crc8(&bytes));
+}
+
+// SHT4x, single byte commands
+#[derive(Debug, Default)]
+pub struct FakeSht4x {
+    output: Vec<u8>,
+}
+
+impl FakeSensor for FakeSht4x {
+    fn write(&mut self, cmd: &[u8], sensor: SimSensor, _now: f32) -> Result<(), BusError> {
+        match cmd {
+            // Measure at high, medium or low precision
+            [0xFD] | [0xF6] | [0xE0] => {
+                self.output.clear();
+                let temp_raw = ((sensor.temp + 45.0) / 175.0 * 65535.0).clamp(0.0, 65535.0) as u16;
+                let hum_raw = ((sensor.hum + 6.0) / 125.0 * 65535.0).clamp(0.0, 65535.0) as u16;
+                push_word(&mut self.output, temp_raw);
+                push_word(&mut self.output, hum_raw);
+                Ok(())
+            }
+            [0x94] => {
+                self.output.clear();
+                Ok(())
+            }
+            _ => Err(BusError::Other(format!(
+                "sim: SHT4x command {cmd:02x?} not supported"
+            ))),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        take_output(&mut self.output, buf)
+    }
+}
+
+// AHT20, comes up calibrated like most of them do
+#[derive(Debug)]
+pub struct FakeAht20 {
+    status: u8,
+    output: Vec<u8>,
+}
+
+impl Default for FakeAht20 {
+    fn default() -> Self {
+        Self {
+            status: 0x18,
+            output: Vec::new(),
+        }
+    }
+}
+
+impl FakeSensor for FakeAht20 {
+    fn write(&mut self, cmd: &[u8], sensor: SimSensor, _now: f32) -> Result<(), BusError> {
+        match cmd {
+            [0xBA] => self.output.clear(),
+            [0xBE, 0x08, 0x00] => self.status |= 0x08,
+            [0xAC, 0x33, 0x00] => {
+                let hum = (sensor.hum / 100.0 * 1048576.0).clamp(0.0, 1048575.0) as u32;
+                let temp = ((sensor.temp + 50.0) / 200.0 * 1048576.0).clamp(0.0, 1048575.0) as u32;
+                self.output = vec![
+                    self.status,
+                    (hum >> 12) as u8,
+                    (hum >> 4) as u8,
+                    ((hum << 4) as u8) | (temp >> 16) as u8,
+                    (temp >> 8) as u8,
+                    temp as u8,
+                ];
+                self.output.push(temp_sensor::crc8(&self.output));
+            }
+            _ => {
+                return Err(BusError::Other(format!(
+                    "sim: AHT20 command {cmd:02x?} not supported"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        // Every read starts with the status byte, a one byte read is just the status
+        if buf.len() == 1 {
+            buf[0] = self.status;
+            return Ok(());
+        }
+        take_output(&mut self.output, buf)
+    }
+}
+
+// Trimming values typical of a real BME280, pressure left at zero since nothing reads it
+const BME280_CALIB_TP: [u8; 26] = [
+    0x70, 0x6B, // T1 27504
+    0x43, 0x67, // T2 26435
+    0x18, 0xFC, // T3 -1000
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,  // P1-P9
+    0,  // Unused
+    75, // H1
+];
+const BME280_CALIB_H: [u8; 7] = [
+    0x6A, 0x01, // H2 362
+    0,    // H3
+    0x13, 0x29, 0x03, // H4 313, H5 50
+    30,   // H6
+];
+
+// BME280 register map, a forced mode measurement lands in the data registers straight away
+#[derive(Debug)]
+pub struct FakeBme280 {
+    regs: [u8; 0x100],
+    pointer: u8,
+}
+
+impl Default for FakeBme280 {
+    fn default() -> Self {
+        let mut regs = [0u8; 0x100];
+        regs[0xD0] = 0x60;
+        regs[0x88..0x88 + 26].copy_from_slice(&BME280_CALIB_TP);
+        regs[0xE1..0xE1 + 7].copy_from_slice(&BME280_CALIB_H);
+        Self { regs, pointer: 0 }
+    }
+}
+
+impl FakeBme280 {
+    // Raw readings that compensate to what the chamber says, found by bisection
+    // since the compensation only goes one way
+    fn measure(&mut self, sensor: SimSensor) {
+        let calibration = Calibration::parse(&BME280_CALIB_TP, &BME280_CALIB_H);
+        let bisect = |mut low: u32, mut high: u32, f: &dyn Fn(u32) -> f32, target: f32| {
+            while low < high {
+                let mid = (low + high) / 2;
+                if f(mid) < target {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        };
+
+        let adc_t = bisect(
+            0,
+            (1 << 20) - 1,
+            &|adc| calibration.temp(adc).1,
+            sensor.temp,
+        );
+        let (t_fine, _) = calibration.temp(adc_t);
+        let adc_h = bisect(0, 0xFFFF, &|adc| calibration.hum(adc, t_fine), sensor.hum);
+
+        self.regs[0xFA] = (adc_t >> 12) as u8;
+        self.regs[0xFB] = (adc_t >> 4) as u8;
+        self.regs[0xFC] = (adc_t << 4) as u8;
+        self.regs[0xFD..0xFF].copy_from_slice(&(adc_h as u16).to_be_bytes());
+    }
+}
+
+impl FakeSensor for FakeBme280 {
+    fn write(&mut self, cmd: &[u8], sensor: SimSensor, _now: f32) -> Result<(), BusError> {
+        match cmd {
+            [reg] => self.pointer = *reg,
+            // Soft reset keeps the trimming, it lives in NVM
+            [0xE0, 0xB6] => self.regs[0xF2..0xF5].fill(0),
+            [reg, value] => {
+                self.regs[*reg as usize] = *value;
+                // Forced mode, measure once and go back to sleep
+                if *reg == 0xF4 && value & 0x03 != 0 {
+                    self.measure(sensor);
+                    self.regs[0xF4] &= !0x03;
+                }
+            }
+            _ => {
+                return Err(BusError::Other(format!(
+                    "sim: BME280 write {cmd:02x?} not supported"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), BusError> {
+        for byte in buf {
+            *byte = self.regs[self.pointer as usize];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
+
+// DHT22 on a GPIO pin
+// When the driver lets go of the line after the start signal, the whole response is worked
+// out up front and is_high plays it back against the real clock, so the driver's timing is
+// tested for real. The odd frame still gets mangled if the machine is busy, same as on a Pi.
+#[derive(Debug)]
+pub struct FakeDhtPin {
+    chamber: Arc<Mutex<Chamber>>,
+    probe: Probe,
+    name: String,
+    faults: Arc<Mutex<SensorFaults>>,
+    held_low: bool,
+    // Level and length in microseconds of every part of the response
+    response: Vec<(bool, u32)>,
+    released: Option<Instant>,
+    frames: u32,
+    // Stands for the start of the model's time
+    started: Instant,
+}
+
+impl FakeDhtPin {
+    pub fn new(
+        chamber: Arc<Mutex<Chamber>>,
+        probe: Probe,
+        name: String,
+        faults: Arc<Mutex<SensorFaults>>,
+    ) -> Self {
+        Self {
+            chamber,
+            probe,
+            name,
+            faults,
+            held_low: false,
+            response: Vec::new(),
+            released: None,
+            frames: 0,
+            started: Instant::now(),
+        }
+    }
+
+    fn respond(&mut self) {
+        self.response.clear();
+        let faults = self.faults.lock().unwrap();
+        if faults.dead.contains(&self.name) {
+            return;
+        }
+
+        let sensor = self.chamber.lock().unwrap().probe(self.probe);
+        let hum = (sensor.hum * 10.0).round() as u16;
+        let temp =
+            (sensor.temp.abs() * 10.0).round() as u16 | if sensor.temp < 0.0 { 0x8000 } else { 0 };
+        let [h1, h0] = hum.to_be_bytes();
+        let [t1, t0] = temp.to_be_bytes();
+        let mut frame = [h1, h0, t1, t0, 0];
+        frame[4] = frame[..4].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        self.frames += 1;
+        if faults.flaky.contains(&self.name) && self.frames.is_multiple_of(2) {
+            frame[0] ^= 0x10;
+        }
+
+        // Pull-up before the sensor answers, then 80us low and 80us high
+        self.response.extend([(true, 30), (false, 80), (true, 80)]);
+        for i in 0..40 {
+            let bit = frame[i / 8] & (0x80 >> (i % 8)) != 0;
+            self.response.push((false, 50));
+            self.response.push((true, if bit { 70 } else { 26 }));
+        }
+        self.response.push((false, 50));
+    }
+}
+
+impl IoPin for FakeDhtPin {
+    fn set_low(&mut self) {
+        self.held_low = true;
+        self.released = None;
+    }
+
+    fn release(&mut self) {
+        if self.held_low {
+            self.held_low = false;
+            self.respond();
+            self.released = Some(Instant::now());
+        }
+    }
+
+    fn is_high(&self) -> bool {
+        if self.held_low {
+            return false;
+        }
+        let Some(released) = self.released else {
+            return true;
+        };
+
+        let mut elapsed = released.elapsed().as_micros();
+        for (level, length) in &self.response {
+            if elapsed < *length as u128 {
+                return *level;
+            }
+            elapsed -= *length as u128;
+        }
+        // Response is over, the pull-up takes the line back high
+        true
+    }
+
+    // The start signal only has to be long enough, no need to actually wait
+    fn delay(&mut self, _duration: Duration) {}
+
+    // The model's time, so the driver spaces its reads out in simulated seconds
+    fn now(&self) -> Instant {
+        self.started + Duration::from_secs_f32(self.chamber.lock().unwrap().elapsed())
+    }
+}
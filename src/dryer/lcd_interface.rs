@@ -1,153 +1,439 @@
-use rppal::i2c::I2c;
+use crate::dryer::char_rom::CharRom;
+use crate::dryer::hal::I2cBus;
+use serde::Deserialize;
 use std::error::Error;
-use std::thread::sleep;
 use std::time::Duration;
 
-// Slave address of the display module
-const ADDR: u16 = 0x27;
+// Which PCF8574 output each HD44780 line is wired to, 0 to 7
+// Most backpacks are D7-D4 on the top four and BL EN RW RS below, but not all
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PinMap {
+    pub rs: u8,
+    pub rw: u8,
+    pub en: u8,
+    pub bl: u8,
+    pub d4: u8,
+    pub d5: u8,
+    pub d6: u8,
+    pub d7: u8,
+}
 
-// Command Table
-const CLEAR: u8 = 0x01;
-const HOME: u8 = 0x02;
+impl Default for PinMap {
+    fn default() -> Self {
+        Self {
+            rs: 0,
+            rw: 1,
+            en: 2,
+            bl: 3,
+            d4: 4,
+            d5: 5,
+            d6: 6,
+            d7: 7,
+        }
+    }
+}
 
-// Clears the display and sets RAM address to 0
-pub fn clear(i2c: &mut I2c) -> Result<(), Box<dyn Error>> {
-    write_command(i2c, CLEAR)?;
-    sleep(Duration::from_millis(2));
-    Ok(())
+impl PinMap {
+    fn pins(&self) -> [u8; 8] {
+        [
+            self.rs, self.rw, self.en, self.bl, self.d4, self.d5, self.d6, self.d7,
+        ]
+    }
 }
 
-// Returns the cursor to (0, 0)
-pub fn home(i2c: &mut I2c) -> Result<(), Box<dyn Error>> {
-    write_command(i2c, HOME)?;
-    sleep(Duration::from_millis(2));
-    Ok(())
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LcdConfig {
+    // Slave address of the backpack, 0x27 or 0x3F depending on the chip and how it is strapped
+    pub addr: u16,
+    pub cols: u8,
+    pub rows: u8,
+    pub pins: PinMap,
+    // Decides which characters other than ASCII can be shown
+    pub rom: CharRom,
 }
 
-// Moves the Display Data RAM Address, or where data is going to be written to
-pub fn set_cursor(i2c: &mut I2c, col: u8, row: u8) -> Result<(), Box<dyn Error>> {
-    let offsets = [0x00, 0x40];
-    // 0x80 is set Display Data RAM Address
-    // 1st row is 0x00 -> 0x27
-    // 2nd row is 0x40 -> 0x67
-    // Both of these are more characters than fit no screen, maybe used for scrolling???
-    let cmd = 0x80 | (col + offsets[row as usize]);
-    write_command(i2c, cmd)?;
-    Ok(())
+impl Default for LcdConfig {
+    fn default() -> Self {
+        Self {
+            addr: 0x27,
+            cols: 16,
+            rows: 2,
+            pins: PinMap::default(),
+            rom: CharRom::A00,
+        }
+    }
 }
 
-// Writes text to display RAM
-pub fn print(i2c: &mut I2c, text: &str) -> Result<(), Box<dyn Error>> {
-    for c in text.chars() {
-        if c.is_ascii() {
-            write_data(i2c, c as u8)?;
-        } else {
-            // Black Square, Error case
-            println!("non ascii char");
-            write_data(i2c, 0xFF)?;
+impl LcdConfig {
+    // Catches displays a single HD44780 can't drive, and ones too small for the screens
+    pub fn check(&self) -> Result<(), String> {
+        if !(16..=40).contains(&self.cols) || !(2..=4).contains(&self.rows) {
+            return Err(format!(
+                "lcd has to be 16 to 40 columns and 2 to 4 rows, not {}x{}",
+                self.cols, self.rows
+            ));
+        }
+        // 40x4 modules have a second controller with its own enable
+        if self.cols as usize * self.rows as usize > 80 {
+            return Err(format!(
+                "a {}x{} lcd needs two controllers, only one is supported",
+                self.cols, self.rows
+            ));
         }
+        let pins = self.pins.pins();
+        if pins.iter().any(|pin| *pin > 7) || (1..pins.len()).any(|i| pins[..i].contains(&pins[i]))
+        {
+            return Err(String::from(
+                "lcd pins have to be 8 different PCF8574 outputs, 0 to 7",
+            ));
+        }
+        Ok(())
     }
-    Ok(())
 }
 
-// Send initialize sequence from Data-Sheet
-pub fn init(i2c: &mut I2c) -> Result<(), Box<dyn Error>> {
-    // Takes 20ms to start, shouldn't be possible to get here faster than that but just in case
-    sleep(Duration::from_millis(20));
-
-    for _ in 0..3 {
-        pulse_enable(i2c, 0x03, false)?;
-        sleep(Duration::from_millis(5));
-    }
-
-    // Switch to 4 bit mode
-    pulse_enable(i2c, 0x02, false)?;
-    sleep(Duration::from_millis(5));
-
-    // Function set: 0b0 0 1 DL N F X X
-    // DL 1 -> 8bit data
-    // DL 0 -> 4bit data
-    // N 0 -> One-line display
-    // N 1 -> Two-line display
-    // F 0 -> 5x8 dots font
-    // F 1 -> 5x10 dots font
-    // Setting: 4bit data, 2 lines, 5x8 font
-    write_command(i2c, 0x28)?;
-
-    // Display off
-    write_command(i2c, 0x08)?;
-
-    // Clear display
-    write_command(i2c, 0x01)?;
-    sleep(Duration::from_millis(2));
-
-    // Entry Mode: 0b0000 0 1 I/D S
-    // I/D, I=1=Increment ; D=0=Decrement
-    // S, S=1=Display Shift ; S=0=No Shift
-    // Setting: Cursor increment, no shift
-    write_command(i2c, 0x06)?;
-
-    // Display On/OFF: 0b0000 1DCB
-    // D, D=1=Display On ; D=0=Display Off
-    // C, C=1=Cursor On ; C=0=Cursor Off
-    // B, B=1=Blinks on ; B=0=Blinks Off
-    write_command(i2c, 0x0C)?;
-
-    Ok(())
+// Command Table
+const CLEAR: u8 = 0x01;
+
+// A character LCD on an HD44780, behind a PCF8574 I2C backpack
+#[derive(Debug)]
+pub struct Lcd {
+    addr: u16,
+    cols: u8,
+    rows: u8,
+    pins: PinMap,
+    // Goes out on the BL pin with every byte sent
+    backlight: bool,
 }
 
-// Sends a nibble across the wire
-// Data is latched on falling edge of Enable bit, which is why it is sent twice
-fn pulse_enable(i2c: &mut I2c, nibble: u8, rs: bool) -> Result<(), Box<dyn Error>> {
-    i2c.set_slave_address(ADDR)?;
+impl Lcd {
+    pub fn new(config: &LcdConfig) -> Self {
+        Self {
+            addr: config.addr,
+            cols: config.cols,
+            rows: config.rows,
+            pins: config.pins,
+            backlight: true,
+        }
+    }
 
-    let byte_en1 = build_byte(nibble, rs, false, true);
-    i2c.write(&[byte_en1])?;
-    sleep(Duration::from_micros(1));
+    pub fn cols(&self) -> usize {
+        self.cols as usize
+    }
 
-    let byte_en0 = build_byte(nibble, rs, false, false);
-    i2c.write(&[byte_en0])?;
-    sleep(Duration::from_micros(50));
+    pub fn rows(&self) -> usize {
+        self.rows as usize
+    }
 
-    Ok(())
-}
+    pub fn backlight(&self) -> bool {
+        self.backlight
+    }
 
-fn build_byte(nibble: u8, rs: bool, rw: bool, en: bool) -> u8 {
-    let mut byte = (nibble & 0x0F) << 4;
+    // Switches the backlight with a byte that leaves enable low, so the HD44780 ignores it
+    pub fn set_backlight(&mut self, i2c: &mut impl I2cBus, on: bool) -> Result<(), Box<dyn Error>> {
+        let byte = if on { 1 << self.pins.bl } else { 0 };
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&[byte])?;
+        // Only once it has gone, so a failed write is tried again
+        self.backlight = on;
+        Ok(())
+    }
+
+    // Clears the display and sets RAM address to 0
+    pub fn clear(&self, i2c: &mut impl I2cBus) -> Result<(), Box<dyn Error>> {
+        self.write_command(i2c, CLEAR)?;
+        i2c.delay(Duration::from_millis(2));
+        Ok(())
+    }
 
-    if rs {
-        byte |= 1 << 0;
+    // Moves the Display Data RAM Address, or where data is going to be written to
+    pub fn set_cursor(
+        &self,
+        i2c: &mut impl I2cBus,
+        col: u8,
+        row: u8,
+    ) -> Result<(), Box<dyn Error>> {
+        // 0x80 is set Display Data RAM Address
+        // 1st row is 0x00 -> 0x27
+        // 2nd row is 0x40 -> 0x67
+        // A 4 row display is the same two lines folded in half, so the 3rd and 4th rows carry
+        // on from the end of the 1st and 2nd
+        let offsets = [0x00, 0x40, self.cols, 0x40 + self.cols];
+        let cmd = 0x80 | (col + offsets[row as usize]);
+        self.write_command(i2c, cmd)?;
+        Ok(())
     }
-    if rw {
-        byte |= 1 << 1;
+
+    // Writes one of the 8 user characters, codes 0 to 7, into character generator RAM
+    // Leaves the RAM address in CGRAM, so set_cursor has to come before any more text
+    pub fn set_glyph(
+        &self,
+        i2c: &mut impl I2cBus,
+        slot: u8,
+        rows: &[u8; 8],
+    ) -> Result<(), Box<dyn Error>> {
+        // 0x40 is set CGRAM Address, each character is 8 rows
+        self.write_command(i2c, 0x40 | ((slot & 0x07) << 3))?;
+        for row in rows {
+            self.write_data(i2c, row & 0x1F)?;
+        }
+        Ok(())
     }
-    if en {
-        byte |= 1 << 2;
+
+    // Writes character codes to display RAM from the cursor on
+    pub fn write(&self, i2c: &mut impl I2cBus, codes: &[u8]) -> Result<(), Box<dyn Error>> {
+        for code in codes {
+            self.write_data(i2c, *code)?;
+        }
+        Ok(())
     }
 
-    // Backlight always on
-    byte |= 1 << 3;
+    // Send initialize sequence from Data-Sheet
+    pub fn init(&self, i2c: &mut impl I2cBus) -> Result<(), Box<dyn Error>> {
+        // Takes 20ms to start, shouldn't be possible to get here faster than that but just in case
+        i2c.delay(Duration::from_millis(20));
 
-    byte
-}
+        for _ in 0..3 {
+            self.pulse_enable(i2c, 0x03, false)?;
+            i2c.delay(Duration::from_millis(5));
+        }
 
-// Sends a byte across the wire
-fn write_byte(i2c: &mut I2c, byte: u8, rs: bool) -> Result<(), Box<dyn Error>> {
-    let high = byte >> 4;
-    let low = byte & 0x0F;
+        // Switch to 4 bit mode
+        self.pulse_enable(i2c, 0x02, false)?;
+        i2c.delay(Duration::from_millis(5));
 
-    pulse_enable(i2c, high, rs)?;
-    pulse_enable(i2c, low, rs)?;
+        // Function set: 0b0 0 1 DL N F X X
+        // DL 1 -> 8bit data
+        // DL 0 -> 4bit data
+        // N 0 -> One-line display
+        // N 1 -> Two-line display
+        // F 0 -> 5x8 dots font
+        // F 1 -> 5x10 dots font
+        // Setting: 4bit data, 2 lines, 5x8 font
+        // 4 row displays are 2 lines as far as the controller is concerned
+        self.write_command(i2c, 0x28)?;
 
-    Ok(())
-}
+        // Display off
+        self.write_command(i2c, 0x08)?;
+
+        // Clear display
+        self.write_command(i2c, 0x01)?;
+        i2c.delay(Duration::from_millis(2));
+
+        // Entry Mode: 0b0000 0 1 I/D S
+        // I/D, I=1=Increment ; D=0=Decrement
+        // S, S=1=Display Shift ; S=0=No Shift
+        // Setting: Cursor increment, no shift
+        self.write_command(i2c, 0x06)?;
+
+        // Display On/OFF: 0b0000 1DCB
+        // D, D=1=Display On ; D=0=Display Off
+        // C, C=1=Cursor On ; C=0=Cursor Off
+        // B, B=1=Blinks on ; B=0=Blinks Off
+        self.write_command(i2c, 0x0C)?;
+
+        Ok(())
+    }
+
+    // Sends a nibble across the wire
+    // Data is latched on falling edge of Enable bit, which is why it is sent twice
+    fn pulse_enable(
+        &self,
+        i2c: &mut impl I2cBus,
+        nibble: u8,
+        rs: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        i2c.set_slave_address(self.addr)?;
+
+        let byte_en1 = self.build_byte(nibble, rs, false, true);
+        i2c.write(&[byte_en1])?;
+        i2c.delay(Duration::from_micros(1));
+
+        let byte_en0 = self.build_byte(nibble, rs, false, false);
+        i2c.write(&[byte_en0])?;
+        i2c.delay(Duration::from_micros(50));
+
+        Ok(())
+    }
 
-// Sends a byte with RS=true(data)
-fn write_data(i2c: &mut I2c, byte: u8) -> Result<(), Box<dyn Error>> {
-    write_byte(i2c, byte, true)
+    // Puts each bit on the PCF8574 output its line is wired to
+    fn build_byte(&self, nibble: u8, rs: bool, rw: bool, en: bool) -> u8 {
+        let pins = &self.pins;
+        let mut byte = 0;
+
+        for (bit, pin) in [pins.d4, pins.d5, pins.d6, pins.d7].into_iter().enumerate() {
+            if nibble & (1 << bit) != 0 {
+                byte |= 1 << pin;
+            }
+        }
+        if rs {
+            byte |= 1 << pins.rs;
+        }
+        if rw {
+            byte |= 1 << pins.rw;
+        }
+        if en {
+            byte |= 1 << pins.en;
+        }
+
+        if self.backlight {
+            byte |= 1 << pins.bl;
+        }
+
+        byte
+    }
+
+    // Sends a byte across the wire
+    fn write_byte(&self, i2c: &mut impl I2cBus, byte: u8, rs: bool) -> Result<(), Box<dyn Error>> {
+        let high = byte >> 4;
+        let low = byte & 0x0F;
+
+        self.pulse_enable(i2c, high, rs)?;
+        self.pulse_enable(i2c, low, rs)?;
+
+        Ok(())
+    }
+
+    // Sends a byte with RS=true(data)
+    fn write_data(&self, i2c: &mut impl I2cBus, byte: u8) -> Result<(), Box<dyn Error>> {
+        self.write_byte(i2c, byte, true)
+    }
+
+    // Sends a byte with RS=false(cmd)
+    fn write_command(&self, i2c: &mut impl I2cBus, byte: u8) -> Result<(), Box<dyn Error>> {
+        self.write_byte(i2c, byte, false)
+    }
 }
 
-// Sends a byte with RS=false(cmd)
-fn write_command(i2c: &mut I2c, byte: u8) -> Result<(), Box<dyn Error>> {
-    write_byte(i2c, byte, false)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::hal::BusError;
+
+    // Reads back the commands the LCD would get, through the same wiring
+    struct Recorder {
+        pins: PinMap,
+        enable: bool,
+        high: Option<u8>,
+        bytes: Vec<u8>,
+        commands: Vec<u8>,
+    }
+
+    impl Recorder {
+        fn new(pins: PinMap) -> Self {
+            Self {
+                pins,
+                enable: false,
+                high: None,
+                bytes: Vec::new(),
+                commands: Vec::new(),
+            }
+        }
+    }
+
+    impl I2cBus for Recorder {
+        fn set_slave_address(&mut self, _addr: u16) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn write(&mut self, buf: &[u8]) -> Result<(), BusError> {
+            let pins = self.pins;
+            for &byte in buf {
+                self.bytes.push(byte);
+                let enable = byte & (1 << pins.en) != 0;
+                if self.enable && !enable {
+                    let nibble = [pins.d4, pins.d5, pins.d6, pins.d7]
+                        .into_iter()
+                        .enumerate()
+                        .fold(0, |nibble, (bit, pin)| nibble | (byte >> pin & 1) << bit);
+                    match self.high.take() {
+                        None => self.high = Some(nibble),
+                        Some(high) => self.commands.push(high << 4 | nibble),
+                    }
+                }
+                self.enable = enable;
+            }
+            Ok(())
+        }
+        fn read(&mut self, _buf: &mut [u8]) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn delay(&mut self, _duration: Duration) {}
+    }
+
+    fn cursor(config: &LcdConfig, col: u8, row: u8) -> u8 {
+        let mut i2c = Recorder::new(config.pins);
+        Lcd::new(config).set_cursor(&mut i2c, col, row).unwrap();
+        assert_eq!(i2c.commands.len(), 1);
+        i2c.commands[0]
+    }
+
+    #[test]
+    fn rows_of_a_16x2() {
+        let config = LcdConfig::default();
+        assert_eq!(cursor(&config, 0, 0), 0x80);
+        assert_eq!(cursor(&config, 15, 0), 0x80 | 0x0F);
+        assert_eq!(cursor(&config, 0, 1), 0x80 | 0x40);
+        assert_eq!(cursor(&config, 15, 1), 0x80 | 0x4F);
+    }
+
+    #[test]
+    fn rows_of_a_20x4_fold_the_two_lines() {
+        let config = LcdConfig {
+            cols: 20,
+            rows: 4,
+            ..LcdConfig::default()
+        };
+        assert_eq!(cursor(&config, 0, 0), 0x80);
+        assert_eq!(cursor(&config, 0, 1), 0x80 | 0x40);
+        // The 3rd row carries on from the end of the 1st, the 4th from the end of the 2nd
+        assert_eq!(cursor(&config, 0, 2), 0x80 | 0x14);
+        assert_eq!(cursor(&config, 19, 2), 0x80 | 0x27);
+        assert_eq!(cursor(&config, 0, 3), 0x80 | 0x54);
+        assert_eq!(cursor(&config, 19, 3), 0x80 | 0x67);
+    }
+
+    #[test]
+    fn pins_follow_the_wiring() {
+        // Data on the bottom four outputs, control lines above
+        let pins = PinMap {
+            rs: 4,
+            rw: 5,
+            en: 6,
+            bl: 7,
+            d4: 0,
+            d5: 1,
+            d6: 2,
+            d7: 3,
+        };
+        let config = LcdConfig {
+            pins,
+            ..LcdConfig::default()
+        };
+        assert_eq!(cursor(&config, 0, 1), 0x80 | 0x40);
+
+        let mut i2c = Recorder::new(pins);
+        Lcd::new(&config).set_cursor(&mut i2c, 0, 1).unwrap();
+        // High nibble 0xC with enable and the backlight, then again with enable dropped
+        assert_eq!(i2c.bytes[..2], [0b1100_1100, 0b1000_1100]);
+    }
+
+    #[test]
+    fn sizes_one_controller_can_drive() {
+        let size = |cols, rows| LcdConfig {
+            cols,
+            rows,
+            ..LcdConfig::default()
+        };
+        assert!(size(16, 2).check().is_ok());
+        assert!(size(20, 4).check().is_ok());
+        assert!(size(40, 2).check().is_ok());
+        assert!(size(40, 4).check().is_err());
+        assert!(size(8, 2).check().is_err());
+        assert!(size(16, 1).check().is_err());
+
+        let mut config = LcdConfig::default();
+        config.pins.d7 = config.pins.rs;
+        assert!(config.check().is_err());
+    }
 }
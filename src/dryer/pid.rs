@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// The part of the controller that changes between builds, saved by autotune
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl PidGains {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+// Gains and limits for the heater controller
+// Output is a heater duty cycle, error is in degrees C
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PidConfig {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    pub output_min: f32,
+    pub output_max: f32,
+    // Length of one heater on/off cycle
+    pub window_secs: u64,
+    // Pulses shorter than this are skipped, the relay doesn't need to click for 50ms of heat
+    pub min_pulse_ms: u64,
+}
+
+impl PidConfig {
+    #[cfg(feature = "sim")]
+    pub fn gains(&self) -> PidGains {
+        PidGains {
+            kp: self.kp,
+            ki: self.ki,
+            kd: self.kd,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+
+    pub fn min_pulse(&self) -> Duration {
+        Duration::from_millis(self.min_pulse_ms)
+    }
+
+    // Catches settings the heater can't be run with
+    pub fn check(&self) -> Result<(), String> {
+        let gains = [("kp", self.kp), ("ki", self.ki), ("kd", self.kd)];
+        if let Some((name, gain)) = gains
+            .iter()
+            .find(|(_, gain)| !gain.is_finite() || *gain < 0.0)
+        {
+            return Err(format!("pid {name} has to be 0 or more, not {gain}"));
+        }
+        // The output is a duty cycle
+        if !(0.0..=1.0).contains(&self.output_min)
+            || !(0.0..=1.0).contains(&self.output_max)
+            || self.output_min >= self.output_max
+        {
+            return Err(format!(
+                "pid output_min and output_max have to be 0 to 1 with min under max, not {} and {}",
+                self.output_min, self.output_max
+            ));
+        }
+        if self.window_secs == 0 || self.min_pulse() >= self.window() {
+            return Err(format!(
+                "pid window_secs has to be longer than the {}ms min_pulse_ms",
+                self.min_pulse_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PidConfig {
+    // Tuned against the simulator, the real box is close enough to start from these
+    fn default() -> Self {
+        Self {
+            kp: 0.3,
+            ki: 0.0004,
+            kd: 4.0,
+            output_min: 0.0,
+            output_max: 1.0,
+            window_secs: 10,
+            min_pulse_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pid {
+    config: PidConfig,
+    // Stored already multiplied by ki so changing gains doesn't bump the output
+    integral: f32,
+    last_measurement: Option<f32>,
+    last_update: Option<Instant>,
+    last_output: f32,
+}
+
+impl Pid {
+    pub fn new(config: PidConfig) -> Self {
+        Self {
+            config,
+            integral: 0.0,
+            last_measurement: None,
+            last_update: None,
+            last_output: 0.0,
+        }
+    }
+
+    #[cfg(feature = "sim")]
+    pub fn config(&self) -> PidConfig {
+        self.config
+    }
+
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.config.kp = gains.kp;
+        self.config.ki = gains.ki;
+        self.config.kd = gains.kd;
+    }
+
+    // Forget the history, used when a new run starts
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_measurement = None;
+        self.last_update = None;
+        self.last_output = 0.0;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.last_output
+    }
+
+    // Returns the duty cycle to run the heater at
+    pub fn update(&mut self, setpoint: f32, measurement: f32, now: Instant) -> f32 {
+        let PidConfig {
+            kp,
+            ki,
+            kd,
+            output_min,
+            output_max,
+            ..
+        } = self.config;
+
+        let dt = self
+            .last_update
+            .map(|last| (now - last).as_secs_f32())
+            .unwrap_or(0.0);
+        let error = setpoint - measurement;
+
+        let p = kp * error;
+
+        // Derivative on measurement so a new setpoint doesn't kick the output
+        let d = match self.last_measurement {
+            Some(last) if dt > 0.0 => -kd * (measurement - last) / dt,
+            _ => 0.0,
+        };
+
+        // Anti-windup, only integrate if it wouldn't push an already saturated output further
+        let unclamped = p + self.integral + d;
+        let saturated_high = unclamped >= output_max && error > 0.0;
+        let saturated_low = unclamped <= output_min && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral += ki * error * dt;
+        }
+        self.integral = self.integral.clamp(output_min, output_max);
+
+        self.last_measurement = Some(measurement);
+        self.last_update = Some(now);
+        self.last_output = (p + self.integral + d).clamp(output_min, output_max);
+        self.last_output
+    }
+}
+
+// Slow PWM for a relay, the heater is on for duty * window at the start of every window
+#[derive(Debug)]
+pub struct TimeProportioner {
+    window: Duration,
+    min_pulse: Duration,
+    window_start: Option<Instant>,
+    on_time: Duration,
+}
+
+impl TimeProportioner {
+    pub fn new(window: Duration, min_pulse: Duration) -> Self {
+        Self {
+            window,
+            min_pulse,
+            window_start: None,
+            on_time: Duration::ZERO,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.window_start = None;
+    }
+
+    // Whether the relay should be on right now
+    // The duty is only picked up when a new window starts so the relay switches at most twice per window
+    pub fn output(&mut self, duty: f32, now: Instant) -> bool {
+        let new_window = match self.window_start {
+            None => true,
+            Some(start) => now - start >= self.window,
+        };
+
+        if new_window {
+            self.window_start = Some(now);
+            let on_time = self.window.mul_f32(duty.clamp(0.0, 1.0));
+            self.on_time = if on_time < self.min_pulse {
+                Duration::ZERO
+            } else if self.window - on_time < self.min_pulse {
+                self.window
+            } else {
+                on_time
+            };
+        }
+
+        match self.window_start {
+            Some(start) => now - start < self.on_time,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(kp: f32, ki: f32, kd: f32) -> PidConfig {
+        PidConfig {
+            kp,
+            ki,
+            kd,
+            ..PidConfig::default()
+        }
+    }
+
+    #[test]
+    fn output_is_clamped() {
+        let mut pid = Pid::new(config(1.0, 0.0, 0.0));
+        let now = Instant::now();
+        assert_eq!(pid.update(80.0, 20.0, now), 1.0);
+        assert_eq!(pid.update(20.0, 80.0, now + Duration::from_secs(1)), 0.0);
+        assert_eq!(pid.update(50.0, 49.5, now + Duration::from_secs(2)), 0.5);
+    }
+
+    // A long climb at full output mustn't leave an integral that overshoots once there
+    #[test]
+    fn integral_does_not_wind_up_while_saturated() {
+        let mut pid = Pid::new(config(0.3, 0.01, 0.0));
+        let start = Instant::now();
+        for s in 0..3600 {
+            pid.update(80.0, 20.0, start + Duration::from_secs(s));
+        }
+        assert_eq!(pid.integral, 0.0);
+        // At the setpoint only the integral is left
+        let at = pid.update(80.0, 80.0, start + Duration::from_secs(3600));
+        assert_eq!(at, 0.0);
+    }
+
+    #[test]
+    fn integral_builds_while_unsaturated() {
+        let mut pid = Pid::new(config(0.1, 0.001, 0.0));
+        let start = Instant::now();
+        for s in 0..100 {
+            pid.update(50.0, 49.0, start + Duration::from_secs(s));
+        }
+        // 99s of 1C error
+        assert!((pid.integral - 0.099).abs() < 1e-4, "{}", pid.integral);
+    }
+
+    #[test]
+    fn derivative_is_on_measurement() {
+        let mut pid = Pid::new(config(0.0, 0.0, 10.0));
+        let start = Instant::now();
+        pid.update(50.0, 40.0, start);
+        // A setpoint change alone doesn't move the output
+        assert_eq!(pid.update(60.0, 40.0, start + Duration::from_secs(1)), 0.0);
+        // Falling 0.05C a second pushes it up
+        let out = pid.update(60.0, 39.95, start + Duration::from_secs(2));
+        assert!((out - 0.5).abs() < 1e-3, "{out}");
+    }
+
+    #[test]
+    fn gains_change_without_a_bump() {
+        let mut pid = Pid::new(config(0.0, 0.01, 0.0));
+        let start = Instant::now();
+        for s in 0..10 {
+            pid.update(50.0, 49.0, start + Duration::from_secs(s));
+        }
+        let before = pid.output();
+        pid.set_gains(PidGains {
+            kp: 0.0,
+            ki: 0.05,
+            kd: 0.0,
+        });
+        let after = pid.update(50.0, 50.0, start + Duration::from_secs(10));
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn proportioner_windows() {
+        let window = Duration::from_secs(10);
+        let mut pwm = TimeProportioner::new(window, Duration::from_millis(500));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(pwm.output(0.3, at(0)));
+        assert!(pwm.output(0.3, at(2900)));
+        assert!(!pwm.output(0.3, at(3100)));
+        // The duty is only picked up at the next window
+        assert!(!pwm.output(1.0, at(9000)));
+        assert!(pwm.output(1.0, at(10_000)));
+        assert!(pwm.output(1.0, at(19_900)));
+    }
+
+    #[test]
+    fn proportioner_skips_short_pulses() {
+        let mut pwm = TimeProportioner::new(Duration::from_secs(10), Duration::from_millis(500));
+        let start = Instant::now();
+        // 0.2s on is skipped
+        assert!(!pwm.output(0.02, start));
+        // 0.2s off is run flat out
+        let mut pwm = TimeProportioner::new(Duration::from_secs(10), Duration::from_millis(500));
+        assert!(pwm.output(0.98, start + Duration::from_millis(9900)));
+        assert!(pwm.output(0.98, start + Duration::from_millis(19_850)));
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// How flat the humidity has to be to call the filament dry
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PlateauConfig {
+    // The RH has to hold for this long
+    pub window_minutes: u64,
+    // Highest minus lowest RH over the window, anything more and it is still falling
+    pub band: f32,
+}
+
+impl Default for PlateauConfig {
+    fn default() -> Self {
+        Self {
+            window_minutes: 30,
+            band: 1.0,
+        }
+    }
+}
+
+impl PlateauConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_minutes * 60)
+    }
+}
+
+// Rolling window of readings, just long enough to look back over the whole window
+#[derive(Debug)]
+pub struct History {
+    window: Duration,
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl History {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn push(&mut self, value: f32, now: Instant) {
+        self.samples.push_back((now, value));
+        // Keep one sample from before the window so it is known to be covered
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(time, _)| now - *time >= self.window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    // Lowest and highest over the window, None until the history goes back that far
+    pub fn range(&self, now: Instant) -> Option<(f32, f32)> {
+        let (oldest, _) = self.samples.front()?;
+        if now - *oldest < self.window {
+            return None;
+        }
+        Some(
+            self.samples
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), (_, value)| {
+                    (lo.min(*value), hi.max(*value))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn nothing_until_the_window_is_covered() {
+        let start = Instant::now();
+        let mut history = History::new(10 * MINUTE);
+
+        assert_eq!(history.range(start), None);
+        history.push(15.0, start);
+        history.push(14.0, start + 9 * MINUTE);
+        assert_eq!(history.range(start + 9 * MINUTE), None);
+        // A whole window back to the first sample
+        history.push(14.5, start + 10 * MINUTE);
+        assert_eq!(history.range(start + 10 * MINUTE), Some((14.0, 15.0)));
+    }
+
+    #[test]
+    fn old_samples_drop_out() {
+        let start = Instant::now();
+        let mut history = History::new(10 * MINUTE);
+
+        history.push(30.0, start);
+        history.push(20.0, start + 5 * MINUTE);
+        history.push(12.0, start + 10 * MINUTE);
+        history.push(12.5, start + 15 * MINUTE);
+        // The 30 is more than a window before the 20, which still covers the window
+        assert_eq!(history.range(start + 15 * MINUTE), Some((12.0, 20.0)));
+        history.push(12.2, start + 20 * MINUTE);
+        assert_eq!(history.range(start + 20 * MINUTE), Some((12.0, 12.5)));
+    }
+
+    #[test]
+    fn clear_starts_the_window_again() {
+        let start = Instant::now();
+        let mut history = History::new(10 * MINUTE);
+
+        history.push(12.0, start);
+        history.push(12.0, start + 10 * MINUTE);
+        assert!(history.range(start + 10 * MINUTE).is_some());
+        history.clear();
+        history.push(12.0, start + 11 * MINUTE);
+        assert_eq!(history.range(start + 11 * MINUTE), None);
+    }
+}
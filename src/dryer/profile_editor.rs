@@ -0,0 +1,247 @@
+use std::{borrow::Cow, time::Duration};
+
+use crate::dryer::dry_table::{
+    _Material, DryTable, MAX_NAME, MAX_TIME, MIN_TEMP, Material, Plateau, ProfileKind, Stage,
+};
+use crate::dryer::glyph::{self, DEGREE, LEFT, RIGHT};
+
+// What the knob steps through when picking a letter, the last one ends the name
+const CHARSET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789 -+._/~";
+const END: char = '~';
+const TIME_STEP: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // Picking the profile to start from, index into the DryTable
+    Source(usize),
+    Name,
+    Temp,
+    Time,
+    Save,
+}
+
+// Makes a new profile on the device by copying one and changing it
+// Confirm moves forward and back moves back, left and right change whatever is shown
+// Saving over a custom profile with the same name is how custom profiles get edited
+// Temp and time are the main soak's, any other stages are copied as they are
+#[derive(Debug)]
+pub struct ProfileEditor {
+    step: Step,
+    // The profile being copied, kept for backing out of the name
+    source: usize,
+    // While on the name step it ends with END unless the name is full
+    // Characters rather than bytes, a copied name can have letters from outside ASCII
+    name: Vec<char>,
+    cursor: usize,
+    stages: Vec<Stage>,
+    // Index of the soak being changed
+    main: usize,
+    temp: u32,
+    time: Duration,
+    store_rh: Option<f32>,
+    plateau: Option<Plateau>,
+    max_temp: Option<u32>,
+    kind: ProfileKind,
+}
+
+impl ProfileEditor {
+    pub fn new() -> Self {
+        Self {
+            step: Step::Source(0),
+            source: 0,
+            name: Vec::new(),
+            cursor: 0,
+            stages: Vec::new(),
+            main: 0,
+            temp: 0,
+            time: Duration::ZERO,
+            store_rh: None,
+            plateau: None,
+            max_temp: None,
+            kind: ProfileKind::Dry,
+        }
+    }
+
+    pub fn right(&mut self, table: &DryTable) {
+        self.turn(table, true);
+    }
+
+    pub fn left(&mut self, table: &DryTable) {
+        self.turn(table, false);
+    }
+
+    fn turn(&mut self, table: &DryTable, up: bool) {
+        match self.step {
+            Step::Source(i) => {
+                let next = if up { i + 1 } else { i + table.len() - 1 };
+                self.step = Step::Source(next % table.len().max(1));
+            }
+            Step::Name => {
+                let was_end = self.name[self.cursor] == END;
+                let charset: Vec<char> = CHARSET.chars().collect();
+                let current = charset.iter().position(|c| *c == self.name[self.cursor]);
+                let next = match (current, up) {
+                    (Some(i), true) => (i + 1) % charset.len(),
+                    (Some(i), false) => (i + charset.len() - 1) % charset.len(),
+                    (None, _) => 0,
+                };
+                self.name[self.cursor] = charset[next];
+                // Typing over the end moves it along
+                if was_end && self.cursor + 1 == self.name.len() && self.name.len() < MAX_NAME {
+                    self.name.push(END);
+                }
+            }
+            Step::Temp => {
+                // Under the chamber limit and the copied profile's own
+                let max = (table.max_temp().ceil() as u32)
+                    .saturating_sub(1)
+                    .min(self.max_temp.map_or(u32::MAX, |max| max.saturating_sub(1)));
+                let temp = if up {
+                    self.temp + 1
+                } else {
+                    self.temp.saturating_sub(1)
+                };
+                self.temp = temp.min(max).max(MIN_TEMP);
+            }
+            Step::Time => {
+                let time = if up {
+                    self.time + TIME_STEP
+                } else {
+                    self.time.saturating_sub(TIME_STEP)
+                };
+                // Snap to the step so odd times from a copied profile line up
+                let steps = (time.as_secs() / TIME_STEP.as_secs()).max(1) as u32;
+                self.time = (TIME_STEP * steps).min(MAX_TIME);
+            }
+            Step::Save => {}
+        }
+    }
+
+    // Moves on to the next step, returns the profile to save after the last one
+    pub fn confirm(&mut self, table: &DryTable) -> Option<_Material> {
+        match self.step {
+            Step::Source(i) => {
+                self.source = i;
+                let source = table.get(Material::Profile(i));
+                self.name = source.name.chars().take(MAX_NAME).collect();
+                if self.name.len() < MAX_NAME {
+                    self.name.push(END);
+                }
+                self.cursor = 0;
+                self.stages = source.stages.to_vec();
+                self.main = source.main_soak().unwrap_or(0);
+                self.store_rh = source.store_rh;
+                self.plateau = source.plateau;
+                self.max_temp = source.max_temp;
+                self.kind = source.kind;
+                if let Some(Stage::Soak { temp, time }) = self.stages.get(self.main) {
+                    (self.temp, self.time) = (*temp, *time);
+                }
+                self.step = Step::Name;
+            }
+            Step::Name => {
+                if self.name[self.cursor] == END {
+                    // A name has to have something in it
+                    if self.name[..self.cursor].iter().all(|c| *c == ' ') {
+                        return None;
+                    }
+                    self.name.truncate(self.cursor);
+                    self.step = Step::Temp;
+                } else if self.cursor + 1 == self.name.len() {
+                    // Full, no room for an end
+                    self.step = Step::Temp;
+                } else {
+                    self.cursor += 1;
+                }
+            }
+            Step::Temp => self.step = Step::Time,
+            Step::Time => self.step = Step::Save,
+            Step::Save => {
+                let mut stages = self.stages.clone();
+                if let Some(Stage::Soak { temp, time }) = stages.get_mut(self.main) {
+                    // A ramp straight into the soak goes to the new temp with it
+                    let old = std::mem::replace(temp, self.temp);
+                    *time = self.time;
+                    if let Some(i) = self.main.checked_sub(1)
+                        && let Stage::Ramp { temp, .. } = &mut stages[i]
+                        && *temp == old
+                    {
+                        *temp = self.temp;
+                    }
+                }
+                return Some(_Material {
+                    name: Cow::Owned(self.name()),
+                    kind: self.kind,
+                    stages: Cow::Owned(stages),
+                    store_rh: self.store_rh,
+                    // A shorter soak can't wait as long before ending early
+                    plateau: self.plateau.map(|plateau| Plateau {
+                        min_time: plateau.min_time.min(self.time),
+                        ..plateau
+                    }),
+                    max_temp: self.max_temp,
+                });
+            }
+        }
+        None
+    }
+
+    // Goes back a step, returns true when backing out of the editor
+    pub fn back(&mut self) -> bool {
+        match self.step {
+            Step::Source(_) => return true,
+            Step::Name if self.cursor == 0 => self.step = Step::Source(self.source),
+            Step::Name => self.cursor -= 1,
+            Step::Temp => {
+                // Back on the end of the name, ready to carry on typing
+                if self.name.len() < MAX_NAME {
+                    self.name.push(END);
+                }
+                self.cursor = self.name.len() - 1;
+                self.step = Step::Name;
+            }
+            Step::Time => self.step = Step::Temp,
+            Step::Save => self.step = Step::Time,
+        }
+        false
+    }
+
+    // Only finished once past the name step
+    fn name(&self) -> String {
+        String::from(self.name.iter().collect::<String>().trim())
+    }
+
+    // Both LCD lines for the current step, cols is the width of the display
+    pub fn lines(&self, table: &DryTable, cols: usize) -> (String, String) {
+        let time = |time: Duration| {
+            let minutes = time.as_secs() / 60;
+            format!("{}h{:02}m", minutes / 60, minutes % 60)
+        };
+        match self.step {
+            Step::Source(i) => (
+                String::from("Copy from"),
+                glyph::choice(&table.get(Material::Profile(i)).name, cols),
+            ),
+            // The end shows as an arrow, the ^ sits under the letter being changed
+            Step::Name => (
+                self.name
+                    .iter()
+                    .map(|&c| if c == END { RIGHT } else { c })
+                    .collect(),
+                format!("{:>1$}", "^", self.cursor + 1),
+            ),
+            Step::Temp => (
+                self.name(),
+                format!("Temp {LEFT}{:>3}{DEGREE}C{RIGHT}", self.temp),
+            ),
+            Step::Time => (
+                self.name(),
+                format!("Time {LEFT}{}{RIGHT}", time(self.time)),
+            ),
+            Step::Save => (
+                self.name(),
+                format!("Save? {}{DEGREE}C {}", self.temp, time(self.time)),
+            ),
+        }
+    }
+}
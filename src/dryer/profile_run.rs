@@ -0,0 +1,440 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dryer::dry_table::{self, _Material, Stage};
+use crate::dryer::plateau::{History, PlateauConfig};
+
+// What the stage a run is on wants from the heater
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    // Run the PID to this setpoint
+    Heat(f32),
+    // Heater off, fan on
+    Cool,
+}
+
+// Enough to pick a run up again after a restart
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunState {
+    pub stage: usize,
+    // Seconds into the stage
+    pub elapsed: u64,
+    // Setpoint the stage started from
+    pub from: f32,
+    pub paused: bool,
+}
+
+// Where a run is in its profile
+// The profile is copied in so saving over it mid-run doesn't change the run
+#[derive(Debug)]
+pub struct ProfileRun {
+    material: _Material,
+    stage: usize,
+    stage_started: Instant,
+    // Setpoint the stage started from, a ramp moves away from it
+    from: f32,
+    // The soak the plateau can cut short, with the humidity since it started
+    main: Option<usize>,
+    band: f32,
+    history: History,
+    // When it was paused, the clock stands still from here until it is resumed
+    paused: Option<Instant>,
+    // Whole profile as guessed at the start, for the progress bar
+    length: Duration,
+}
+
+impl ProfileRun {
+    // temp is the chamber now, a ramp at the start climbs from there
+    pub fn new(material: &_Material, config: PlateauConfig, temp: f32, now: Instant) -> Self {
+        if let [first, _, ..] = &*material.stages {
+            println!("Stage 1/{}: {}", material.stages.len(), first.name());
+        }
+        Self {
+            material: material.clone(),
+            stage: 0,
+            stage_started: now,
+            from: temp,
+            main: material.main_soak(),
+            band: config.band,
+            history: History::new(config.window()),
+            paused: None,
+            length: material.time(temp),
+        }
+    }
+
+    // Picks a run up from a checkpoint, skipping any stages the elapsed time has run past
+    pub fn restore(
+        material: _Material,
+        config: PlateauConfig,
+        state: RunState,
+        now: Instant,
+    ) -> Self {
+        // The stages already done are guessed from where the one it was on started
+        let length = material.time(state.from);
+        let RunState {
+            mut stage,
+            elapsed,
+            mut from,
+            paused,
+        } = state;
+        let mut elapsed = Duration::from_secs(elapsed);
+        while let Some(done) = material.stages.get(stage)
+            && elapsed >= done.time(from)
+        {
+            elapsed -= done.time(from);
+            from = done.temp().map_or(from, |temp| temp as f32);
+            stage += 1;
+        }
+        if let Some(current) = material.stages.get(stage)
+            && material.stages.len() > 1
+        {
+            println!(
+                "Stage {}/{}: {}",
+                stage + 1,
+                material.stages.len(),
+                current.name()
+            );
+        }
+        Self {
+            length,
+            main: material.main_soak(),
+            material,
+            stage,
+            stage_started: now.checked_sub(elapsed).unwrap_or(now),
+            from,
+            band: config.band,
+            history: History::new(config.window()),
+            paused: paused.then_some(now),
+        }
+    }
+
+    pub fn state(&self, now: Instant) -> RunState {
+        RunState {
+            stage: self.stage,
+            elapsed: (self.clock(now) - self.stage_started).as_secs(),
+            from: self.from,
+            paused: self.is_paused(),
+        }
+    }
+
+    pub fn material(&self) -> &_Material {
+        &self.material
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        if self.paused.is_none() {
+            println!("Run paused");
+            self.paused = Some(now);
+        }
+    }
+
+    // Carries on from where it was paused, the time spent paused doesn't count
+    pub fn resume(&mut self, now: Instant) {
+        if let Some(paused) = self.paused.take() {
+            println!("Run resumed after {}s", (now - paused).as_secs());
+            self.stage_started += now - paused;
+            // Humidity from while the heater was off says nothing about the filament
+            self.history.clear();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    // The run's own clock, stopped while paused
+    fn clock(&self, now: Instant) -> Instant {
+        self.paused.unwrap_or(now)
+    }
+
+    // Moves on when a stage is done, None once the last one is
+    // temp and hum are the chamber now
+    pub fn update(&mut self, temp: f32, hum: f32, now: Instant) -> Option<Output> {
+        while let Some(&stage) = self.material.stages.get(self.stage)
+            && (now - self.stage_started >= stage.time(self.from) || self.dry(hum, now))
+        {
+            self.history.clear();
+            // After cooling there is no setpoint to carry on from, only the chamber
+            self.from = stage.temp().map_or(temp, |temp| temp as f32);
+            self.stage += 1;
+            self.stage_started = now;
+            if let Some(next) = self.material.stages.get(self.stage) {
+                println!(
+                    "Stage {}/{}: {}",
+                    self.stage + 1,
+                    self.material.stages.len(),
+                    next.name()
+                );
+            }
+        }
+        self.output(now)
+    }
+
+    // What the current stage wants right now, None once the run is over
+    pub fn output(&self, now: Instant) -> Option<Output> {
+        let stage = self.material.stages.get(self.stage)?;
+        Some(match *stage {
+            Stage::Ramp { temp, rate } => {
+                let moved = rate * (self.clock(now) - self.stage_started).as_secs_f32() / 60.0;
+                let temp = temp as f32;
+                Output::Heat(if temp > self.from {
+                    (self.from + moved).min(temp)
+                } else {
+                    (self.from - moved).max(temp)
+                })
+            }
+            Stage::Soak { temp, .. } => Output::Heat(temp as f32),
+            Stage::Cool { .. } => Output::Cool,
+        })
+    }
+
+    // Whether the main soak can end, the humidity has to have settled under the plateau rh
+    // for the whole window and the soak has to have run its minimum
+    fn dry(&mut self, hum: f32, now: Instant) -> bool {
+        let Some(plateau) = self.material.plateau else {
+            return false;
+        };
+        if self.main != Some(self.stage) {
+            return false;
+        }
+        self.history.push(hum, now);
+        let Some((low, high)) = self.history.range(now) else {
+            return false;
+        };
+        let dry = now - self.stage_started >= plateau.min_time
+            && high < plateau.rh
+            && high - low <= self.band;
+        if dry {
+            println!("Humidity settled at {hum:.1}%rh, soak done early");
+        }
+        dry
+    }
+
+    // Stage being run and its number from 1, and how many there are
+    pub fn stage(&self) -> Option<(Stage, usize, usize)> {
+        let stage = self.material.stages.get(self.stage)?;
+        Some((*stage, self.stage + 1, self.material.stages.len()))
+    }
+
+    pub fn stage_left(&self, now: Instant) -> Duration {
+        match self.material.stages.get(self.stage) {
+            Some(stage) => stage
+                .time(self.from)
+                .saturating_sub(self.clock(now) - self.stage_started),
+            None => Duration::ZERO,
+        }
+    }
+
+    // This stage and the ones after it, ramps still to come are guessed from the setpoints
+    pub fn total_left(&self, now: Instant) -> Duration {
+        let Some(stage) = self.material.stages.get(self.stage) else {
+            return Duration::ZERO;
+        };
+        let from = stage.temp().map_or(self.from, |temp| temp as f32);
+        self.stage_left(now) + dry_table::time(&self.material.stages[self.stage + 1..], from)
+    }
+
+    // How far through the whole profile it is, 0 to 1
+    pub fn progress(&self, now: Instant) -> f32 {
+        let left = self.total_left(now).as_secs_f32();
+        // A slow ramp can make it longer than it looked at the start
+        let length = self.length.as_secs_f32().max(left);
+        if length > 0.0 {
+            1.0 - left / length
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    // Up to 60C at 2C a minute, half an hour there, then ten minutes cooling
+    fn material() -> _Material {
+        _Material {
+            stages: Cow::Owned(vec![
+                Stage::Ramp {
+                    temp: 60,
+                    rate: 2.0,
+                },
+                Stage::Soak {
+                    temp: 60,
+                    time: 30 * MINUTE,
+                },
+                Stage::Cool { time: 10 * MINUTE },
+            ]),
+            .._Material::NONE
+        }
+    }
+
+    #[test]
+    fn ramp_climbs_at_its_rate_from_the_chamber() {
+        let start = Instant::now();
+        let mut run = ProfileRun::new(&material(), PlateauConfig::default(), 20.0, start);
+
+        assert_eq!(run.update(20.0, 50.0, start), Some(Output::Heat(20.0)));
+        assert_eq!(
+            run.update(30.0, 50.0, start + 10 * MINUTE),
+            Some(Output::Heat(40.0))
+        );
+        // 40C at 2C a minute
+        assert_eq!(run.stage_left(start + 10 * MINUTE), 10 * MINUTE);
+        assert_eq!(run.total_left(start), 60 * MINUTE);
+    }
+
+    #[test]
+    fn ramp_down_stops_at_its_temp() {
+        let start = Instant::now();
+        let material = _Material {
+            stages: Cow::Owned(vec![Stage::Ramp {
+                temp: 40,
+                rate: 4.0,
+            }]),
+            .._Material::NONE
+        };
+        let run = ProfileRun::new(&material, PlateauConfig::default(), 80.0, start);
+
+        assert_eq!(run.output(start + 5 * MINUTE), Some(Output::Heat(60.0)));
+        assert_eq!(run.output(start + 20 * MINUTE), Some(Output::Heat(40.0)));
+        assert_eq!(run.stage_left(start), 10 * MINUTE);
+    }
+
+    #[test]
+    fn stages_advance_in_order() {
+        let start = Instant::now();
+        let mut run = ProfileRun::new(&material(), PlateauConfig::default(), 20.0, start);
+
+        let soak = start + 20 * MINUTE;
+        assert_eq!(run.update(60.0, 50.0, soak), Some(Output::Heat(60.0)));
+        assert!(matches!(run.stage(), Some((Stage::Soak { .. }, 2, 3))));
+        assert_eq!(run.total_left(soak), 40 * MINUTE);
+
+        let cool = soak + 30 * MINUTE;
+        assert_eq!(run.update(60.0, 50.0, cool), Some(Output::Cool));
+        assert!(matches!(run.stage(), Some((Stage::Cool { .. }, 3, 3))));
+
+        assert_eq!(run.update(30.0, 50.0, cool + 10 * MINUTE), None);
+        assert_eq!(run.stage(), None);
+        assert_eq!(run.total_left(cool + 10 * MINUTE), Duration::ZERO);
+    }
+
+    #[test]
+    fn a_late_update_skips_finished_stages() {
+        let start = Instant::now();
+        let mut run = ProfileRun::new(&material(), PlateauConfig::default(), 20.0, start);
+
+        // Each stage is timed from the update that started it, so a long gap ends the ramp and
+        // starts the soak then
+        let late = start + 25 * MINUTE;
+        assert_eq!(run.update(60.0, 50.0, late), Some(Output::Heat(60.0)));
+        assert_eq!(run.stage_left(late), 30 * MINUTE);
+    }
+
+    #[test]
+    fn plateau_ends_the_main_soak_early() {
+        let start = Instant::now();
+        let material = _Material {
+            stages: Cow::Owned(vec![
+                Stage::Soak {
+                    temp: 60,
+                    time: 240 * MINUTE,
+                },
+                Stage::Cool { time: 10 * MINUTE },
+            ]),
+            plateau: Some(dry_table::Plateau {
+                rh: 15.0,
+                min_time: 60 * MINUTE,
+            }),
+            .._Material::NONE
+        };
+        let mut run = ProfileRun::new(&material, PlateauConfig::default(), 60.0, start);
+
+        // Settled under 15% from the start, but the soak has to run its minimum
+        for minute in 0..60 {
+            let hum = if minute % 2 == 0 { 12.0 } else { 12.5 };
+            let now = start + minute * MINUTE;
+            assert_eq!(
+                run.update(60.0, hum, now),
+                Some(Output::Heat(60.0)),
+                "{minute}"
+            );
+        }
+        assert_eq!(
+            run.update(60.0, 12.0, start + 60 * MINUTE),
+            Some(Output::Cool)
+        );
+    }
+
+    #[test]
+    fn plateau_waits_for_the_rh_to_settle() {
+        let start = Instant::now();
+        let material = _Material {
+            stages: Cow::Owned(vec![Stage::Soak {
+                temp: 60,
+                time: 240 * MINUTE,
+            }]),
+            plateau: Some(dry_table::Plateau {
+                rh: 15.0,
+                min_time: Duration::ZERO,
+            }),
+            .._Material::NONE
+        };
+        let mut run = ProfileRun::new(&material, PlateauConfig::default(), 60.0, start);
+
+        // Flat but over the plateau rh, then under it but still falling
+        for minute in 0..60 {
+            let hum = if minute < 30 {
+                20.0
+            } else {
+                15.0 - (minute - 30) as f32 * 0.1
+            };
+            let now = start + minute * MINUTE;
+            assert!(run.update(60.0, hum, now).is_some(), "{minute}");
+        }
+        for minute in 60..80 {
+            let now = start + minute * MINUTE;
+            assert!(run.update(60.0, 12.0, now).is_some(), "{minute}");
+        }
+        // Half an hour back it was down to 13%, within the 1% band of where it settled
+        assert_eq!(run.update(60.0, 12.0, start + 80 * MINUTE), None);
+    }
+
+    #[test]
+    fn paused_time_doesnt_count() {
+        let start = Instant::now();
+        let mut run = ProfileRun::new(&material(), PlateauConfig::default(), 20.0, start);
+
+        let paused = start + 10 * MINUTE;
+        run.update(40.0, 50.0, paused);
+        run.pause(paused);
+        assert!(run.is_paused());
+        // The clock stands still, the ramp and the time left with it
+        let later = paused + 60 * MINUTE;
+        assert_eq!(run.output(later), Some(Output::Heat(40.0)));
+        assert_eq!(run.stage_left(later), 10 * MINUTE);
+        assert_eq!(run.total_left(later), 50 * MINUTE);
+
+        // Pausing again doesn't move when it was paused
+        run.pause(later);
+        run.resume(later);
+        assert!(!run.is_paused());
+        assert_eq!(run.output(later), Some(Output::Heat(40.0)));
+        assert_eq!(
+            run.update(50.0, 50.0, later + 5 * MINUTE),
+            Some(Output::Heat(50.0))
+        );
+        // The ramp ends 10 minutes after it was resumed, not 10 minutes after it was paused
+        assert!(matches!(
+            run.update(60.0, 50.0, later + 10 * MINUTE),
+            Some(Output::Heat(_))
+        ));
+        assert!(matches!(run.stage(), Some((Stage::Soak { .. }, 2, 3))));
+        assert_eq!(run.stage_left(later + 10 * MINUTE), 30 * MINUTE);
+    }
+}
@@ -0,0 +1,266 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+
+// How hard to try before a sensor read counts as failed
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    // Total attempts, including the first
+    pub attempts: u32,
+    // Wait before the first retry, doubles after every failed retry up to max_backoff_ms
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Readings outside these are treated as bad data and retried
+    // max_temp has to be over the safety overtemp, or a chamber that hot would only show as a failed sensor
+    pub min_temp: f32,
+    pub max_temp: f32,
+    pub min_hum: f32,
+    pub max_hum: f32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff_ms: 50,
+            max_backoff_ms: 500,
+            // Colder than the room it is in or past the top of the SHT3x range means something is wrong
+            min_temp: -10.0,
+            max_temp: 125.0,
+            min_hum: 0.0,
+            max_hum: 100.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self) -> Duration {
+        Duration::from_millis(self.backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.backoff_ms > self.max_backoff_ms {
+            return Err(format!(
+                "sensor_retry backoff_ms {} is over max_backoff_ms {}",
+                self.backoff_ms, self.max_backoff_ms
+            ));
+        }
+        // False for NaN too
+        let ordered = self.min_temp < self.max_temp && self.min_hum < self.max_hum;
+        if !ordered {
+            return Err(String::from(
+                "sensor_retry min_temp and min_hum have to be under max_temp and max_hum",
+            ));
+        }
+        Ok(())
+    }
+
+    // Reads the sensor until it gives a believable reading or runs out of attempts
+    // The last error is returned if every attempt fails
+    pub fn read<I: I2cBus, S: TempHumSensor<I> + ?Sized>(
+        &self,
+        sensor: &mut S,
+        i2c: &mut I,
+    ) -> Result<Reading, SensorError> {
+        let mut backoff = self.backoff();
+        let mut result = Err(SensorError::Nack);
+
+        for attempt in 0..self.attempts.max(1) {
+            if attempt > 0 {
+                i2c.delay(backoff);
+                backoff = (backoff * 2).min(self.max_backoff());
+            }
+
+            result = sensor
+                .read(i2c)
+                .and_then(|reading| self.check_range(reading));
+            match &result {
+                Ok(_) => return result,
+                // The last failure is up to the caller to report
+                Err(e) if attempt + 1 < self.attempts => {
+                    println!("Sensor read attempt {} failed, retrying: {e}", attempt + 1);
+                }
+                Err(_) => {}
+            }
+        }
+
+        result
+    }
+
+    fn check_range(&self, reading: Reading) -> Result<Reading, SensorError> {
+        let temp_ok = (self.min_temp..=self.max_temp).contains(&reading.temp);
+        let hum_ok = (self.min_hum..=self.max_hum).contains(&reading.hum);
+        if temp_ok && hum_ok {
+            Ok(reading)
+        } else {
+            Err(SensorError::OutOfRange(reading))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::hal::BusError;
+
+    // A bus that only remembers how long it was told to wait
+    #[derive(Default)]
+    struct DelayBus {
+        delays: Vec<Duration>,
+    }
+
+    impl I2cBus for DelayBus {
+        fn set_slave_address(&mut self, _addr: u16) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn write(&mut self, _buf: &[u8]) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn read(&mut self, _buf: &mut [u8]) -> Result<(), BusError> {
+            Ok(())
+        }
+        fn delay(&mut self, duration: Duration) {
+            self.delays.push(duration);
+        }
+    }
+
+    // Gives back the scripted results in order, then keeps failing
+    struct Scripted {
+        results: Vec<Result<Reading, SensorError>>,
+        reads: usize,
+    }
+
+    impl Scripted {
+        fn new(mut results: Vec<Result<Reading, SensorError>>) -> Self {
+            results.reverse();
+            Self { results, reads: 0 }
+        }
+    }
+
+    impl TempHumSensor<DelayBus> for Scripted {
+        fn read(&mut self, _i2c: &mut DelayBus) -> Result<Reading, SensorError> {
+            self.reads += 1;
+            self.results.pop().unwrap_or(Err(SensorError::Nack))
+        }
+    }
+
+    const GOOD: Reading = Reading {
+        temp: 45.0,
+        hum: 20.0,
+    };
+
+    #[test]
+    fn first_good_read_is_returned() {
+        let mut sensor = Scripted::new(vec![Ok(GOOD)]);
+        let mut bus = DelayBus::default();
+
+        assert_eq!(RetryPolicy::default().read(&mut sensor, &mut bus), Ok(GOOD));
+        assert_eq!(sensor.reads, 1);
+        assert!(bus.delays.is_empty());
+    }
+
+    #[test]
+    fn retries_until_a_good_read() {
+        let mut sensor = Scripted::new(vec![Err(SensorError::TempCrc), Ok(GOOD)]);
+        let mut bus = DelayBus::default();
+
+        assert_eq!(RetryPolicy::default().read(&mut sensor, &mut bus), Ok(GOOD));
+        assert_eq!(sensor.reads, 2);
+        assert_eq!(bus.delays, vec![Duration::from_millis(50)]);
+    }
+
+    #[test]
+    fn gives_up_with_the_last_error() {
+        let mut sensor = Scripted::new(vec![
+            Err(SensorError::Nack),
+            Err(SensorError::TempCrc),
+            Err(SensorError::HumCrc),
+            Ok(GOOD),
+        ]);
+        let mut bus = DelayBus::default();
+
+        assert_eq!(
+            RetryPolicy::default().read(&mut sensor, &mut bus),
+            Err(SensorError::HumCrc)
+        );
+        assert_eq!(sensor.reads, 3);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let policy = RetryPolicy {
+            attempts: 6,
+            ..RetryPolicy::default()
+        };
+        let mut sensor = Scripted::new(vec![]);
+        let mut bus = DelayBus::default();
+
+        assert!(policy.read(&mut sensor, &mut bus).is_err());
+        let millis: Vec<_> = bus.delays.iter().map(|d| d.as_millis()).collect();
+        assert_eq!(millis, vec![50, 100, 200, 400, 500]);
+    }
+
+    #[test]
+    fn zero_attempts_still_reads_once() {
+        let policy = RetryPolicy {
+            attempts: 0,
+            ..RetryPolicy::default()
+        };
+        let mut sensor = Scripted::new(vec![Ok(GOOD)]);
+
+        assert_eq!(policy.read(&mut sensor, &mut DelayBus::default()), Ok(GOOD));
+        assert_eq!(sensor.reads, 1);
+    }
+
+    #[test]
+    fn out_of_range_readings_are_retried() {
+        let hot = Reading {
+            temp: 150.0,
+            hum: 20.0,
+        };
+        let wet = Reading {
+            temp: 45.0,
+            hum: 101.0,
+        };
+        let mut sensor = Scripted::new(vec![Ok(hot), Ok(wet), Ok(GOOD)]);
+
+        assert_eq!(
+            RetryPolicy::default().read(&mut sensor, &mut DelayBus::default()),
+            Ok(GOOD)
+        );
+
+        let mut sensor = Scripted::new(vec![Ok(hot), Ok(hot), Ok(wet)]);
+        assert_eq!(
+            RetryPolicy::default().read(&mut sensor, &mut DelayBus::default()),
+            Err(SensorError::OutOfRange(wet))
+        );
+    }
+
+    #[test]
+    fn backoff_and_ranges_are_checked() {
+        assert!(RetryPolicy::default().check().is_ok());
+        let bad = [
+            RetryPolicy {
+                backoff_ms: 600,
+                ..RetryPolicy::default()
+            },
+            RetryPolicy {
+                min_temp: 125.0,
+                ..RetryPolicy::default()
+            },
+            RetryPolicy {
+                max_hum: f32::NAN,
+                ..RetryPolicy::default()
+            },
+        ];
+        for policy in bad {
+            assert!(policy.check().is_err(), "{policy:?}");
+        }
+    }
+}
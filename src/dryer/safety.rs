@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+
+use crate::dryer::hal::{Reading, SensorError};
+use crate::dryer::temp_sensor::Role;
+
+// Limits the supervisor enforces, independent of whatever the controller is doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SafetyConfig {
+    // Hard ceiling for any sensor
+    pub max_temp: f32,
+    // Largest difference allowed between the control sensors
+    pub max_sensor_delta: f32,
+    // Fewer working control sensors than this is a fault, otherwise a failed sensor is just left out
+    pub min_sensors: usize,
+    // While the chamber is more than heating_margin below target the heater should be
+    // flat out, and the temperature has to go up by heating_rise every heating_period
+    pub heating_margin: f32,
+    pub heating_rise: f32,
+    pub heating_period: Duration,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            max_temp: 95.0,
+            max_sensor_delta: 10.0,
+            min_sensors: 1,
+            heating_margin: 5.0,
+            heating_rise: 2.0,
+            heating_period: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    SensorLoss,
+    SensorMismatch,
+    NotHeating,
+    OverTemp,
+}
+
+impl Fault {
+    // Short enough to fit after "FAULT " on the LCD
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::SensorLoss => "no sensor",
+            Fault::SensorMismatch => "mismatch",
+            Fault::NotHeating => "no heat",
+            Fault::OverTemp => "overtemp",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SafetySupervisor {
+    config: SafetyConfig,
+    // Start of the current heating watch, time and temperature
+    watch: Option<(Instant, f32)>,
+}
+
+impl SafetySupervisor {
+    pub fn new(config: SafetyConfig) -> Self {
+        Self {
+            config,
+            watch: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.watch = None;
+    }
+
+    // Checks a fresh read of every sensor
+    // Sensor loss and mismatch only count control sensors, over temperature counts
+    // anything in the chamber
+    pub fn check_readings(
+        &self,
+        readings: &[(Role, Result<Reading, SensorError>)],
+    ) -> Result<(), Fault> {
+        let temps = |keep: fn(Role) -> bool| -> Vec<f32> {
+            readings
+                .iter()
+                .filter(|(role, _)| keep(*role))
+                .filter_map(|(_, reading)| reading.as_ref().ok())
+                .map(|reading| reading.temp)
+                .collect()
+        };
+        let control = temps(|role| role == Role::Control);
+        let chamber = temps(|role| role.in_chamber());
+
+        if control.len() < self.config.min_sensors.max(1) {
+            return Err(Fault::SensorLoss);
+        }
+
+        if chamber.iter().any(|temp| *temp > self.config.max_temp) {
+            return Err(Fault::OverTemp);
+        }
+        let hottest = control.iter().copied().fold(f32::MIN, f32::max);
+        let coldest = control.iter().copied().fold(f32::MAX, f32::min);
+        if hottest - coldest > self.config.max_sensor_delta {
+            return Err(Fault::SensorMismatch);
+        }
+
+        Ok(())
+    }
+
+    // Checks that the heater is actually heating
+    // target is None whenever nothing is asking for heat
+    pub fn check_heating(
+        &mut self,
+        temp: f32,
+        target: Option<f32>,
+        now: Instant,
+    ) -> Result<(), Fault> {
+        let heating = target.is_some_and(|target| temp < target - self.config.heating_margin);
+        if !heating {
+            self.watch = None;
+            return Ok(());
+        }
+
+        let (started, start_temp) = *self.watch.get_or_insert((now, temp));
+        if now - started >= self.config.heating_period {
+            if temp < start_temp + self.config.heating_rise {
+                return Err(Fault::NotHeating);
+            }
+            self.watch = Some((now, temp));
+        }
+
+        Ok(())
+    }
+}
+
+// Limits under the overtemp fault, for the enclosure and the spool in it
+// Going over holds the heater off without latching anything, whatever is running carries on
+// once the chamber has cooled a degree under
+#[derive(Debug, Default)]
+pub struct Ceiling {
+    // The limit the chamber is over
+    over: Option<f32>,
+}
+
+impl Ceiling {
+    // hottest is the hottest chamber sensor, limit the lower of the chamber's and the material's
+    // Returns whether the heater has to be off
+    pub fn check(&mut self, hottest: f32, limit: f32) -> bool {
+        let over = match self.over {
+            Some(_) => hottest > limit - 1.0,
+            None => hottest > limit,
+        };
+        match (over, self.over) {
+            (true, None) => {
+                println!("Chamber at {hottest:.1}C is over the {limit}C limit, heater off")
+            }
+            (false, Some(was)) => println!("Chamber back under the {was}C limit"),
+            _ => {}
+        }
+        self.over = over.then_some(limit);
+        over
+    }
+
+    pub fn over(&self) -> Option<f32> {
+        self.over
+    }
+
+    // What the heater can be expected to reach, nothing while it is held off
+    pub fn cap(&self, target: Option<f32>, limit: f32) -> Option<f32> {
+        match self.over {
+            Some(_) => None,
+            None => target.map(|target| target.min(limit)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The ceiling holding the heater off short of the setpoint isn't the heater failing
+    #[test]
+    fn ceiling_hold_is_not_a_heating_fault() {
+        let mut safety = SafetySupervisor::new(SafetyConfig::default());
+        let mut ceiling = Ceiling::default();
+        let start = Instant::now();
+        for minute in 0..30 {
+            let now = start + Duration::from_secs(minute * 60);
+            // Hovering at the 70C limit with an 80C profile
+            let hottest = if minute % 2 == 0 { 70.5 } else { 69.5 };
+            ceiling.check(hottest, 70.0);
+            let target = ceiling.cap(Some(80.0), 70.0);
+            assert_eq!(safety.check_heating(69.0, target, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn cap_clamps_to_the_limit() {
+        let mut ceiling = Ceiling::default();
+        assert_eq!(ceiling.cap(Some(80.0), 70.0), Some(70.0));
+        assert_eq!(ceiling.cap(Some(50.0), 70.0), Some(50.0));
+        assert_eq!(ceiling.cap(None, 70.0), None);
+        ceiling.check(71.0, 70.0);
+        assert_eq!(ceiling.cap(Some(80.0), 70.0), None);
+    }
+
+    #[test]
+    fn stalled_heater_faults() {
+        let mut safety = SafetySupervisor::new(SafetyConfig::default());
+        let start = Instant::now();
+        assert_eq!(safety.check_heating(30.0, Some(60.0), start), Ok(()));
+        let later = start + Duration::from_secs(5 * 60);
+        assert_eq!(
+            safety.check_heating(31.0, Some(60.0), later),
+            Err(Fault::NotHeating)
+        );
+    }
+
+    #[test]
+    fn overtemp_and_mismatch() {
+        let config = SafetyConfig::default();
+        let safety = SafetySupervisor::new(config);
+        let reading = |temp| Ok(Reading { temp, hum: 20.0 });
+        assert_eq!(
+            safety.check_readings(&[(Role::Control, reading(40.0))]),
+            Ok(())
+        );
+        assert_eq!(
+            safety.check_readings(&[(Role::Control, reading(config.max_temp + 1.0))]),
+            Err(Fault::OverTemp)
+        );
+        assert_eq!(
+            safety.check_readings(&[
+                (Role::Control, reading(40.0)),
+                (Role::Control, reading(40.0 + config.max_sensor_delta + 1.0)),
+            ]),
+            Err(Fault::SensorMismatch)
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use chrono::{DateTime, Local, MappedLocalTime, NaiveTime, TimeDelta, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::dryer::checkpoint;
+use crate::dryer::dry_table::Material;
+use crate::dryer::glyph::{self, LEFT, RIGHT};
+
+const MINUTE_STEP: u32 = 15;
+const DAY_MINUTES: u32 = 24 * 60;
+
+// When a run picked from the menu starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum When {
+    Now,
+    StartAt,
+    // Started early enough that the last stage ends at the time
+    FinishBy,
+}
+
+impl When {
+    pub fn name(self) -> &'static str {
+        match self {
+            When::Now => "Start now",
+            When::StartAt => "Start at",
+            When::FinishBy => "Finish by",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            When::Now => When::StartAt,
+            When::StartAt => When::FinishBy,
+            When::FinishBy => When::Now,
+        }
+    }
+
+    fn prev(self) -> Self {
+        match self {
+            When::Now => When::FinishBy,
+            When::StartAt => When::Now,
+            When::FinishBy => When::StartAt,
+        }
+    }
+}
+
+// The start screen, between picking a material and running it
+// Left and right pick when, then the time of day for a start at or finish by
+#[derive(Debug, Clone)]
+pub struct SchedulePicker {
+    material: Material,
+    when: When,
+    // Minutes past midnight, local time
+    minutes: u32,
+    // On the time rather than on when
+    timing: bool,
+}
+
+impl SchedulePicker {
+    // now is the wall clock, the time starts at the next quarter hour
+    pub fn new(material: Material, now: SystemTime) -> Self {
+        let minutes = minute_of_day(now) / MINUTE_STEP * MINUTE_STEP + MINUTE_STEP;
+        Self {
+            material,
+            when: When::Now,
+            minutes: minutes % DAY_MINUTES,
+            timing: false,
+        }
+    }
+
+    pub fn material(&self) -> Material {
+        self.material
+    }
+
+    pub fn right(&mut self) {
+        if self.timing {
+            self.minutes = (self.minutes + MINUTE_STEP) % DAY_MINUTES;
+        } else {
+            self.when = self.when.next();
+        }
+    }
+
+    pub fn left(&mut self) {
+        if self.timing {
+            self.minutes = (self.minutes + DAY_MINUTES - MINUTE_STEP) % DAY_MINUTES;
+        } else {
+            self.when = self.when.prev();
+        }
+    }
+
+    // True once there is nothing left to pick
+    pub fn confirm(&mut self) -> bool {
+        if self.when == When::Now || self.timing {
+            return true;
+        }
+        self.timing = true;
+        false
+    }
+
+    // Steps back off the time, true to leave the screen
+    pub fn back(&mut self) -> bool {
+        if self.timing {
+            self.timing = false;
+            return false;
+        }
+        true
+    }
+
+    pub fn starts_now(&self) -> bool {
+        self.when == When::Now
+    }
+
+    // When a profile this long has to start, Err if it is too late to finish by the time
+    pub fn start(&self, length: Duration, now: SystemTime) -> Result<SystemTime, String> {
+        let at = next_at(self.minutes, now);
+        match self.when {
+            When::Now => Ok(now),
+            When::StartAt => Ok(at),
+            When::FinishBy => at
+                .checked_sub(length)
+                .filter(|start| *start >= now)
+                .ok_or_else(|| format!("Can't make {}", clock(at))),
+        }
+    }
+
+    // length is the profile from the chamber as it is now, cols the width of the display
+    pub fn lines(
+        &self,
+        name: &str,
+        length: Duration,
+        now: SystemTime,
+        cols: usize,
+    ) -> (String, String) {
+        if !self.timing {
+            let minutes = length.as_secs() / 60;
+            return (
+                format!("{name:.10} {}h{:02}m", minutes / 60, minutes % 60),
+                glyph::choice(self.when.name(), cols),
+            );
+        }
+        let time = format!(
+            "{LEFT}{:02}:{:02}{RIGHT}",
+            self.minutes / 60,
+            self.minutes % 60
+        );
+        match (self.when, self.start(length, now)) {
+            (When::FinishBy, Ok(start)) => (
+                format!("Starts {}", clock(start)),
+                format!("Done by {time}"),
+            ),
+            (When::FinishBy, Err(_)) => (String::from("Too late"), format!("Done by {time}")),
+            (_, start) => {
+                let start = start.unwrap_or(now);
+                (
+                    format!("Done at {}", clock(start + length)),
+                    format!("Start at {time}"),
+                )
+            }
+        }
+    }
+}
+
+// A run waiting for its start time, kept on disk so a restart doesn't lose it
+// Only the name is kept, the profile is looked up again when it starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scheduled {
+    pub material: String,
+    // Unix seconds, wall clock so it still means the same time after a restart
+    start_at: u64,
+}
+
+impl Scheduled {
+    pub fn new(material: &str, start: SystemTime) -> Self {
+        Self {
+            material: String::from(material),
+            start_at: start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        checkpoint::write(path, &toml::to_string(self)?)
+    }
+
+    pub fn start(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.start_at)
+    }
+
+    // Zero once it is due
+    pub fn left(&self, now: SystemTime) -> Duration {
+        self.start().duration_since(now).unwrap_or(Duration::ZERO)
+    }
+}
+
+// 23:15 in local time
+pub fn clock(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%H:%M").to_string()
+}
+
+fn minute_of_day(time: SystemTime) -> u32 {
+    let time = DateTime::<Local>::from(time).time();
+    (time - NaiveTime::MIN).num_minutes() as u32
+}
+
+// The next time the local clock reads minutes past midnight, after now
+fn next_at(minutes: u32, now: SystemTime) -> SystemTime {
+    next_in(&Local, minutes, now)
+}
+
+// next_at for any time zone, so the clocks changing can be tried without changing the Pi's
+fn next_in<Tz: TimeZone>(tz: &Tz, minutes: u32, now: SystemTime) -> SystemTime {
+    let local = DateTime::<Utc>::from(now).with_timezone(tz);
+    let time = NaiveTime::MIN + TimeDelta::minutes(minutes as i64);
+    // Today or tomorrow, unless the clocks changing lose it
+    let mut date = local.date_naive();
+    for _ in 0..3 {
+        // A time skipped by the clocks going forward comes an hour later instead
+        let at = date.and_time(time);
+        let found = match at.and_local_timezone(tz.clone()) {
+            MappedLocalTime::None => (at + TimeDelta::hours(1)).and_local_timezone(tz.clone()),
+            found => found,
+        };
+        // One repeated by the clocks going back counts the second time round too
+        if let Some(at) = [found.clone().earliest(), found.latest()]
+            .into_iter()
+            .flatten()
+            .find(|at| *at > local)
+        {
+            return at.into();
+        }
+        date = date.succ_opt().unwrap_or(date);
+    }
+    now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+
+    // UTC in winter, an hour ahead from 01:00 UTC on the last Sunday of March 2024 to 01:00
+    // UTC on the last Sunday of October, like the UK
+    #[derive(Debug, Clone)]
+    struct Uk;
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn system(time: NaiveDateTime) -> SystemTime {
+        time.and_utc().into()
+    }
+
+    impl TimeZone for Uk {
+        type Offset = FixedOffset;
+
+        fn from_offset(_offset: &FixedOffset) -> Self {
+            Uk
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            let summer = FixedOffset::east_opt(3600).unwrap();
+            let winter = FixedOffset::east_opt(0).unwrap();
+            // Each offset is right if going back to UTC with it gives the same offset
+            let fits = |offset: FixedOffset| {
+                self.offset_from_utc_datetime(
+                    &(*local - TimeDelta::seconds(offset.local_minus_utc() as i64)),
+                ) == offset
+            };
+            match (fits(summer), fits(winter)) {
+                (true, true) => MappedLocalTime::Ambiguous(summer, winter),
+                (true, false) => MappedLocalTime::Single(summer),
+                (false, true) => MappedLocalTime::Single(winter),
+                (false, false) => MappedLocalTime::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, time: &NaiveDateTime) -> FixedOffset {
+            let summer = *time >= utc(3, 31, 1, 0) && *time < utc(10, 27, 1, 0);
+            FixedOffset::east_opt(if summer { 3600 } else { 0 }).unwrap()
+        }
+    }
+
+    #[test]
+    fn later_today_or_tomorrow() {
+        // 10:00 local in June
+        let now = system(utc(6, 1, 9, 0));
+        assert_eq!(next_in(&Uk, 11 * 60 + 30, now), system(utc(6, 1, 10, 30)));
+        assert_eq!(next_in(&Uk, 9 * 60, now), system(utc(6, 2, 8, 0)));
+        // The time it is now is tomorrow's
+        assert_eq!(next_in(&Uk, 10 * 60, now), system(utc(6, 2, 9, 0)));
+    }
+
+    // 01:30 never happens on the day the clocks go forward, it comes as 02:30
+    #[test]
+    fn skipped_time_comes_an_hour_later() {
+        let now = system(utc(3, 31, 0, 30));
+        assert_eq!(next_in(&Uk, 90, now), system(utc(3, 31, 1, 30)));
+    }
+
+    // 01:30 happens twice the day the clocks go back, whichever is next counts
+    #[test]
+    fn repeated_time_is_the_first() {
+        let now = system(utc(10, 26, 23, 30));
+        assert_eq!(next_in(&Uk, 90, now), system(utc(10, 27, 0, 30)));
+        // Past the first, the second is still to come
+        let now = system(utc(10, 27, 0, 45));
+        assert_eq!(next_in(&Uk, 90, now), system(utc(10, 27, 1, 30)));
+    }
+
+    #[test]
+    fn across_the_change() {
+        // 22:00 the night before the clocks go forward, 06:00 is 7 hours away not 8
+        let now = system(utc(3, 30, 22, 0));
+        assert_eq!(next_in(&Uk, 6 * 60, now), system(utc(3, 31, 5, 0)));
+    }
+
+    #[test]
+    fn picker_steps_round_the_clock() {
+        let mut picker = SchedulePicker::new(Material::Profile(0), SystemTime::now());
+        assert!(picker.starts_now());
+        picker.right();
+        assert_eq!(picker.when, When::StartAt);
+        assert!(!picker.confirm());
+        picker.minutes = 0;
+        picker.left();
+        assert_eq!(picker.minutes, DAY_MINUTES - MINUTE_STEP);
+        picker.right();
+        assert_eq!(picker.minutes, 0);
+        // Back goes off the time first, then off the screen
+        assert!(!picker.back());
+        assert!(picker.back());
+    }
+
+    #[test]
+    fn finish_by_too_soon() {
+        let mut picker = SchedulePicker::new(Material::Profile(0), SystemTime::now());
+        picker.left();
+        assert_eq!(picker.when, When::FinishBy);
+        let now = SystemTime::now();
+        // Never enough time for two days of drying
+        assert!(picker.start(Duration::from_secs(48 * 3600), now).is_err());
+        // The default time is the next step, so half the wait until it always fits
+        let wait = next_at(picker.minutes, now).duration_since(now).unwrap();
+        let start = picker.start(wait / 2, now).unwrap();
+        assert!(start >= now);
+    }
+
+    #[test]
+    fn scheduled_left() {
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        let scheduled = Scheduled::new("PLA", start);
+        let back: Scheduled = toml::from_str(&toml::to_string(&scheduled).unwrap()).unwrap();
+        assert_eq!(back.start(), start);
+        assert_eq!(
+            back.left(start - Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(back.left(start + Duration::from_secs(60)), Duration::ZERO);
+    }
+}
@@ -1,6 +1,12 @@
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
-use crate::dryer::{HeaterState, display::DisplayState, dry_table::Material};
+use crate::dryer::{
+    HeaterState,
+    display::{DisplayState, RunPhase},
+    dry_table::{_Material, DryTable, Material},
+    profile_editor::ProfileEditor,
+    schedule::SchedulePicker,
+};
 
 #[derive(Debug)]
 pub struct SharedData {
@@ -8,17 +14,59 @@ pub struct SharedData {
     pub display_state: DisplayState,
     pub material: Material,
     pub hovered_material: Material,
-    pub heater_started: Instant,
+    // Everything on the menu, material and hovered_material index into it
+    pub dry_table: DryTable,
+    // Open while the display is on the editor
+    pub editor: Option<ProfileEditor>,
+    // Finished in the editor, waiting for the dryer to save it
+    pub new_profile: Option<_Material>,
+    // Open while the display is on the start screen
+    pub picker: Option<SchedulePicker>,
+    // Picked on the start screen, waiting for the dryer to work out the start time
+    pub new_schedule: Option<SchedulePicker>,
+    // Wall clock as of the last update, the start screen counts from it
+    pub wall_clock: SystemTime,
+    // None until the dryer picks up a newly started run
+    pub heater_started: Option<Instant>,
+    // Set by the dryer once the backlight has timed out
+    pub asleep: bool,
+    // A button was pressed since the dryer last looked, the backlight timeout starts again
+    pub pressed: bool,
 }
 
 impl SharedData {
-    pub fn new() -> Self {
+    pub fn new(dry_table: DryTable) -> Self {
         Self {
             heater_state: HeaterState::Idle,
             display_state: DisplayState::Idle,
             material: Material::None,
             hovered_material: Material::None,
-            heater_started: Instant::now(),
+            dry_table,
+            editor: None,
+            new_profile: None,
+            picker: None,
+            new_schedule: None,
+            wall_clock: SystemTime::now(),
+            heater_started: None,
+            asleep: false,
+            pressed: false,
+        }
+    }
+
+    // Called first by every button, true when the screen was dark and the press only woke it
+    pub fn wake(&mut self) -> bool {
+        self.pressed = true;
+        std::mem::take(&mut self.asleep)
+    }
+
+    // Some while a profile is running or waiting to, or autotune is going
+    pub fn run_phase(&self) -> Option<RunPhase> {
+        match self.heater_state {
+            HeaterState::Waiting => Some(RunPhase::Waiting),
+            HeaterState::Running if self.material != Material::None => Some(RunPhase::Running),
+            HeaterState::Paused => Some(RunPhase::Paused),
+            HeaterState::Autotune => Some(RunPhase::Tuning),
+            _ => None,
         }
     }
 }
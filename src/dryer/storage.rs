@@ -0,0 +1,153 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// Keeping spools dry after a profile finishes, off unless turned on in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub enabled: bool,
+    // RH that starts a burst, for profiles that don't give their own store_rh
+    pub rh: f32,
+    // Setpoint during a burst, warm enough to push the RH down without baking the spool
+    pub temp: f32,
+    // A burst runs for its whole length, warming the air drops the RH long before the
+    // spool has given anything up, so stopping on RH would only cycle the heater
+    pub burst_minutes: u64,
+    // Least time between bursts, however humid it gets
+    pub rest_minutes: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rh: 20.0,
+            temp: 40.0,
+            burst_minutes: 15,
+            rest_minutes: 30,
+        }
+    }
+}
+
+// Sits idle after a profile, heating in short bursts whenever the box gets humid
+#[derive(Debug)]
+pub struct Storage {
+    config: StorageConfig,
+    rh: f32,
+    // Start of the burst running now
+    burst: Option<Instant>,
+    last_burst: Option<Instant>,
+}
+
+impl Storage {
+    // rh is the profile's threshold, or None for the one from the config
+    pub fn new(config: StorageConfig, rh: Option<f32>) -> Self {
+        Self {
+            config,
+            rh: rh.unwrap_or(config.rh),
+            burst: None,
+            last_burst: None,
+        }
+    }
+
+    pub fn rh(&self) -> f32 {
+        self.rh
+    }
+
+    pub fn temp(&self) -> f32 {
+        self.config.temp
+    }
+
+    pub fn heating(&self) -> bool {
+        self.burst.is_some()
+    }
+
+    // Returns whether to heat, starting and ending bursts as it goes
+    pub fn update(&mut self, hum: f32, now: Instant) -> bool {
+        let burst = Duration::from_secs(self.config.burst_minutes * 60);
+        let rest = Duration::from_secs(self.config.rest_minutes * 60);
+        match self.burst {
+            Some(started) if now - started >= burst => {
+                println!("Keep dry: burst done at {hum:.1}%rh");
+                self.burst = None;
+                self.last_burst = Some(now);
+            }
+            None if hum > self.rh && self.last_burst.is_none_or(|last| now - last >= rest) => {
+                println!("Keep dry: {hum:.1}%rh is over {:.0}%rh, heating", self.rh);
+                self.burst = Some(now);
+            }
+            _ => {}
+        }
+        self.burst.is_some()
+    }
+
+    // Time left in the burst running now
+    pub fn burst_left(&self, now: Instant) -> Option<Duration> {
+        let burst = Duration::from_secs(self.config.burst_minutes * 60);
+        self.burst
+            .map(|started| burst.saturating_sub(now - started))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn enabled() -> StorageConfig {
+        StorageConfig {
+            enabled: true,
+            ..StorageConfig::default()
+        }
+    }
+
+    #[test]
+    fn burst_starts_over_the_rh() {
+        let start = Instant::now();
+        let mut storage = Storage::new(enabled(), None);
+
+        assert!(!storage.update(20.0, start));
+        assert!(!storage.heating());
+        assert_eq!(storage.burst_left(start), None);
+        assert!(storage.update(20.5, start));
+        assert!(storage.heating());
+        assert_eq!(storage.temp(), 40.0);
+    }
+
+    #[test]
+    fn profile_rh_wins_over_the_config() {
+        let start = Instant::now();
+        let mut storage = Storage::new(enabled(), Some(10.0));
+
+        assert_eq!(storage.rh(), 10.0);
+        assert!(storage.update(12.0, start));
+    }
+
+    #[test]
+    fn burst_runs_its_length_whatever_the_rh() {
+        let start = Instant::now();
+        let mut storage = Storage::new(enabled(), None);
+
+        assert!(storage.update(30.0, start));
+        // Warming the air drops the RH straight away, the burst carries on
+        assert!(storage.update(10.0, start + 5 * MINUTE));
+        assert_eq!(storage.burst_left(start + 5 * MINUTE), Some(10 * MINUTE));
+        assert!(storage.update(10.0, start + 15 * MINUTE - Duration::from_secs(1)));
+        assert!(!storage.update(10.0, start + 15 * MINUTE));
+        assert!(!storage.heating());
+    }
+
+    #[test]
+    fn rests_between_bursts() {
+        let start = Instant::now();
+        let mut storage = Storage::new(enabled(), None);
+
+        assert!(storage.update(30.0, start));
+        let done = start + 15 * MINUTE;
+        assert!(!storage.update(30.0, done));
+        // Still humid, but not until the rest is over
+        assert!(!storage.update(30.0, done + 29 * MINUTE));
+        assert!(storage.update(30.0, done + 30 * MINUTE));
+    }
+}
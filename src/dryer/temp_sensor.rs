@@ -1,85 +1,133 @@
-use rppal::i2c::I2c;
-use std::thread;
-use std::time::Duration;
+pub mod aht20;
+pub mod bme280;
+pub mod dht22;
+pub mod sht3x;
+pub mod sht4x;
+pub mod tca9548a;
+
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::dryer::hal::{I2cBus, IoPin, TempHumSensor};
+use aht20::Aht20;
+use bme280::Bme280;
+use dht22::Dht22;
+use sht3x::{Sht3x, Sht3xConfig};
+use sht4x::Sht4x;
+use tca9548a::{CHANNELS, MuxChannel, Muxed};
+
+// Which part is fitted and where it is, so one binary runs on any mix of sensors
+// In the config file: type = "sht3x", addr = 0x45 or type = "dht22", pin = 4
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SensorConfig {
+    // 0x44 or 0x45
+    Sht3x { addr: u16 },
+    // 0x44, other part numbers sit at 0x45 or 0x46
+    Sht4x { addr: u16 },
+    // Always at 0x38, so only one per bus
+    Aht20,
+    // 0x76 or 0x77
+    Bme280 { addr: u16 },
+    // Single wire on a GPIO pin, BCM numbering
+    Dht22 { pin: u8 },
+}
 
-#[derive(Debug)]
-pub struct TempSensor {
-    addr: u16,
+// What a sensor is there for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Chamber air, fused into the reading the heater is controlled on
+    #[default]
+    Control,
+    // Next to a spool, shown and logged
+    Spool,
+    // In the air leaving through the vent, shown and logged
+    Exhaust,
+    // Outside the box, shown and logged but never compared against the chamber
+    Ambient,
 }
 
-#[derive(Debug)]
-pub enum SHTAddr {
-    Default,
-    Alternate,
+impl Role {
+    // Ambient is the only one outside the chamber
+    pub fn in_chamber(&self) -> bool {
+        *self != Role::Ambient
+    }
 }
 
-const SHT_DEFAULT_ADDR: u16 = 0x44;
-const SHT_ALTERNATE_ADDR: u16 = 0x45;
+// One [[sensors]] entry in the config file
+// type and its address or pin go in the same table as the name
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SensorSpec {
+    // Shown on the LCD and in the log, keep it short
+    pub name: String,
+    #[serde(default)]
+    pub role: Role,
+    // mux = { addr = 0x70, channel = 2 } for a sensor behind a TCA9548A
+    #[serde(default)]
+    pub mux: Option<MuxChannel>,
+    #[serde(flatten)]
+    pub part: SensorConfig,
+}
 
-impl TempSensor {
-    pub fn new(addr: SHTAddr) -> Self {
+impl SensorSpec {
+    pub fn new(name: &str, role: Role, part: SensorConfig) -> Self {
         Self {
-            addr: match addr {
-                SHTAddr::Default => SHT_DEFAULT_ADDR,
-                SHTAddr::Alternate => SHT_ALTERNATE_ADDR,
-            },
+            name: String::from(name),
+            role,
+            mux: None,
+            part,
         }
     }
+}
 
-    pub fn read(&self, i2c: &mut I2c) -> (f32, f32) {
-        let _ = i2c.set_slave_address(self.addr);
-
-        // High repeatability, single shot measure command
-        // Clock stretching disable bc Pi doesn't support properly
-        let cmd: [u8; 2] = [0x24, 0x00];
-
-        let mut buf = [0u8; 6];
-
-        // Send cmd
-        let _ = i2c.write(&cmd);
-
-        // Wait for sensor to take measurment
-        thread::sleep(Duration::from_millis(20));
-
-        // Data format is temp MSB, temp LSB, CRC, Hum MSB, Hum LSB, CRC
-        let _ = i2c.read(&mut buf);
-
-        if !TempSensor::crc(&buf[0..2], buf[2]) {
-            println!("Temperature CRC not valid");
+// Makes the driver for a sensor
+// pin turns a BCM pin number into a GPIO pin, only single wire parts use it
+pub fn build<I: I2cBus + 'static, P: IoPin + 'static>(
+    spec: &SensorSpec,
+    sht3x: Sht3xConfig,
+    pin: impl FnOnce(u8) -> Result<P, Box<dyn Error>>,
+) -> Result<Box<dyn TempHumSensor<I>>, Box<dyn Error>> {
+    let sensor: Box<dyn TempHumSensor<I>> = match spec.part {
+        SensorConfig::Sht3x { addr } => Box::new(Sht3x::new(addr, sht3x)),
+        SensorConfig::Sht4x { addr } => Box::new(Sht4x::new(addr)),
+        SensorConfig::Aht20 => Box::new(Aht20::new()),
+        SensorConfig::Bme280 { addr } => Box::new(Bme280::new(addr)),
+        SensorConfig::Dht22 { pin: bcm_pin } => Box::new(Dht22::new(pin(bcm_pin)?)),
+    };
+
+    Ok(match spec.mux {
+        None => sensor,
+        Some(_) if matches!(spec.part, SensorConfig::Dht22 { .. }) => {
+            return Err(format!("{}: a DHT22 can't go behind an I2C mux", spec.name).into());
         }
-
-        if !TempSensor::crc(&buf[3..5], buf[5]) {
-            println!("Humidity CRC not valid");
+        Some(mux) if mux.channel >= CHANNELS => {
+            return Err(format!("{}: mux channel {} doesn't exist", spec.name, mux.channel).into());
         }
+        Some(mux) => Box::new(Muxed::new(mux, sensor)),
+    })
+}
 
-        let temp_raw = u16::from_be_bytes([buf[0], buf[1]]);
-        let hum_raw = u16::from_be_bytes([buf[3], buf[4]]);
-
-        // -45 + 175 * (temp / (2^16-1))
-        let temperature = -45.0 + 175.0 * (temp_raw as f32) / 65535.0;
-
-        // 100 * (hum / (2^16-1))
-        let humidity = 100.0 * (hum_raw as f32) / 65535.0;
-
-        (temperature, humidity)
-    }
-
-    // Verifies the CRC for the read temperature and humidity
-    fn crc(data: &[u8], crc: u8) -> bool {
-        let polynomial: u8 = 0x31;
-        let mut init: u8 = 0xFF;
+// Verifies the CRC on a word read from a sensor
+fn crc(data: &[u8], crc: u8) -> bool {
+    crc8(data) == crc
+}
 
-        for byte in data {
-            init ^= byte;
-            for _ in 0..8 {
-                if init & 0x80 != 0 {
-                    init = (init << 1) ^ polynomial;
-                } else {
-                    init <<= 1;
-                }
+// CRC-8 used by the Sensirion parts and the AHT20, polynomial 0x31 with 0xFF init
+pub fn crc8(data: &[u8]) -> u8 {
+    let polynomial: u8 = 0x31;
+    let mut init: u8 = 0xFF;
+
+    for byte in data {
+        init ^= byte;
+        for _ in 0..8 {
+            if init & 0x80 != 0 {
+                init = (init << 1) ^ polynomial;
+            } else {
+                init <<= 1;
             }
         }
-
-        init == crc
     }
+
+    init
 }
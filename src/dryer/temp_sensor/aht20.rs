@@ -0,0 +1,79 @@
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+use crate::dryer::temp_sensor::crc;
+use std::time::Duration;
+
+// Fixed address, the AHT20 has no address pin
+const ADDR: u16 = 0x38;
+
+const CMD_INIT: [u8; 3] = [0xBE, 0x08, 0x00];
+const CMD_MEASURE: [u8; 3] = [0xAC, 0x33, 0x00];
+const CMD_SOFT_RESET: u8 = 0xBA;
+
+const STATUS_BUSY: u8 = 0x80;
+const STATUS_CALIBRATED: u8 = 0x08;
+
+// AHT20 temperature and humidity sensor
+#[derive(Debug, Default)]
+pub struct Aht20;
+
+impl Aht20 {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn status<I: I2cBus>(&self, i2c: &mut I) -> Result<u8, SensorError> {
+        let mut buf = [0u8; 1];
+        i2c.read(&mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl<I: I2cBus> TempHumSensor<I> for Aht20 {
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        i2c.set_slave_address(ADDR)?;
+        i2c.write(&[CMD_SOFT_RESET])?;
+        i2c.delay(Duration::from_millis(20));
+
+        // The calibration coefficients have to be loaded once after power up
+        if self.status(i2c)? & STATUS_CALIBRATED == 0 {
+            i2c.write(&CMD_INIT)?;
+            i2c.delay(Duration::from_millis(10));
+        }
+
+        if self.status(i2c)? & STATUS_CALIBRATED == 0 {
+            return Err(SensorError::Bus(String::from("AHT20 didn't calibrate")));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        i2c.set_slave_address(ADDR)?;
+        i2c.write(&CMD_MEASURE)?;
+        i2c.delay(Duration::from_millis(80));
+
+        // Status, 20 bits of humidity, 20 bits of temperature, CRC
+        let mut buf = [0u8; 7];
+        i2c.read(&mut buf)?;
+
+        // Still measuring, treated like the sensor not answering so the read gets retried
+        if buf[0] & STATUS_BUSY != 0 {
+            return Err(SensorError::Nack);
+        }
+
+        // One CRC covers the whole frame
+        if !crc(&buf[0..6], buf[6]) {
+            return Err(SensorError::Checksum);
+        }
+
+        let hum_raw = (buf[1] as u32) << 12 | (buf[2] as u32) << 4 | (buf[3] as u32) >> 4;
+        let temp_raw = ((buf[3] & 0x0F) as u32) << 16 | (buf[4] as u32) << 8 | buf[5] as u32;
+
+        // 100 * (hum / 2^20)
+        let hum = 100.0 * hum_raw as f32 / 1048576.0;
+
+        // 200 * (temp / 2^20) - 50
+        let temp = 200.0 * temp_raw as f32 / 1048576.0 - 50.0;
+
+        Ok(Reading { temp, hum })
+    }
+}
@@ -0,0 +1,167 @@
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+use std::time::Duration;
+
+const REG_CALIB_TP: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_RESET: u8 = 0xE0;
+const REG_CALIB_H: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_DATA: u8 = 0xF7;
+
+const CHIP_ID: u8 = 0x60;
+const RESET: u8 = 0xB6;
+const STATUS_MEASURING: u8 = 0x08;
+
+// Temperature and humidity oversampling x1, pressure skipped, forced mode
+const CTRL_HUM: u8 = 0x01;
+const CTRL_MEAS: u8 = (0x01 << 5) | 0x01;
+
+// Trimming values burned into every chip, needed to turn raw readings into real units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    h1: f64,
+    h2: f64,
+    h3: f64,
+    h4: f64,
+    h5: f64,
+    h6: f64,
+}
+
+impl Calibration {
+    // tp is the 26 bytes from 0x88, h is the 7 bytes from 0xE1
+    pub fn parse(tp: &[u8; 26], h: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        let i16_at = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            // Pressure trimming sits in between, then H1 on its own at 0xA1
+            h1: tp[25] as f64,
+            h2: i16::from_le_bytes([h[0], h[1]]) as f64,
+            h3: h[2] as f64,
+            // H4 and H5 are 12 bits each and share a nibble
+            h4: (((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16) as f64,
+            h5: (((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16) as f64,
+            h6: h[6] as i8 as f64,
+        }
+    }
+
+    // Floating point compensation from the datasheet
+    // Returns t_fine, which the humidity compensation needs, and the temperature in C
+    pub fn temp(&self, adc_t: u32) -> (f64, f32) {
+        let adc_t = adc_t as f64;
+        let var1 = (adc_t / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (adc_t / 131072.0 - self.t1 / 8192.0).powi(2) * self.t3;
+        let t_fine = var1 + var2;
+        (t_fine, (t_fine / 5120.0) as f32)
+    }
+
+    pub fn hum(&self, adc_h: u32, t_fine: f64) -> f32 {
+        let var_h = t_fine - 76800.0;
+        let var_h = (adc_h as f64 - (self.h4 * 64.0 + self.h5 / 16384.0 * var_h))
+            * (self.h2 / 65536.0
+                * (1.0 + self.h6 / 67108864.0 * var_h * (1.0 + self.h3 / 67108864.0 * var_h)));
+        let var_h = var_h * (1.0 - self.h1 * var_h / 524288.0);
+        var_h.clamp(0.0, 100.0) as f32
+    }
+}
+
+// BME280 temperature, humidity and pressure sensor, pressure isn't used
+#[derive(Debug)]
+pub struct Bme280 {
+    addr: u16,
+    // Read in init, the sensor can't be read without it
+    calibration: Option<Calibration>,
+}
+
+impl Bme280 {
+    pub fn new(addr: u16) -> Self {
+        Self {
+            addr,
+            calibration: None,
+        }
+    }
+
+    fn read_regs<I: I2cBus>(
+        &self,
+        i2c: &mut I,
+        reg: u8,
+        buf: &mut [u8],
+    ) -> Result<(), SensorError> {
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&[reg])?;
+        i2c.read(buf)?;
+        Ok(())
+    }
+
+    fn write_reg<I: I2cBus>(&self, i2c: &mut I, reg: u8, value: u8) -> Result<(), SensorError> {
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&[reg, value])?;
+        Ok(())
+    }
+}
+
+impl<I: I2cBus> TempHumSensor<I> for Bme280 {
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        let mut id = [0u8; 1];
+        self.read_regs(i2c, REG_CHIP_ID, &mut id)?;
+        if id[0] != CHIP_ID {
+            // A BMP280 answers at the same address but has no humidity sensor
+            return Err(SensorError::Bus(format!(
+                "chip id {:#04x} at {:#04x} isn't a BME280",
+                id[0], self.addr
+            )));
+        }
+
+        self.write_reg(i2c, REG_RESET, RESET)?;
+        i2c.delay(Duration::from_millis(5));
+
+        let mut tp = [0u8; 26];
+        let mut h = [0u8; 7];
+        self.read_regs(i2c, REG_CALIB_TP, &mut tp)?;
+        self.read_regs(i2c, REG_CALIB_H, &mut h)?;
+        self.calibration = Some(Calibration::parse(&tp, &h));
+        Ok(())
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        // Init failed at startup, try again rather than fail forever
+        let calibration = match self.calibration {
+            Some(calibration) => calibration,
+            None => {
+                self.init(i2c)?;
+                self.calibration.ok_or(SensorError::Nack)?
+            }
+        };
+
+        // ctrl_hum only takes effect after a write to ctrl_meas
+        self.write_reg(i2c, REG_CTRL_HUM, CTRL_HUM)?;
+        self.write_reg(i2c, REG_CTRL_MEAS, CTRL_MEAS)?;
+        i2c.delay(Duration::from_millis(10));
+
+        let mut status = [0u8; 1];
+        self.read_regs(i2c, REG_STATUS, &mut status)?;
+        if status[0] & STATUS_MEASURING != 0 {
+            return Err(SensorError::Nack);
+        }
+
+        // Pressure MSB, LSB, XLSB, temp MSB, LSB, XLSB, hum MSB, LSB
+        let mut buf = [0u8; 8];
+        self.read_regs(i2c, REG_DATA, &mut buf)?;
+
+        let adc_t = (buf[3] as u32) << 12 | (buf[4] as u32) << 4 | (buf[5] as u32) >> 4;
+        let adc_h = (buf[6] as u32) << 8 | buf[7] as u32;
+
+        let (t_fine, temp) = calibration.temp(adc_t);
+        let hum = calibration.hum(adc_h, t_fine);
+
+        Ok(Reading { temp, hum })
+    }
+}
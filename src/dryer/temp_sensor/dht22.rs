@@ -0,0 +1,178 @@
+use crate::dryer::hal::{I2cBus, IoPin, Reading, SensorError, TempHumSensor};
+use std::time::{Duration, Instant};
+
+// A high bit is ~70us, a low bit ~26us
+const ONE_THRESHOLD: Duration = Duration::from_micros(48);
+// Longest any single level lasts in a response, the sensor's 80us plus slack
+const EDGE_TIMEOUT: Duration = Duration::from_micros(200);
+// Linux can take the CPU away in the middle of a frame and mangle it, which says nothing
+// about the sensor, so a bad frame is asked for again this many times before it counts
+const FRAME_ATTEMPTS: u32 = 5;
+// Asked again any sooner, the sensor doesn't answer
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+// DHT22/AM2302 on a single wire GPIO, bit-banged
+// Doesn't use the I2C bus, it only takes one to fit the sensor trait
+// The sensor measures when asked and sends the previous measurement,
+// so readings lag by one read. It wants 2s between reads, reads sooner than that get the
+// last one again
+#[derive(Debug)]
+pub struct Dht22<P: IoPin> {
+    pin: P,
+    // When the last good frame came in and what it said
+    last: Option<(Instant, Reading)>,
+}
+
+impl<P: IoPin> Dht22<P> {
+    pub fn new(mut pin: P) -> Self {
+        pin.release();
+        Self { pin, last: None }
+    }
+
+    // Waits for the line to go to level, returns how long it took
+    fn wait_for(&self, level: bool) -> Result<Duration, SensorError> {
+        let start = Instant::now();
+        while self.pin.is_high() != level {
+            if start.elapsed() > EDGE_TIMEOUT {
+                return Err(SensorError::Nack);
+            }
+        }
+        Ok(start.elapsed())
+    }
+
+    fn read_frame(&mut self) -> Result<[u8; 5], SensorError> {
+        // Start signal, hold the line low for at least 1ms then let it go
+        self.pin.set_low();
+        self.pin.delay(Duration::from_millis(2));
+        self.pin.release();
+
+        // Sensor answers with 80us low, 80us high, then the bits
+        self.wait_for(false)?;
+        self.wait_for(true)?;
+        self.wait_for(false)?;
+
+        // Every bit is 50us low then a high whose length is the bit
+        let mut frame = [0u8; 5];
+        for i in 0..40 {
+            self.wait_for(true)?;
+            let high = self.wait_for(false)?;
+            if high > ONE_THRESHOLD {
+                frame[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        Ok(frame)
+    }
+}
+
+impl<I: I2cBus, P: IoPin> TempHumSensor<I> for Dht22<P> {
+    fn read(&mut self, _i2c: &mut I) -> Result<Reading, SensorError> {
+        let now = self.pin.now();
+        if let Some((at, reading)) = self.last
+            && now - at < MIN_INTERVAL
+        {
+            return Ok(reading);
+        }
+
+        let mut result = Err(SensorError::Nack);
+        for _ in 0..FRAME_ATTEMPTS {
+            result = self.read_frame();
+            // Always leave the line floating, even after a timeout
+            self.pin.release();
+
+            // Last byte is the low byte of the sum of the rest
+            result = result.and_then(|frame| {
+                let sum = frame[..4].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                if sum == frame[4] {
+                    Ok(frame)
+                } else {
+                    Err(SensorError::Checksum)
+                }
+            });
+            if result.is_ok() {
+                break;
+            }
+        }
+        let frame = result?;
+
+        // Humidity and temperature in tenths, temperature is sign and magnitude
+        let hum = u16::from_be_bytes([frame[0], frame[1]]) as f32 / 10.0;
+        let magnitude = u16::from_be_bytes([frame[2] & 0x7F, frame[3]]) as f32 / 10.0;
+        let temp = if frame[2] & 0x80 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+
+        if hum > 100.0 {
+            return Err(SensorError::OutOfRange(Reading { temp, hum }));
+        }
+
+        self.last = Some((now, Reading { temp, hum }));
+        Ok(Reading { temp, hum })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryer::hal::BusError;
+
+    // Nothing is on the bus, the DHT22 doesn't use it
+    struct NoBus;
+
+    impl I2cBus for NoBus {
+        fn set_slave_address(&mut self, _addr: u16) -> Result<(), BusError> {
+            Err(BusError::Nack)
+        }
+        fn write(&mut self, _buf: &[u8]) -> Result<(), BusError> {
+            Err(BusError::Nack)
+        }
+        fn read(&mut self, _buf: &mut [u8]) -> Result<(), BusError> {
+            Err(BusError::Nack)
+        }
+    }
+
+    // A line nobody answers on, counting start signals, on a clock the test moves
+    struct IdlePin {
+        starts: u32,
+        now: Instant,
+    }
+
+    impl IoPin for IdlePin {
+        fn set_low(&mut self) {
+            self.starts += 1;
+        }
+        fn release(&mut self) {}
+        fn is_high(&self) -> bool {
+            true
+        }
+        fn delay(&mut self, _duration: Duration) {}
+        fn now(&self) -> Instant {
+            self.now
+        }
+    }
+
+    #[test]
+    fn reads_inside_two_seconds_get_the_last_reading() {
+        let start = Instant::now();
+        let pin = IdlePin {
+            starts: 0,
+            now: start,
+        };
+        let mut dht = Dht22::new(pin);
+        let reading = Reading {
+            temp: 40.0,
+            hum: 20.0,
+        };
+        dht.last = Some((start, reading));
+
+        dht.pin.now = start + Duration::from_secs(1);
+        assert_eq!(dht.read(&mut NoBus), Ok(reading));
+        assert_eq!(dht.pin.starts, 0);
+
+        // Past the interval the sensor is asked, and this one doesn't answer
+        dht.pin.now = start + MIN_INTERVAL;
+        assert_eq!(dht.read(&mut NoBus), Err(SensorError::Nack));
+        assert_eq!(dht.pin.starts, FRAME_ATTEMPTS);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+use crate::dryer::temp_sensor::crc;
+use serde::Deserialize;
+use std::time::Duration;
+
+// SHT3x temperature and humidity sensor, at 0x44 or 0x45 depending on the ADDR pin
+#[derive(Debug)]
+pub struct Sht3x {
+    addr: u16,
+    config: Sht3xConfig,
+    // Periodic mode has been started and the sensor hasn't reset since
+    periodic_running: bool,
+}
+
+// Commands from the datasheet, all sent MSB first
+const CMD_FETCH: u16 = 0xE000;
+const CMD_BREAK: u16 = 0x3093;
+const CMD_SOFT_RESET: u16 = 0x30A2;
+const CMD_HEATER_ON: u16 = 0x306D;
+const CMD_HEATER_OFF: u16 = 0x3066;
+const CMD_READ_STATUS: u16 = 0xF32D;
+const CMD_CLEAR_STATUS: u16 = 0x3041;
+
+// Anything on the bus that supports general call resets on this
+const GENERAL_CALL_ADDR: u16 = 0x00;
+const GENERAL_CALL_RESET: u8 = 0x06;
+
+// Higher repeatability is less noisy but takes longer and heats the sensor more
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeatability {
+    Low,
+    Medium,
+    High,
+}
+
+impl Repeatability {
+    // Max measurement duration from the datasheet, rounded up
+    fn duration(&self) -> Duration {
+        Duration::from_millis(match self {
+            Repeatability::Low => 5,
+            Repeatability::Medium => 7,
+            Repeatability::High => 16,
+        })
+    }
+}
+
+// Measurements per second in periodic mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mps {
+    Half,
+    One,
+    Two,
+    Four,
+    Ten,
+}
+
+// Written "single" or "periodic-" and the rate, e.g. "periodic-0.5"
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Sht3xMode {
+    // Measure when asked, the sensor idles in between
+    SingleShot,
+    // The sensor measures on its own and reads fetch the latest result
+    // A fetch before a new measurement is ready gets NACKed, so keep the rate above the read rate
+    Periodic(Mps),
+}
+
+impl TryFrom<String> for Sht3xMode {
+    type Error = String;
+
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        Ok(match mode.as_str() {
+            "single" => Sht3xMode::SingleShot,
+            "periodic-0.5" => Sht3xMode::Periodic(Mps::Half),
+            "periodic-1" => Sht3xMode::Periodic(Mps::One),
+            "periodic-2" => Sht3xMode::Periodic(Mps::Two),
+            "periodic-4" => Sht3xMode::Periodic(Mps::Four),
+            "periodic-10" => Sht3xMode::Periodic(Mps::Ten),
+            _ => return Err(format!("unknown SHT3x mode {mode}")),
+        })
+    }
+}
+
+// Heats the sensor with its on-chip heater to drive off condensation
+// Above ~95%RH water can condense on the sensor and it reads 100% until it dries out
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Decondense {
+    // Off skips the condensation check at startup
+    pub enabled: bool,
+    // Humidity at startup that counts as saturated
+    pub above_hum: f32,
+    pub heat_secs: u64,
+    // Time for the sensor to drop back to chamber temperature after heating
+    pub cool_secs: u64,
+    // Gives up after this many heat/cool cycles, the chamber might really be that humid
+    pub max_cycles: u32,
+}
+
+impl Decondense {
+    pub fn heat_for(&self) -> Duration {
+        Duration::from_secs(self.heat_secs)
+    }
+
+    pub fn cool_for(&self) -> Duration {
+        Duration::from_secs(self.cool_secs)
+    }
+}
+
+impl Default for Decondense {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            above_hum: 95.0,
+            heat_secs: 10,
+            cool_secs: 5,
+            max_cycles: 6,
+        }
+    }
+}
+
+// Shared by every SHT3x in the sensor list
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Sht3xConfig {
+    pub repeatability: Repeatability,
+    pub mode: Sht3xMode,
+    pub decondense: Decondense,
+}
+
+impl Default for Sht3xConfig {
+    fn default() -> Self {
+        Self {
+            repeatability: Repeatability::High,
+            mode: Sht3xMode::SingleShot,
+            decondense: Decondense::default(),
+        }
+    }
+}
+
+// Status register
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sht3xStatus(pub u16);
+
+impl Sht3xStatus {
+    pub fn alert_pending(&self) -> bool {
+        self.0 & (1 << 15) != 0
+    }
+
+    pub fn heater_on(&self) -> bool {
+        self.0 & (1 << 13) != 0
+    }
+
+    pub fn hum_alert(&self) -> bool {
+        self.0 & (1 << 11) != 0
+    }
+
+    pub fn temp_alert(&self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    // Set at power up and after any reset until the status is cleared
+    pub fn reset_detected(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    // The last command wasn't processed, either invalid or failed its checksum
+    pub fn command_failed(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub fn write_checksum_failed(&self) -> bool {
+        self.0 & 1 != 0
+    }
+}
+
+impl Sht3x {
+    pub fn new(addr: u16, config: Sht3xConfig) -> Self {
+        Self {
+            addr,
+            config,
+            periodic_running: false,
+        }
+    }
+
+    fn command<I: I2cBus>(&self, i2c: &mut I, cmd: u16) -> Result<(), SensorError> {
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&cmd.to_be_bytes())?;
+        Ok(())
+    }
+
+    // Clock stretching disabled on all of these bc Pi doesn't support properly
+    fn single_shot_cmd(&self) -> u16 {
+        match self.config.repeatability {
+            Repeatability::High => 0x2400,
+            Repeatability::Medium => 0x240B,
+            Repeatability::Low => 0x2416,
+        }
+    }
+
+    fn periodic_cmd(&self, mps: Mps) -> u16 {
+        let (high, medium, low) = match mps {
+            Mps::Half => (0x2032, 0x2024, 0x202F),
+            Mps::One => (0x2130, 0x2126, 0x212D),
+            Mps::Two => (0x2236, 0x2220, 0x222B),
+            Mps::Four => (0x2334, 0x2322, 0x2329),
+            Mps::Ten => (0x2737, 0x2721, 0x272A),
+        };
+        match self.config.repeatability {
+            Repeatability::High => high,
+            Repeatability::Medium => medium,
+            Repeatability::Low => low,
+        }
+    }
+
+    // Resets this sensor, it goes back to idle single shot mode with the heater off
+    pub fn soft_reset<I: I2cBus>(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        // A sensor in periodic mode ignores everything but break and fetch
+        if self.periodic_running {
+            self.command(i2c, CMD_BREAK)?;
+            i2c.delay(Duration::from_millis(1));
+        }
+        self.periodic_running = false;
+        self.command(i2c, CMD_SOFT_RESET)?;
+        i2c.delay(Duration::from_millis(2));
+        Ok(())
+    }
+
+    // Resets every device on the bus that listens to general call, including the other sensor
+    // Works on a sensor that is too confused to take a soft reset
+    pub fn general_call_reset<I: I2cBus>(i2c: &mut I) -> Result<(), SensorError> {
+        i2c.set_slave_address(GENERAL_CALL_ADDR)?;
+        i2c.write(&[GENERAL_CALL_RESET])?;
+        i2c.delay(Duration::from_millis(2));
+        Ok(())
+    }
+
+    pub fn set_heater<I: I2cBus>(&mut self, i2c: &mut I, on: bool) -> Result<(), SensorError> {
+        self.command(i2c, if on { CMD_HEATER_ON } else { CMD_HEATER_OFF })
+    }
+
+    pub fn status<I: I2cBus>(&mut self, i2c: &mut I) -> Result<Sht3xStatus, SensorError> {
+        self.command(i2c, CMD_READ_STATUS)?;
+
+        // Status MSB, status LSB, CRC
+        let mut buf = [0u8; 3];
+        i2c.read(&mut buf)?;
+        if !crc(&buf[0..2], buf[2]) {
+            return Err(SensorError::StatusCrc);
+        }
+
+        Ok(Sht3xStatus(u16::from_be_bytes([buf[0], buf[1]])))
+    }
+
+    pub fn clear_status<I: I2cBus>(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        self.command(i2c, CMD_CLEAR_STATUS)
+    }
+
+    // Heats the sensor until it stops reading saturated or runs out of cycles
+    fn decondense<I: I2cBus>(
+        &mut self,
+        i2c: &mut I,
+        decondense: Decondense,
+    ) -> Result<(), SensorError> {
+        for cycle in 0..decondense.max_cycles {
+            // Always single shot, the heater can only be switched while the sensor is idle
+            let reading = Sht3x::decode(self.single_shot(i2c)?)?;
+            if reading.hum < decondense.above_hum {
+                if cycle > 0 {
+                    println!("Sensor {:#04x} dried out, {:.1}%rh", self.addr, reading.hum);
+                }
+                return Ok(());
+            }
+
+            println!(
+                "Sensor {:#04x} reads {:.1}%rh, heating it to drive off condensation",
+                self.addr, reading.hum
+            );
+            self.set_heater(i2c, true)?;
+            i2c.delay(decondense.heat_for());
+            self.set_heater(i2c, false)?;
+            // Make sure the heater really went off, a stuck heater reads hot and dry forever
+            if self.status(i2c)?.heater_on() {
+                return Err(SensorError::Bus(String::from("heater didn't turn off")));
+            }
+            i2c.delay(decondense.cool_for());
+        }
+
+        println!(
+            "Sensor {:#04x} still reads saturated after heating",
+            self.addr
+        );
+        Ok(())
+    }
+
+    fn single_shot<I: I2cBus>(&mut self, i2c: &mut I) -> Result<[u8; 6], SensorError> {
+        self.command(i2c, self.single_shot_cmd())?;
+
+        // Wait for sensor to take measurment
+        i2c.delay(self.config.repeatability.duration());
+
+        let mut buf = [0u8; 6];
+        i2c.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn fetch<I: I2cBus>(&mut self, i2c: &mut I, mps: Mps) -> Result<[u8; 6], SensorError> {
+        if !self.periodic_running {
+            self.command(i2c, self.periodic_cmd(mps))?;
+            self.periodic_running = true;
+            i2c.delay(self.config.repeatability.duration());
+        }
+
+        self.command(i2c, CMD_FETCH)?;
+        let mut buf = [0u8; 6];
+        if let Err(e) = i2c.read(&mut buf) {
+            // No new data yet, or the sensor reset and dropped out of periodic mode
+            // Start it again on the next read if it reset
+            if self.status(i2c).is_ok_and(|status| status.reset_detected()) {
+                println!("Sensor {:#04x} reset, restarting periodic mode", self.addr);
+                let _ = self.clear_status(i2c);
+                self.periodic_running = false;
+            }
+            return Err(e.into());
+        }
+        Ok(buf)
+    }
+
+    fn decode(buf: [u8; 6]) -> Result<Reading, SensorError> {
+        // Data format is temp MSB, temp LSB, CRC, Hum MSB, Hum LSB, CRC
+        if !crc(&buf[0..2], buf[2]) {
+            return Err(SensorError::TempCrc);
+        }
+
+        if !crc(&buf[3..5], buf[5]) {
+            return Err(SensorError::HumCrc);
+        }
+
+        let temp_raw = u16::from_be_bytes([buf[0], buf[1]]);
+        let hum_raw = u16::from_be_bytes([buf[3], buf[4]]);
+
+        // -45 + 175 * (temp / (2^16-1))
+        let temperature = -45.0 + 175.0 * (temp_raw as f32) / 65535.0;
+
+        // 100 * (hum / (2^16-1))
+        let humidity = 100.0 * (hum_raw as f32) / 65535.0;
+
+        let reading = Reading {
+            temp: temperature,
+            hum: humidity,
+        };
+
+        // Outside the range in the datasheet, the formula can go a little past it
+        if !(-40.0..=125.0).contains(&reading.temp) {
+            return Err(SensorError::OutOfRange(reading));
+        }
+
+        Ok(reading)
+    }
+}
+
+impl<I: I2cBus> TempHumSensor<I> for Sht3x {
+    // Puts the sensor in a known state: reset, heater off, status cleared,
+    // dried out if it came up saturated, then in the configured mode
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        if let Err(e) = self.soft_reset(i2c) {
+            println!(
+                "Sensor {:#04x} soft reset failed ({e}), trying general call",
+                self.addr
+            );
+            Sht3x::general_call_reset(i2c)?;
+        }
+
+        let status = self.status(i2c)?;
+        if !status.reset_detected() {
+            println!("Sensor {:#04x} didn't report the reset", self.addr);
+        }
+        if status.alert_pending() || status.temp_alert() || status.hum_alert() {
+            println!(
+                "Sensor {:#04x} had alerts pending: {:#06x}",
+                self.addr, status.0
+            );
+        }
+        self.clear_status(i2c)?;
+        let status = self.status(i2c)?;
+        if status.command_failed() || status.write_checksum_failed() {
+            return Err(SensorError::Bus(String::from("clear status not accepted")));
+        }
+
+        if self.config.decondense.enabled {
+            self.decondense(i2c, self.config.decondense)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        let buf = match self.config.mode {
+            Sht3xMode::SingleShot => self.single_shot(i2c)?,
+            Sht3xMode::Periodic(mps) => self.fetch(i2c, mps)?,
+        };
+        Sht3x::decode(buf)
+    }
+}
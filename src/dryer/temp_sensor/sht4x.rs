@@ -0,0 +1,58 @@
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+use crate::dryer::temp_sensor::crc;
+use std::time::Duration;
+
+// SHT4x temperature and humidity sensor
+// Single byte commands, otherwise it talks like the SHT3x
+#[derive(Debug)]
+pub struct Sht4x {
+    addr: u16,
+}
+
+// High precision measurement, takes up to 8.3ms
+const CMD_MEASURE: u8 = 0xFD;
+const CMD_SOFT_RESET: u8 = 0x94;
+
+impl Sht4x {
+    pub fn new(addr: u16) -> Self {
+        Self { addr }
+    }
+}
+
+impl<I: I2cBus> TempHumSensor<I> for Sht4x {
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&[CMD_SOFT_RESET])?;
+        i2c.delay(Duration::from_millis(1));
+        Ok(())
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        i2c.set_slave_address(self.addr)?;
+        i2c.write(&[CMD_MEASURE])?;
+        i2c.delay(Duration::from_millis(9));
+
+        // Data format is temp MSB, temp LSB, CRC, Hum MSB, Hum LSB, CRC
+        let mut buf = [0u8; 6];
+        i2c.read(&mut buf)?;
+
+        if !crc(&buf[0..2], buf[2]) {
+            return Err(SensorError::TempCrc);
+        }
+
+        if !crc(&buf[3..5], buf[5]) {
+            return Err(SensorError::HumCrc);
+        }
+
+        let temp_raw = u16::from_be_bytes([buf[0], buf[1]]);
+        let hum_raw = u16::from_be_bytes([buf[3], buf[4]]);
+
+        // -45 + 175 * (temp / (2^16-1))
+        let temp = -45.0 + 175.0 * (temp_raw as f32) / 65535.0;
+
+        // -6 + 125 * (hum / (2^16-1)), the datasheet says to clip it to 0-100
+        let hum = (-6.0 + 125.0 * (hum_raw as f32) / 65535.0).clamp(0.0, 100.0);
+
+        Ok(Reading { temp, hum })
+    }
+}
@@ -0,0 +1,61 @@
+use serde::Deserialize;
+
+use crate::dryer::hal::{I2cBus, Reading, SensorError, TempHumSensor};
+
+pub const CHANNELS: u8 = 8;
+
+// Where a sensor sits behind a TCA9548A I2C mux
+// The mux is at 0x70 to 0x77 and switches 8 downstream buses onto the main one,
+// so sensors that share an address can each get their own channel
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct MuxChannel {
+    pub addr: u16,
+    pub channel: u8,
+}
+
+// A sensor behind a mux channel
+// The channel is switched in for every transaction and switched out after,
+// so nothing on it clashes with the LCD or sensors on the main bus
+#[derive(Debug)]
+pub struct Muxed<S> {
+    mux: MuxChannel,
+    sensor: S,
+}
+
+impl<S> Muxed<S> {
+    pub fn new(mux: MuxChannel, sensor: S) -> Self {
+        Self { mux, sensor }
+    }
+
+    // The control register is a bit per channel
+    fn select<I: I2cBus>(&self, i2c: &mut I, channels: u8) -> Result<(), SensorError> {
+        i2c.set_slave_address(self.mux.addr)?;
+        i2c.write(&[channels])?;
+        Ok(())
+    }
+
+    // Runs f with the channel switched in
+    // An error from the sensor wins over one switching the channel back out
+    fn with_channel<I: I2cBus, T>(
+        &mut self,
+        i2c: &mut I,
+        f: impl FnOnce(&mut S, &mut I) -> Result<T, SensorError>,
+    ) -> Result<T, SensorError> {
+        self.select(i2c, 1 << self.mux.channel)?;
+        let result = f(&mut self.sensor, i2c);
+        let released = self.select(i2c, 0);
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+}
+
+impl<I: I2cBus, S: TempHumSensor<I>> TempHumSensor<I> for Muxed<S> {
+    fn init(&mut self, i2c: &mut I) -> Result<(), SensorError> {
+        self.with_channel(i2c, |sensor, i2c| sensor.init(i2c))
+    }
+
+    fn read(&mut self, i2c: &mut I) -> Result<Reading, SensorError> {
+        self.with_channel(i2c, |sensor, i2c| sensor.read(i2c))
+    }
+}
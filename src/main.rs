@@ -1,14 +1,63 @@
+#[cfg(any(feature = "rpi", feature = "sim"))]
 mod dryer;
 
-use std::{thread, time::Duration};
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-use dryer::Dryer;
+    // pi_dry --sim runs the control loop against a model of the chamber
+    #[cfg(feature = "sim")]
+    if args.first().is_some_and(|arg| arg == "--sim") {
+        return dryer::hal::sim::run(&args[1..]);
+    }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut dryer = Dryer::new();
+    run(&args)
+}
+
+// pi_dry [--config pi_dry.toml]
+// Without --config, pi_dry.toml in the working directory is used if there is one
+#[cfg(feature = "rpi")]
+fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::{
+        path::Path,
+        thread,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use dryer::{Dryer, config::Config};
 
+    let config = match args {
+        [flag, path] if flag == "--config" => Config::load(Path::new(path))?,
+        [] if Path::new("pi_dry.toml").exists() => Config::load(Path::new("pi_dry.toml"))?,
+        [] => Config::default(),
+        _ => return Err("usage: pi_dry [--config pi_dry.toml] or pi_dry --sim ...".into()),
+    };
+    let mut dryer = Dryer::new(dryer::hal::rpi::hardware(&config)?, config)?;
+
+    // Control runs once a second, the screen is redrawn in between so the buttons feel quick
+    let mut last_update: Option<Instant> = None;
     loop {
-        dryer.update();
-        thread::sleep(Duration::from_millis(1000));
+        let now = Instant::now();
+        if last_update.is_none_or(|at| now - at >= Duration::from_secs(1)) {
+            dryer.sync_clock(now, SystemTime::now());
+            if let Err(e) = dryer.update(now) {
+                println!("Update failed: {e}");
+            }
+            last_update = Some(now);
+        } else {
+            // A broken display shows up in the update errors, no need for it 20 times a second
+            let _ = dryer.refresh(now);
+        }
+        thread::sleep(Duration::from_millis(50));
     }
 }
+
+#[cfg(all(feature = "sim", not(feature = "rpi")))]
+fn run(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    Err("pi_dry was built without the `rpi` feature, only --sim is available".into())
+}
+
+// Nothing to drive without either, but it still builds so each feature can be checked on its own
+#[cfg(not(any(feature = "rpi", feature = "sim")))]
+fn run(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    Err("pi_dry was built without the `rpi` and `sim` features, there is nothing to run".into())
+}